@@ -0,0 +1,299 @@
+#![feature(question_mark)]
+
+#[macro_use]
+extern crate log;
+
+extern crate misc;
+extern crate interface as dl;
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use misc::interface as root;
+
+
+/// Same as Ethernet, for lack of any better number
+pub const DEFAULT_MTU: usize = 1500;
+
+#[derive(PartialEq, Eq,
+         Copy, Clone, Hash, Debug)]
+pub enum Error {
+  /// The link was cut with `set_carrier`
+  NoCarrier,
+}
+
+/// The receiving half of an interface
+struct Endpoint<'a> {
+  handler: RwLock<(bool, dl::BatchHandler<'a>)>,
+  /// Packets dropped while disabled
+  dropped:   AtomicUsize,
+  on_status: RwLock<dl::StatusHandler<'a>>,
+}
+
+impl<'a> Endpoint<'a> {
+  fn new(on_recv: dl::Handler<'a>) -> Arc<Endpoint<'a>> {
+    Arc::new(Endpoint {
+      handler:   RwLock::new((true, dl::unbatch(on_recv))),
+      dropped:   AtomicUsize::new(0),
+      on_status: RwLock::new(Box::new(|_: bool| ())),
+    })
+  }
+
+  fn notify(&self, up: bool) {
+    (**self.on_status.read().unwrap())(up);
+  }
+
+  fn deliver(&self, packets: Vec<dl::Packet>) {
+    match *self.handler.read().unwrap() {
+      (true, ref on_recv) => {
+        debug!("Delivering {} packet(s)", packets.len());
+        (**on_recv)(packets);
+      },
+      (false, _) => {
+        debug!("Channel interface is not enabled, dropping packet(s)");
+        self.dropped.fetch_add(packets.len(), Ordering::Relaxed);
+      },
+    }
+  }
+}
+
+type Pending<'a> = Arc<Mutex<VecDeque<(Arc<Endpoint<'a>>, dl::Packet)>>>;
+
+
+/// Packets sent over queued links wait here until the caller steps them
+/// through. One queue can be shared by every link in a topology, so that the
+/// whole thing runs deterministically on a single thread.
+#[derive(Clone)]
+pub struct Queue<'a> {
+  pending: Pending<'a>,
+}
+
+impl<'a> Queue<'a> {
+  pub fn new() -> Queue<'a> {
+    Queue { pending: Arc::new(Mutex::new(VecDeque::new())) }
+  }
+
+  /// Connects two new interfaces whose packets go through this queue
+  pub fn link(&self,
+              on_recv_1: dl::Handler<'a>,
+              on_recv_2: dl::Handler<'a>)
+              -> (Interface<'a>, Interface<'a>)
+  {
+    make_pair(Some(self.pending.clone()), on_recv_1, on_recv_2)
+  }
+
+  /// Delivers the oldest pending packet. Returns false if there was none.
+  pub fn step(&self) -> bool {
+    // the lock must be released before delivery, as the handler may well send
+    let next = self.pending.lock().unwrap().pop_front();
+    match next {
+      None                => false,
+      Some((dst, packet)) => {
+        dst.deliver(vec![packet]);
+        true
+      },
+    }
+  }
+
+  /// Delivers until nothing is left, including whatever was sent in response
+  /// along the way. Returns the number of packets delivered.
+  ///
+  /// Packets are delivered in the same order as by `step`, but consecutive
+  /// ones for the same endpoint are handed over as one batch.
+  ///
+  /// This will not return if the handlers keep each other talking forever.
+  pub fn run(&self) -> usize {
+    let mut count = 0;
+    loop {
+      // anything sent in response lands behind what we took, just as if we
+      // were stepping
+      let taken = mem::replace(&mut *self.pending.lock().unwrap(), VecDeque::new());
+      if taken.is_empty() {
+        return count;
+      }
+      count += taken.len();
+
+      let mut batch: Option<(Arc<Endpoint<'a>>, Vec<dl::Packet>)> = None;
+      for (dst, packet) in taken {
+        batch = match batch {
+          Some((cur, mut packets)) => {
+            if &*cur as *const Endpoint == &*dst as *const Endpoint {
+              packets.push(packet);
+              Some((cur, packets))
+            } else {
+              cur.deliver(packets);
+              Some((dst, vec![packet]))
+            }
+          },
+          None => Some((dst, vec![packet])),
+        };
+      }
+      if let Some((cur, packets)) = batch {
+        cur.deliver(packets);
+      }
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.pending.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+
+/// Connects two new interfaces that deliver synchronously: the remote
+/// handler runs inside `send`.
+///
+/// Beware that a handler which sends back over the same link is then
+/// reentrant, so topologies with forwarding should use a `Queue` instead.
+pub fn link<'a>(on_recv_1: dl::Handler<'a>,
+                on_recv_2: dl::Handler<'a>)
+                -> (Interface<'a>, Interface<'a>)
+{
+  make_pair(None, on_recv_1, on_recv_2)
+}
+
+fn make_pair<'a>(queue:     Option<Pending<'a>>,
+                 on_recv_1: dl::Handler<'a>,
+                 on_recv_2: dl::Handler<'a>)
+                 -> (Interface<'a>, Interface<'a>)
+{
+  let e1 = Endpoint::new(on_recv_1);
+  let e2 = Endpoint::new(on_recv_2);
+  let carrier = Arc::new(AtomicBool::new(true));
+
+  let i1 = Interface {
+    local:         e1.clone(),
+    remote:        e2.clone(),
+    queue:         queue.clone(),
+    carrier:       carrier.clone(),
+    mtu:           DEFAULT_MTU,
+    cached_status: true,
+  };
+  let i2 = Interface {
+    local:         e2,
+    remote:        e1,
+    queue:         queue,
+    carrier:       carrier,
+    mtu:           DEFAULT_MTU,
+    cached_status: true,
+  };
+  (i1, i2)
+}
+
+
+/// One end of an in-memory point-to-point link
+pub struct Interface<'a> {
+  local:         Arc<Endpoint<'a>>,
+  remote:        Arc<Endpoint<'a>>,
+  queue:         Option<Pending<'a>>,
+  /// Shared by both ends
+  carrier:       Arc<AtomicBool>,
+  mtu:           usize,
+  cached_status: bool,
+}
+
+impl<'a> Interface<'a> {
+  /// Only reported, as nothing is actually too large for memory
+  pub fn set_mtu(&mut self, mtu: usize) {
+    self.mtu = mtu;
+  }
+
+  /// Plugs or unplugs the "cable", for both ends. While it is unplugged
+  /// sending fails, and on any change both ends' status handlers are told.
+  pub fn set_carrier(&self, up: bool) {
+    self.cable().set_carrier(up);
+  }
+
+  /// A handle on the link itself, which outlives handing this end off
+  pub fn cable(&self) -> Cable<'a> {
+    Cable {
+      carrier: self.carrier.clone(),
+      ends:    (self.local.clone(), self.remote.clone()),
+    }
+  }
+}
+
+
+/// The "cable" between two ends, which can still be unplugged once both have
+/// been handed off, e.g. to the IP layer
+#[derive(Clone)]
+pub struct Cable<'a> {
+  carrier: Arc<AtomicBool>,
+  ends:    (Arc<Endpoint<'a>>, Arc<Endpoint<'a>>),
+}
+
+impl<'a> Cable<'a> {
+  /// Like `Interface::set_carrier`
+  pub fn set_carrier(&self, up: bool) {
+    if self.carrier.swap(up, Ordering::SeqCst) != up {
+      self.ends.0.notify(up);
+      self.ends.1.notify(up);
+    }
+  }
+}
+
+impl<'a> root::Interface for Interface<'a> {
+  type Error = Error;
+}
+
+impl<'a> dl::Interface<'a> for Interface<'a> {
+  fn send(&self, packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    if self.cached_status == false {
+      Err(dl::Error::Disabled)?;
+    }
+    if !self.carrier.load(Ordering::SeqCst) {
+      Err(Error::NoCarrier)?;
+    }
+    match self.queue {
+      None            => self.remote.deliver(vec![packet]),
+      Some(ref queue) => queue.lock().unwrap().push_back((self.remote.clone(), packet)),
+    };
+    Ok(())
+  }
+
+  fn mtu(&self) -> usize {
+    self.mtu
+  }
+
+  fn update_recv_handler<'b>(&'b self, on_recv: dl::Handler<'a>)
+    where 'a: 'b
+  {
+    self.local.handler.write().unwrap().1 = dl::unbatch(on_recv);
+  }
+
+  fn update_recv_batch_handler<'b>(&'b self, on_recv: dl::BatchHandler<'a>)
+    where 'a: 'b
+  {
+    self.local.handler.write().unwrap().1 = on_recv;
+  }
+
+  fn update_status_handler<'b>(&'b self, on_status: dl::StatusHandler<'a>)
+    where 'a: 'b
+  {
+    *self.local.on_status.write().unwrap() = on_status;
+  }
+
+  fn dropped_disabled(&self) -> usize {
+    self.local.dropped.load(Ordering::Relaxed)
+  }
+
+  fn enable(&mut self) {
+    self.cached_status = true;
+    self.local.handler.write().unwrap().0 = true;
+  }
+
+  fn disable(&mut self) {
+    self.cached_status = false;
+    self.local.handler.write().unwrap().0 = false;
+  }
+
+  fn get_status(&self) -> bool {
+    self.cached_status
+  }
+}
@@ -0,0 +1,124 @@
+#![feature(box_syntax)]
+
+extern crate misc;
+extern crate interface as dl;
+extern crate channel;
+
+use std::sync::mpsc::channel;
+
+use misc::SenderClosure;
+use channel::*;
+
+const M1: &'static str = "Hey Josh!";
+const M2: &'static str = "Hey Cody!";
+
+#[test]
+fn talk_synchronous() {
+  let (tx1, rx1) = channel::<(dl::Packet,)>();
+  let (tx2, rx2) = channel::<(dl::Packet,)>();
+
+  let (i1, i2) = link(box SenderClosure::new(tx1), box SenderClosure::new(tx2));
+
+  dl::Interface::send(&i1, M2.as_bytes().to_vec()).unwrap();
+  // already there, no waiting
+  assert_eq!(rx2.try_recv().unwrap().0.as_slice(), M2.as_bytes());
+
+  dl::Interface::send(&i2, M1.as_bytes().to_vec()).unwrap();
+  assert_eq!(rx1.try_recv().unwrap().0.as_slice(), M1.as_bytes());
+}
+
+#[test]
+fn talk_queued() {
+  let (tx1, rx1) = channel::<(dl::Packet,)>();
+  let (tx2, rx2) = channel::<(dl::Packet,)>();
+
+  let queue = Queue::new();
+  let (i1, i2) = queue.link(box SenderClosure::new(tx1), box SenderClosure::new(tx2));
+
+  dl::Interface::send(&i1, M2.as_bytes().to_vec()).unwrap();
+  dl::Interface::send(&i2, M1.as_bytes().to_vec()).unwrap();
+
+  // nothing moves until stepped
+  assert_eq!(queue.len(), 2);
+  assert!(rx1.try_recv().is_err());
+  assert!(rx2.try_recv().is_err());
+
+  assert!(queue.step());
+  assert_eq!(rx2.try_recv().unwrap().0.as_slice(), M2.as_bytes());
+  assert!(rx1.try_recv().is_err());
+
+  assert_eq!(queue.run(), 1);
+  assert_eq!(rx1.try_recv().unwrap().0.as_slice(), M1.as_bytes());
+  assert!(queue.is_empty());
+}
+
+#[test]
+fn disable_then_cant_send() {
+  let (mut i1, _i2) = link(box |_| {}, box |_| {});
+
+  dl::Interface::disable(&mut i1);
+
+  match dl::Interface::send(&i1, Vec::new()).unwrap_err() {
+    dl::Error::Disabled => (),
+    _ => panic!("was not disabled")
+  }
+}
+
+#[test]
+fn disabled_drops_incoming() {
+  let (tx2, rx2) = channel::<(dl::Packet,)>();
+
+  let queue = Queue::new();
+  let (i1, mut i2) = queue.link(box |_| {}, box SenderClosure::new(tx2));
+
+  dl::Interface::send(&i1, M2.as_bytes().to_vec()).unwrap();
+  dl::Interface::disable(&mut i2);
+
+  assert_eq!(queue.run(), 1);
+  assert!(rx2.try_recv().is_err());
+}
+
+#[test]
+fn run_delivers_in_batches() {
+  let (tx, rx) = channel::<(Vec<dl::Packet>,)>();
+
+  let queue = Queue::new();
+  let (i1, i2) = queue.link(box |_| {}, box |_| {});
+  dl::Interface::update_recv_batch_handler(&i2, box SenderClosure::new(tx));
+
+  dl::Interface::send(&i1, vec![1]).unwrap();
+  dl::Interface::send(&i1, vec![2]).unwrap();
+  dl::Interface::send(&i2, vec![3]).unwrap();
+  dl::Interface::send(&i1, vec![4]).unwrap();
+
+  assert_eq!(queue.run(), 4);
+  // the packet for the other end splits them up
+  assert_eq!(rx.try_recv().unwrap().0, vec![vec![1], vec![2]]);
+  assert_eq!(rx.try_recv().unwrap().0, vec![vec![4]]);
+  assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn cut_carrier() {
+  let (tx1, rx1) = channel::<(bool,)>();
+  let (tx2, rx2) = channel::<(bool,)>();
+
+  let (i1, i2) = link(box |_| {}, box |_| {});
+  dl::Interface::update_status_handler(&i1, box SenderClosure::new(tx1));
+  dl::Interface::update_status_handler(&i2, box SenderClosure::new(tx2));
+
+  i2.set_carrier(false);
+  assert_eq!(rx1.try_recv().unwrap().0, false);
+  assert_eq!(rx2.try_recv().unwrap().0, false);
+  assert_eq!(dl::Interface::send(&i1, vec![1]),
+             Err(dl::Error::External(Error::NoCarrier)));
+
+  // no change, no news
+  i1.set_carrier(false);
+  assert!(rx1.try_recv().is_err());
+
+  i1.set_carrier(true);
+  assert_eq!(rx1.try_recv().unwrap().0, true);
+  assert_eq!(rx2.try_recv().unwrap().0, true);
+  dl::Interface::send(&i1, vec![1]).unwrap();
+}
@@ -0,0 +1,220 @@
+//! ARP for IPv4 over Ethernet, from RFC 826
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry::{Occupied, Vacant};
+use std::time::{Duration, Instant};
+
+use dl;
+use misc::pool::Pool;
+
+use super::{MacAddr, ETHERTYPE_IPV4, parse_mac};
+
+
+pub const HTYPE_ETHERNET:  u16   = 1;
+pub const LEN:             usize = 28;
+
+/// How long a learned address is trusted, in seconds
+pub const ENTRY_TTL:       u64   = 60;
+/// How long to wait on an unanswered request before asking again, in seconds
+pub const RETRY_PERIOD:    u64   = 1;
+/// How long packets may wait on an address which never answers, in seconds
+pub const PENDING_TIMEOUT: u64   = 3;
+/// How many packets may wait on any one address
+pub const MAX_PENDING:     usize = 16;
+
+#[repr(u16)]
+#[derive(PartialEq, Eq,
+         Copy, Clone, Hash, Debug)]
+pub enum Operation {
+  Request = 1,
+  Reply   = 2,
+}
+
+#[derive(PartialEq, Eq,
+         Copy, Clone, Hash, Debug)]
+pub struct Packet {
+  pub operation:  Operation,
+  pub sender_mac: MacAddr,
+  pub sender_ip:  [u8; 4],
+  pub target_mac: MacAddr,
+  pub target_ip:  [u8; 4],
+}
+
+#[inline]
+fn read_u16(b: &[u8]) -> u16 {
+  (b[0] as u16) << 8 | b[1] as u16
+}
+
+impl Packet {
+  /// `None` for anything but a well formed Ethernet / IPv4 ARP packet
+  pub fn parse(buf: &[u8]) -> Option<Packet> {
+    if buf.len() < LEN {
+      return None;
+    }
+    if read_u16(&buf[0..2]) != HTYPE_ETHERNET
+      || read_u16(&buf[2..4]) != ETHERTYPE_IPV4
+      || buf[4] != 6
+      || buf[5] != 4
+    {
+      return None;
+    }
+    let operation = match read_u16(&buf[6..8]) {
+      1 => Operation::Request,
+      2 => Operation::Reply,
+      _ => return None,
+    };
+    Some(Packet {
+      operation:  operation,
+      sender_mac: parse_mac(&buf[8..14]),
+      sender_ip:  [buf[14], buf[15], buf[16], buf[17]],
+      target_mac: parse_mac(&buf[18..24]),
+      target_ip:  [buf[24], buf[25], buf[26], buf[27]],
+    })
+  }
+
+  pub fn write(&self, buf: &mut Vec<u8>) {
+    let op = self.operation as u16;
+    buf.extend_from_slice(&[(HTYPE_ETHERNET >> 8) as u8, HTYPE_ETHERNET as u8,
+                            (ETHERTYPE_IPV4 >> 8) as u8, ETHERTYPE_IPV4 as u8,
+                            6, 4,
+                            (op >> 8) as u8, op as u8]);
+    buf.extend_from_slice(&self.sender_mac.0);
+    buf.extend_from_slice(&self.sender_ip);
+    buf.extend_from_slice(&self.target_mac.0);
+    buf.extend_from_slice(&self.target_ip);
+  }
+}
+
+
+/// What to do with an outgoing packet
+pub enum Resolution {
+  /// The address is known, send it
+  Send(MacAddr, dl::Packet),
+  /// The packet was queued, and a request should go out
+  Request,
+  /// The packet was queued (or dropped if too many were) behind a recent
+  /// request
+  Wait,
+}
+
+struct Pending {
+  /// When the first packet began waiting
+  since:     Instant,
+  requested: Instant,
+  packets:   Vec<dl::Packet>,
+}
+
+impl Pending {
+  fn new(packet: dl::Packet) -> Pending {
+    let now = Instant::now();
+    Pending { since: now, requested: now, packets: vec![packet] }
+  }
+
+  fn release(self, pool: &Pool) {
+    for packet in self.packets {
+      pool.give(packet);
+    }
+  }
+}
+
+pub struct Cache {
+  entries:     HashMap<[u8; 4], (MacAddr, Instant)>,
+  pending:     HashMap<[u8; 4], Pending>,
+  timeout:     Duration,
+  max_pending: usize,
+}
+
+impl Cache {
+  pub fn new() -> Cache {
+    Cache::with_limits(Duration::from_secs(PENDING_TIMEOUT), MAX_PENDING)
+  }
+
+  /// How long packets may wait on an address, and how many of them
+  pub fn with_limits(timeout: Duration, max_pending: usize) -> Cache {
+    Cache {
+      entries:     HashMap::new(),
+      pending:     HashMap::new(),
+      timeout:     timeout,
+      max_pending: max_pending,
+    }
+  }
+
+  pub fn lookup(&mut self, ip: [u8; 4]) -> Option<MacAddr> {
+    match self.entries.get(&ip) {
+      Some(&(mac, learned_at))
+        if learned_at.elapsed() < Duration::from_secs(ENTRY_TTL) => return Some(mac),
+      _ => (),
+    };
+    // forget it if stale
+    self.entries.remove(&ip);
+    None
+  }
+
+  /// Buffers of packets which are dropped are given to `pool`
+  pub fn resolve(&mut self, ip: [u8; 4], packet: dl::Packet, pool: &Pool) -> Resolution {
+    self.expire(pool);
+
+    if let Some(mac) = self.lookup(ip) {
+      return Resolution::Send(mac, packet);
+    }
+    match self.pending.entry(ip) {
+      Vacant(entry) => {
+        entry.insert(Pending::new(packet));
+        Resolution::Request
+      },
+      Occupied(mut entry) => {
+        let pending = entry.get_mut();
+        if pending.packets.len() < self.max_pending {
+          pending.packets.push(packet);
+        } else {
+          debug!("too many packets waiting on ARP, dropping one");
+          pool.give(packet);
+        }
+        if pending.requested.elapsed() >= Duration::from_secs(RETRY_PERIOD) {
+          pending.requested = Instant::now();
+          Resolution::Request
+        } else {
+          Resolution::Wait
+        }
+      },
+    }
+  }
+
+  /// Records a mapping heard on the wire, returning the packets which were
+  /// waiting on it. Per RFC 826, unknown mappings are only added if `create`,
+  /// or if we asked.
+  pub fn learn(&mut self, ip: [u8; 4], mac: MacAddr, create: bool) -> Vec<dl::Packet> {
+    let waiting = self.pending.remove(&ip);
+    if create || waiting.is_some() || self.entries.contains_key(&ip) {
+      debug!("ARP: {}.{}.{}.{} is at {}", ip[0], ip[1], ip[2], ip[3], mac);
+      self.entries.insert(ip, (mac, Instant::now()));
+    }
+    waiting.map(|p| p.packets).unwrap_or(Vec::new())
+  }
+
+  /// Drops the packets which have waited too long on an address which never
+  /// answered, returning how many addresses there were
+  pub fn expire(&mut self, pool: &Pool) -> usize {
+    let timeout = self.timeout;
+    let expired: Vec<[u8; 4]> = self.pending.iter()
+      .filter(|&(_, p)| p.since.elapsed() >= timeout)
+      .map(|(ip, _)| *ip)
+      .collect();
+    for ip in expired.iter() {
+      debug!("ARP: {}.{}.{}.{} never answered", ip[0], ip[1], ip[2], ip[3]);
+      if let Some(pending) = self.pending.remove(ip) {
+        pending.release(pool);
+      }
+    }
+    expired.len()
+  }
+
+  /// How many packets wait on the address
+  pub fn pending(&self, ip: [u8; 4]) -> usize {
+    self.pending.get(&ip).map_or(0, |p| p.packets.len())
+  }
+
+  pub fn entries(&self) -> Vec<([u8; 4], MacAddr)> {
+    self.entries.iter().map(|(ip, &(mac, _))| (*ip, mac)).collect()
+  }
+}
@@ -0,0 +1,320 @@
+#![feature(box_syntax)]
+#![feature(question_mark)]
+
+#[macro_use]
+extern crate log;
+
+extern crate misc;
+extern crate interface as dl;
+
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+
+use misc::interface as root;
+use misc::pool::Pool;
+
+pub mod arp;
+
+
+pub const HDR_LEN: usize = 14;
+
+/// How many spare buffers an interface keeps by default, each big enough for
+/// a typical frame
+pub const DEFAULT_POOL_SIZE: usize = 64;
+const DEFAULT_BUF_SIZE: usize = 1500 + HDR_LEN;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP:  u16 = 0x0806;
+
+#[derive(PartialEq, PartialOrd, Eq, Ord,
+         Copy, Clone, Hash, Debug)]
+pub struct MacAddr(pub [u8; 6]);
+
+pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+
+impl MacAddr {
+  pub fn is_broadcast(&self) -> bool {
+    *self == BROADCAST
+  }
+
+  /// A locally administered address made from an IPv4 address, which is
+  /// handy for virtual links
+  pub fn from_ipv4(ip: [u8; 4]) -> MacAddr {
+    MacAddr([0x02, 0x00, ip[0], ip[1], ip[2], ip[3]])
+  }
+}
+
+impl fmt::Display for MacAddr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let m = self.0;
+    write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", m[0], m[1], m[2], m[3], m[4], m[5])
+  }
+}
+
+#[inline]
+pub fn parse_mac(b: &[u8]) -> MacAddr {
+  MacAddr([b[0], b[1], b[2], b[3], b[4], b[5]])
+}
+
+/// Builds an Ethernet II frame, in a buffer from the pool
+pub fn frame(pool:      &Pool,
+             dst:       MacAddr,
+             src:       MacAddr,
+             ethertype: u16,
+             payload:   &[u8])
+             -> dl::Packet
+{
+  let mut buf = pool.take();
+  buf.extend_from_slice(&dst.0);
+  buf.extend_from_slice(&src.0);
+  buf.extend_from_slice(&[(ethertype >> 8) as u8, ethertype as u8]);
+  buf.extend_from_slice(payload);
+  buf
+}
+
+
+struct Shared<'a, E> {
+  inner:   RwLock<Box<dl::Interface<'a, Error=E> + Send + Sync + 'a>>,
+  mac:     MacAddr,
+  ip:      [u8; 4],
+  cache:   Mutex<arp::Cache>,
+  handler: RwLock<dl::BatchHandler<'a>>,
+  pool:    RwLock<Arc<Pool>>,
+}
+
+impl<'a, E> Shared<'a, E> {
+  fn send_frame(&self, dst: MacAddr, ethertype: u16, payload: &[u8]) -> dl::Result<(), E> {
+    let frame = frame(&**self.pool.read().unwrap(), dst, self.mac, ethertype, payload);
+    self.inner.read().unwrap().send(frame)
+  }
+
+  /// Like `send_frame`, and then gives the payload's buffer to the pool
+  fn send_packet(&self, dst: MacAddr, packet: dl::Packet) -> dl::Result<(), E> {
+    let result = self.send_frame(dst, ETHERTYPE_IPV4, &packet[..]);
+    self.pool.read().unwrap().give(packet);
+    result
+  }
+
+  fn send_arp(&self, dst: MacAddr, packet: arp::Packet) -> dl::Result<(), E> {
+    let mut buf = Vec::with_capacity(arp::LEN);
+    packet.write(&mut buf);
+    self.send_frame(dst, ETHERTYPE_ARP, &buf[..])
+  }
+
+  fn send_ip(&self, next_hop: [u8; 4], packet: dl::Packet) -> dl::Result<(), E> {
+    // the cache must not be locked while sending, as the reply may be
+    // delivered right away
+    let resolution = {
+      let pool = self.pool.read().unwrap();
+      self.cache.lock().unwrap().resolve(next_hop, packet, &**pool)
+    };
+    match resolution {
+      arp::Resolution::Send(mac, packet) => self.send_packet(mac, packet),
+      arp::Resolution::Request           => {
+        debug!("ARP: asking who has {:?}", next_hop);
+        self.send_arp(BROADCAST, arp::Packet {
+          operation:  arp::Operation::Request,
+          sender_mac: self.mac,
+          sender_ip:  self.ip,
+          target_mac: MacAddr([0; 6]),
+          target_ip:  next_hop,
+        })
+      },
+      arp::Resolution::Wait              => Ok(()),
+    }
+  }
+
+  /// Handles a batch of frames, passing on the IP packets among them as one
+  /// batch in turn
+  fn receive_batch(&self, frames: Vec<dl::Packet>) {
+    // whatever the batch holds, packets waiting too long on ARP go
+    self.cache.lock().unwrap().expire(&**self.pool.read().unwrap());
+
+    let packets: Vec<dl::Packet> = frames.into_iter()
+      .filter_map(|frame| self.receive(frame))
+      .collect();
+    if !packets.is_empty() {
+      (**self.handler.read().unwrap())(packets);
+    }
+  }
+
+  /// Returns the IP packet carried by the frame, if any
+  fn receive(&self, mut frame: dl::Packet) -> Option<dl::Packet> {
+    if frame.len() < HDR_LEN {
+      debug!("dropping runt frame");
+      return None;
+    }
+
+    let dst = parse_mac(&frame[0..6]);
+    if dst != self.mac && !dst.is_broadcast() {
+      debug!("dropping frame for {}, which isn't us", dst);
+      return None;
+    }
+
+    match (frame[12] as u16) << 8 | frame[13] as u16 {
+      ETHERTYPE_IPV4 => {
+        frame.drain(..HDR_LEN);
+        // short frames get padded, which IP would take for a bad length
+        if frame.len() >= 4 {
+          let total_length = (frame[2] as usize) << 8 | frame[3] as usize;
+          if total_length < frame.len() {
+            frame.truncate(total_length);
+          }
+        }
+        return Some(frame);
+      },
+      ETHERTYPE_ARP  => match arp::Packet::parse(&frame[HDR_LEN..]) {
+        None    => debug!("dropping malformed ARP packet"),
+        Some(p) => self.receive_arp(p),
+      },
+      t              => debug!("dropping frame of unknown type {:04x}", t),
+    };
+    None
+  }
+
+  fn receive_arp(&self, packet: arp::Packet) {
+    // a sender of 0.0.0.0 is just probing, and has nothing to teach us
+    if packet.sender_ip == [0; 4] {
+      return;
+    }
+
+    let for_us   = packet.target_ip == self.ip;
+    let released = self.cache.lock().unwrap()
+      .learn(packet.sender_ip, packet.sender_mac, for_us);
+
+    if for_us && packet.operation == arp::Operation::Request {
+      let reply = arp::Packet {
+        operation:  arp::Operation::Reply,
+        sender_mac: self.mac,
+        sender_ip:  self.ip,
+        target_mac: packet.sender_mac,
+        target_ip:  packet.sender_ip,
+      };
+      if self.send_arp(packet.sender_mac, reply).is_err() {
+        debug!("could not send ARP reply");
+      }
+    }
+
+    for p in released {
+      if self.send_packet(packet.sender_mac, p).is_err() {
+        debug!("could not send packet which was waiting on ARP");
+      }
+    }
+  }
+}
+
+
+/// Ethernet II framing, with ARP, over a link which carries raw frames (e.g.
+/// the tap driver in tap mode). Unlike the point-to-point drivers, one of
+/// these can reach any number of neighbors on the segment.
+pub struct Interface<'a, E> {
+  shared: Arc<Shared<'a, E>>,
+}
+
+impl<'a, E> Interface<'a, E> where E: 'a {
+  pub fn new(inner:   Box<dl::Interface<'a, Error=E> + Send + Sync + 'a>,
+             mac:     MacAddr,
+             ip:      [u8; 4],
+             on_recv: dl::Handler<'a>)
+             -> Interface<'a, E>
+  {
+    let shared = Arc::new(Shared {
+      inner:   RwLock::new(inner),
+      mac:     mac,
+      ip:      ip,
+      cache:   Mutex::new(arp::Cache::new()),
+      handler: RwLock::new(dl::unbatch(on_recv)),
+      pool:    RwLock::new(Arc::new(Pool::new(DEFAULT_BUF_SIZE, DEFAULT_POOL_SIZE))),
+    });
+
+    // weak, as the inner interface owns its handler
+    let weak = Arc::downgrade(&shared);
+    shared.inner.read().unwrap().update_recv_batch_handler(box move |frames: Vec<dl::Packet>| {
+      if let Some(shared) = weak.upgrade() {
+        shared.receive_batch(frames);
+      }
+    });
+
+    Interface { shared: shared }
+  }
+
+  pub fn mac(&self) -> MacAddr {
+    self.shared.mac
+  }
+
+  /// What we currently know of our neighbors
+  pub fn arp_entries(&self) -> Vec<([u8; 4], MacAddr)> {
+    self.shared.cache.lock().unwrap().entries()
+  }
+}
+
+impl<'a, E> root::Interface for Interface<'a, E> {
+  type Error = E;
+}
+
+impl<'a, E> dl::Interface<'a> for Interface<'a, E> where E: 'a {
+  /// Assumes the destination of the packet is on the segment
+  fn send(&self, packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    if packet.len() < 20 {
+      // can't be IPv4, so there is no one to resolve
+      if !self.get_status() {
+        Err(dl::Error::Disabled)?;
+      }
+      return self.shared.send_packet(BROADCAST, packet);
+    }
+    let dst = [packet[16], packet[17], packet[18], packet[19]];
+    self.send_to(dst, packet)
+  }
+
+  fn send_to(&self, next_hop: [u8; 4], packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    if !self.get_status() {
+      Err(dl::Error::Disabled)?;
+    }
+    self.shared.send_ip(next_hop, packet)
+  }
+
+  /// Frames are built in buffers from the pool, which the inner interface
+  /// shares
+  fn stock(&self, pool: Arc<Pool>) {
+    self.shared.inner.read().unwrap().stock(pool.clone());
+    *self.shared.pool.write().unwrap() = pool;
+  }
+
+  fn mtu(&self) -> usize {
+    self.shared.inner.read().unwrap().mtu().saturating_sub(HDR_LEN)
+  }
+
+  fn update_recv_handler<'b>(&'b self, on_recv: dl::Handler<'a>)
+    where 'a: 'b
+  {
+    *self.shared.handler.write().unwrap() = dl::unbatch(on_recv);
+  }
+
+  fn update_recv_batch_handler<'b>(&'b self, on_recv: dl::BatchHandler<'a>)
+    where 'a: 'b
+  {
+    *self.shared.handler.write().unwrap() = on_recv;
+  }
+
+  fn update_status_handler<'b>(&'b self, on_status: dl::StatusHandler<'a>)
+    where 'a: 'b
+  {
+    self.shared.inner.read().unwrap().update_status_handler(on_status);
+  }
+
+  fn dropped_disabled(&self) -> usize {
+    self.shared.inner.read().unwrap().dropped_disabled()
+  }
+
+  fn enable(&mut self) {
+    self.shared.inner.write().unwrap().enable();
+  }
+
+  fn disable(&mut self) {
+    self.shared.inner.write().unwrap().disable();
+  }
+
+  fn get_status(&self) -> bool {
+    self.shared.inner.read().unwrap().get_status()
+  }
+}
@@ -0,0 +1,95 @@
+#![feature(box_syntax)]
+
+extern crate misc;
+extern crate interface as dl;
+extern crate channel;
+extern crate ethernet;
+
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use misc::SenderClosure;
+use misc::pool::Pool;
+use channel::Queue;
+use ethernet::{arp, Interface, MacAddr};
+
+const IP1: [u8; 4] = [10, 0, 0, 1];
+const IP2: [u8; 4] = [10, 0, 0, 2];
+
+#[test]
+fn resolves_then_delivers() {
+  let queue = Queue::new();
+  let (l1, l2) = queue.link(box |_| {}, box |_| {});
+
+  let (tx, rx) = channel::<(dl::Packet,)>();
+  let e1 = Interface::new(box l1, MacAddr::from_ipv4(IP1), IP1, box |_| {});
+  let e2 = Interface::new(box l2, MacAddr::from_ipv4(IP2), IP2, box SenderClosure::new(tx));
+
+  // total length 6, as IP would have it
+  let packet = vec![0x45, 0, 0, 6, 1, 2];
+
+  dl::Interface::send_to(&e1, IP2, packet.clone()).unwrap();
+  // request, reply, then the packet itself
+  assert_eq!(queue.run(), 3);
+  assert_eq!(rx.try_recv().unwrap().0, packet);
+
+  assert_eq!(e1.arp_entries(), vec![(IP2, e2.mac())]);
+  assert_eq!(e2.arp_entries(), vec![(IP1, e1.mac())]);
+
+  // now known, so straight out
+  dl::Interface::send_to(&e1, IP2, packet.clone()).unwrap();
+  assert_eq!(queue.run(), 1);
+  assert_eq!(rx.try_recv().unwrap().0, packet);
+}
+
+#[test]
+fn unanswered_requests_hold_packets() {
+  let queue = Queue::new();
+  let (l1, _l2) = queue.link(box |_| {}, box |_| {});
+
+  let e1 = Interface::new(box l1, MacAddr::from_ipv4(IP1), IP1, box |_| {});
+
+  dl::Interface::send_to(&e1, IP2, vec![1]).unwrap();
+  dl::Interface::send_to(&e1, IP2, vec![2]).unwrap();
+  // only the one request went out, nobody answered
+  assert_eq!(queue.run(), 1);
+  assert!(e1.arp_entries().is_empty());
+}
+
+#[test]
+fn pending_capped_per_address() {
+  let pool = Pool::new(1, 16);
+  let mut cache = arp::Cache::with_limits(Duration::from_secs(30), 2);
+
+  match cache.resolve(IP2, vec![1], &pool) {
+    arp::Resolution::Request => (),
+    _                        => panic!("should have asked"),
+  }
+  for b in 2..5 {
+    cache.resolve(IP2, vec![b], &pool);
+  }
+  assert_eq!(cache.pending(IP2), 2);
+  // the rest went back to the pool
+  assert_eq!(pool.len(), 2);
+
+  let released = cache.learn(IP2, MacAddr::from_ipv4(IP2), false);
+  assert_eq!(released, vec![vec![1], vec![2]]);
+}
+
+#[test]
+fn pending_times_out() {
+  let pool = Pool::new(1, 16);
+  let mut cache = arp::Cache::with_limits(Duration::from_millis(10), 16);
+
+  cache.resolve(IP2, vec![1], &pool);
+  cache.resolve(IP2, vec![2], &pool);
+  thread::sleep(Duration::from_millis(20));
+  assert_eq!(cache.expire(&pool), 1);
+  assert_eq!(cache.pending(IP2), 0);
+  assert_eq!(pool.len(), 2);
+
+  // a late answer releases nothing, and isn't learned as we no longer ask
+  assert!(cache.learn(IP2, MacAddr::from_ipv4(IP2), false).is_empty());
+  assert!(cache.entries().is_empty());
+}
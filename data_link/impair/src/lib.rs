@@ -0,0 +1,396 @@
+#![feature(question_mark)]
+
+#[macro_use]
+extern crate log;
+extern crate rand;
+
+extern crate misc;
+extern crate interface as dl;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt::Debug;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use misc::interface as root;
+use misc::pool::Pool;
+
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// How badly to treat outgoing packets. Probabilities are per packet, and
+/// should be between 0 and 1.
+#[derive(PartialEq,
+         Copy, Clone, Debug)]
+pub struct Config {
+  pub loss:      f64,
+  pub duplicate: f64,
+  pub reorder:   f64,
+  pub corrupt:   f64,
+
+  /// Added to every packet
+  pub delay:     Duration,
+  /// Upper bound of a further, uniformly distributed delay. Packets may
+  /// overtake each other as a result.
+  pub jitter:    Duration,
+  /// How long a packet held back for reordering waits for another to
+  /// overtake it, before it goes anyway
+  pub hold:      Duration,
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    Config {
+      loss:      0.0,
+      duplicate: 0.0,
+      reorder:   0.0,
+      corrupt:   0.0,
+      delay:     Duration::new(0, 0),
+      jitter:    Duration::new(0, 0),
+      hold:      Duration::from_millis(100),
+    }
+  }
+}
+
+type Inner<E> = Arc<RwLock<Box<dl::Interface<'static, Error=E> + Send + Sync>>>;
+
+/// A packet, and the neighbor it is for if the sender said
+type Outgoing = (Option<[u8; 4]>, dl::Packet);
+
+enum Job {
+  Send(Outgoing),
+  /// Sends the held packet, if it is still the one this job was queued for
+  Release,
+}
+
+/// Something for the delay thread to do once its time comes
+struct Due {
+  at:  Instant,
+  // breaks ties in the order they were queued
+  seq: u64,
+  job: Job,
+}
+
+impl PartialEq for Due {
+  fn eq(&self, other: &Due) -> bool {
+    (self.at, self.seq) == (other.at, other.seq)
+  }
+}
+
+impl Eq for Due {}
+
+impl PartialOrd for Due {
+  fn partial_cmp(&self, other: &Due) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Due {
+  /// Reversed, so the heap gives the earliest first
+  fn cmp(&self, other: &Due) -> Ordering {
+    (other.at, other.seq).cmp(&(self.at, self.seq))
+  }
+}
+
+struct Queue {
+  jobs:     BinaryHeap<Due>,
+  next_seq: u64,
+  stop:     bool,
+}
+
+/// What the interface shares with its delay thread
+struct Shared<E> {
+  inner: Inner<E>,
+  queue: Mutex<Queue>,
+  wake:  Condvar,
+  // a packet being reordered waits here for its successor, with the number
+  // of the job which releases it otherwise
+  held:  Mutex<Option<(u64, Outgoing)>>,
+}
+
+impl<E> Shared<E> where E: Debug + 'static {
+  /// Returns the job's number
+  fn schedule(&self, at: Instant, job: Job) -> u64 {
+    let mut queue = self.queue.lock().unwrap();
+    let seq = queue.next_seq;
+    queue.next_seq += 1;
+    queue.jobs.push(Due { at: at, seq: seq, job: job });
+    self.wake.notify_one();
+    seq
+  }
+
+  /// Runs the jobs as they come due, until told to stop
+  fn run(&self) {
+    let mut queue = self.queue.lock().unwrap();
+    while !queue.stop {
+      let now = Instant::now();
+      let wait = match queue.jobs.peek() {
+        None                      => None,
+        Some(due) if due.at > now => Some(due.at - now),
+        Some(_)                   => {
+          let due = queue.jobs.pop().unwrap();
+          // not while locked, as the inner interface may deliver
+          // synchronously and end up back here
+          drop(queue);
+          self.work(due);
+          queue = self.queue.lock().unwrap();
+          continue;
+        },
+      };
+      queue = match wait {
+        None       => self.wake.wait(queue).unwrap(),
+        Some(wait) => self.wake.wait_timeout(queue, wait).unwrap().0,
+      };
+    }
+  }
+
+  fn work(&self, due: Due) {
+    let packet = match due.job {
+      Job::Send(packet) => packet,
+      Job::Release      => {
+        let mut held = self.held.lock().unwrap();
+        match held.take() {
+          Some((seq, packet)) if seq == due.seq => {
+            debug!("impairment: nothing overtook held packet, sending it");
+            packet
+          },
+          // overtaken or flushed already, and maybe another held since
+          other => {
+            *held = other;
+            return;
+          },
+        }
+      },
+    };
+    match send_inner(&self.inner, packet) {
+      Ok(())  => (),
+      Err(e)  => debug!("delayed packet could not be sent because {:?}", e),
+    }
+  }
+}
+
+/// Wraps another interface, mistreating whatever is sent over it.
+///
+/// Only the outgoing direction is impaired, so wrap both ends of a link to
+/// impair both directions. All randomness comes from the given seed, so a run
+/// can be reproduced as long as the same packets are sent in the same order.
+/// The inner interface must be `'static`, as delayed packets are sent from a
+/// thread of its own, which is stopped and joined on drop. Packets still
+/// waiting then are dropped.
+pub struct Interface<E> {
+  shared:  Arc<Shared<E>>,
+  config:  Config,
+  rng:     Mutex<XorShiftRng>,
+  delayer: Option<JoinHandle<()>>,
+}
+
+impl<E> Interface<E> where E: Debug + 'static {
+  /// The seed must not be all zeros.
+  pub fn new(inner:  Box<dl::Interface<'static, Error=E> + Send + Sync>,
+             config: Config,
+             seed:   [u32; 4])
+             -> Interface<E>
+  {
+    let shared = Arc::new(Shared {
+      inner: Arc::new(RwLock::new(inner)),
+      queue: Mutex::new(Queue {
+        jobs:     BinaryHeap::new(),
+        next_seq: 0,
+        stop:     false,
+      }),
+      wake:  Condvar::new(),
+      held:  Mutex::new(None),
+    });
+    let delayer = {
+      let shared = shared.clone();
+      thread::spawn(move || shared.run())
+    };
+    Interface {
+      shared:  shared,
+      config:  config,
+      rng:     Mutex::new(SeedableRng::from_seed(seed)),
+      delayer: Some(delayer),
+    }
+  }
+
+  pub fn config(&self) -> &Config {
+    &self.config
+  }
+
+  pub fn set_config(&mut self, config: Config) {
+    self.config = config;
+  }
+
+  /// Sends the packet being held back for reordering, if there is one,
+  /// without waiting for its hold to run out
+  pub fn flush(&self) -> dl::Result<(), E> {
+    let held = self.shared.held.lock().unwrap().take();
+    match held {
+      None              => Ok(()),
+      Some((_, packet)) => {
+        let delay = self.pick_delay(&mut *self.rng.lock().unwrap());
+        self.transmit(packet, delay)
+      },
+    }
+  }
+
+  fn pick_delay(&self, rng: &mut XorShiftRng) -> Duration {
+    let jitter = self.config.jitter.as_secs() * NANOS_PER_SEC
+      + self.config.jitter.subsec_nanos() as u64;
+    if jitter == 0 {
+      return self.config.delay;
+    }
+    let extra = rng.gen_range(0, jitter + 1);
+    self.config.delay + Duration::new(extra / NANOS_PER_SEC,
+                                      (extra % NANOS_PER_SEC) as u32)
+  }
+
+  fn transmit(&self, packet: Outgoing, delay: Duration) -> dl::Result<(), E> {
+    if delay == Duration::new(0, 0) {
+      return send_inner(&self.shared.inner, packet);
+    }
+    self.shared.schedule(Instant::now() + delay, Job::Send(packet));
+    Ok(())
+  }
+
+  fn impair(&self, mut packet: Outgoing) -> dl::Result<(), E> {
+    if !self.shared.inner.read().unwrap().get_status() {
+      Err(dl::Error::Disabled)?;
+    }
+
+    // decide everything up front, as the inner interface may deliver
+    // synchronously and end up back here
+    let mut outgoing: Vec<(Outgoing, Duration)> = Vec::with_capacity(3);
+    {
+      let mut rng = self.rng.lock().unwrap();
+
+      if rng.gen::<f64>() < self.config.loss {
+        debug!("impairment: dropping packet");
+        return Ok(());
+      }
+
+      if !packet.1.is_empty() && rng.gen::<f64>() < self.config.corrupt {
+        let bit = rng.gen_range(0, packet.1.len() * 8);
+        debug!("impairment: flipping bit {}", bit);
+        packet.1[bit / 8] ^= 1 << (bit % 8);
+      }
+
+      if rng.gen::<f64>() < self.config.duplicate {
+        debug!("impairment: duplicating packet");
+        let delay = self.pick_delay(&mut *rng);
+        outgoing.push((packet.clone(), delay));
+      }
+
+      let reorder = rng.gen::<f64>() < self.config.reorder;
+      let mut held = self.shared.held.lock().unwrap();
+      match held.take() {
+        Some((_, earlier)) => {
+          // overtaken by this one
+          let delay = self.pick_delay(&mut *rng);
+          outgoing.push((packet, delay));
+          let delay = self.pick_delay(&mut *rng);
+          outgoing.push((earlier, delay));
+        },
+        None if reorder => {
+          debug!("impairment: holding packet back");
+          // the lock on `held` keeps the release from coming first
+          let at = Instant::now() + self.config.hold;
+          let seq = self.shared.schedule(at, Job::Release);
+          *held = Some((seq, packet));
+        },
+        None => {
+          let delay = self.pick_delay(&mut *rng);
+          outgoing.push((packet, delay));
+        },
+      }
+    }
+
+    for (packet, delay) in outgoing {
+      self.transmit(packet, delay)?;
+    }
+    Ok(())
+  }
+}
+
+impl<E> Drop for Interface<E> {
+  fn drop(&mut self) {
+    self.shared.queue.lock().unwrap().stop = true;
+    self.shared.wake.notify_one();
+    if let Some(delayer) = self.delayer.take() {
+      if delayer.join().is_err() {
+        debug!("impairment delay thread had panicked");
+      }
+    }
+  }
+}
+
+fn send_inner<E>(inner: &Inner<E>, (next_hop, packet): Outgoing) -> dl::Result<(), E>
+  where E: 'static
+{
+  let inner = inner.read().unwrap();
+  match next_hop {
+    None           => inner.send(packet),
+    Some(next_hop) => inner.send_to(next_hop, packet),
+  }
+}
+
+impl<E> root::Interface for Interface<E> {
+  type Error = E;
+}
+
+impl<E> dl::Interface<'static> for Interface<E> where E: Debug + 'static {
+  fn send(&self, packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    self.impair((None, packet))
+  }
+
+  fn send_to(&self, next_hop: [u8; 4], packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    self.impair((Some(next_hop), packet))
+  }
+
+  fn stock(&self, pool: Arc<Pool>) {
+    self.shared.inner.read().unwrap().stock(pool);
+  }
+
+  fn mtu(&self) -> usize {
+    self.shared.inner.read().unwrap().mtu()
+  }
+
+  fn update_recv_handler<'b>(&'b self, on_recv: dl::Handler<'static>)
+    where 'static: 'b
+  {
+    self.shared.inner.read().unwrap().update_recv_handler(on_recv);
+  }
+
+  fn update_recv_batch_handler<'b>(&'b self, on_recv: dl::BatchHandler<'static>)
+    where 'static: 'b
+  {
+    self.shared.inner.read().unwrap().update_recv_batch_handler(on_recv);
+  }
+
+  fn update_status_handler<'b>(&'b self, on_status: dl::StatusHandler<'static>)
+    where 'static: 'b
+  {
+    self.shared.inner.read().unwrap().update_status_handler(on_status);
+  }
+
+  fn dropped_disabled(&self) -> usize {
+    self.shared.inner.read().unwrap().dropped_disabled()
+  }
+
+  fn enable(&mut self) {
+    self.shared.inner.write().unwrap().enable();
+  }
+
+  fn disable(&mut self) {
+    self.shared.inner.write().unwrap().disable();
+  }
+
+  fn get_status(&self) -> bool {
+    self.shared.inner.read().unwrap().get_status()
+  }
+}
@@ -0,0 +1,164 @@
+#![feature(box_syntax)]
+
+extern crate misc;
+extern crate interface as dl;
+extern crate channel;
+extern crate impair;
+
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use misc::SenderClosure;
+use impair::{Config, Interface};
+
+const SEED: [u32; 4] = [1, 2, 3, 4];
+
+/// Long enough that no held packet goes by itself during a test
+const NEVER: u64 = 3600;
+
+fn impaired(config: Config) -> (Interface<channel::Error>, Receiver<(dl::Packet,)>) {
+  impaired_with_seed(config, SEED)
+}
+
+fn impaired_with_seed(config: Config, seed: [u32; 4])
+                      -> (Interface<channel::Error>, Receiver<(dl::Packet,)>)
+{
+  let (tx, rx) = channel::<(dl::Packet,)>();
+  let (i1, _i2) = channel::link(box |_| {}, box SenderClosure::new(tx));
+  (Interface::new(box i1, config, seed), rx)
+}
+
+/// The bytes of whatever has arrived so far
+fn received(rx: &Receiver<(dl::Packet,)>) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  while let Ok((packet,)) = rx.try_recv() {
+    bytes.push(packet[0]);
+  }
+  bytes
+}
+
+fn send(i: &Interface<channel::Error>, byte: u8) {
+  dl::Interface::send(i, vec![byte]).unwrap();
+}
+
+#[test]
+fn total_loss() {
+  let (i, rx) = impaired(Config { loss: 1.0, ..Default::default() });
+  send(&i, 1);
+  send(&i, 2);
+  assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn always_duplicate() {
+  let (i, rx) = impaired(Config { duplicate: 1.0, ..Default::default() });
+  send(&i, 1);
+  assert_eq!(rx.try_recv().unwrap().0, vec![1]);
+  assert_eq!(rx.try_recv().unwrap().0, vec![1]);
+  assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn reorder_swaps_pairs() {
+  let (i, rx) = impaired(Config {
+    reorder: 1.0,
+    hold:    Duration::from_secs(NEVER),
+    ..Default::default()
+  });
+  send(&i, 1);
+  assert!(rx.try_recv().is_err());
+  send(&i, 2);
+  assert_eq!(rx.try_recv().unwrap().0, vec![2]);
+  assert_eq!(rx.try_recv().unwrap().0, vec![1]);
+
+  send(&i, 3);
+  i.flush().unwrap();
+  assert_eq!(rx.try_recv().unwrap().0, vec![3]);
+}
+
+#[test]
+fn corruption_flips_one_bit() {
+  let (i, rx) = impaired(Config { corrupt: 1.0, ..Default::default() });
+  send(&i, 0);
+  assert_eq!(rx.try_recv().unwrap().0[0].count_ones(), 1);
+}
+
+#[test]
+fn hold_runs_out() {
+  let (i, rx) = impaired(Config {
+    reorder: 1.0,
+    hold:    Duration::from_millis(20),
+    ..Default::default()
+  });
+  send(&i, 1);
+  assert!(rx.try_recv().is_err());
+  // nothing comes to overtake it
+  assert_eq!(rx.recv().unwrap().0, vec![1]);
+}
+
+#[test]
+fn same_seed_same_run() {
+  let config = Config {
+    loss:      0.2,
+    duplicate: 0.2,
+    reorder:   0.2,
+    hold:      Duration::from_secs(NEVER),
+    ..Default::default()
+  };
+  let run = |seed| {
+    let (i, rx) = impaired_with_seed(config, seed);
+    for byte in 0..100 {
+      send(&i, byte);
+    }
+    i.flush().unwrap();
+    received(&rx)
+  };
+
+  let first = run(SEED);
+  assert_eq!(run(SEED), first);
+  // and something did happen
+  assert!(first != (0..100).collect::<Vec<u8>>());
+  assert!(run([5, 6, 7, 8]) != first);
+}
+
+#[test]
+fn delay() {
+  let (i, rx) = impaired(Config {
+    delay: Duration::from_millis(50),
+    ..Default::default()
+  });
+  send(&i, 1);
+  send(&i, 2);
+  assert!(rx.try_recv().is_err());
+  thread::sleep(Duration::from_millis(200));
+  // with the same delay, they keep their order
+  assert_eq!(received(&rx), vec![1, 2]);
+}
+
+#[test]
+fn jitter_delivers_everything() {
+  let (i, rx) = impaired(Config {
+    jitter: Duration::from_millis(20),
+    ..Default::default()
+  });
+  for byte in 0..20 {
+    send(&i, byte);
+  }
+  thread::sleep(Duration::from_millis(200));
+  let mut got = received(&rx);
+  got.sort();
+  assert_eq!(got, (0..20).collect::<Vec<u8>>());
+}
+
+#[test]
+fn drop_discards_delayed() {
+  let (i, rx) = impaired(Config {
+    delay: Duration::from_secs(NEVER),
+    ..Default::default()
+  });
+  send(&i, 1);
+  // joins the delay thread, rather than waiting for the packet to go
+  drop(i);
+  assert!(rx.try_recv().is_err());
+}
@@ -5,13 +5,27 @@ extern crate core;
 extern crate misc;
 
 use core::convert::From;
+use std::sync::Arc;
 
 use misc::interface as i;
+use misc::pool::Pool;
 
 
-// TODO: use Box<[u8]> instead of Vec<u8>
+// Vec rather than Box<[u8]>, so buffers can be reused for packets of any size
 pub type Packet = Vec<u8>;
 pub type Handler<'a> = i::Handler<'a, Packet>;
+pub type BatchHandler<'a> = i::BatchHandler<'a, Packet>;
+/// Called with whether the link now works, when the driver notices a change
+pub type StatusHandler<'a> = Box<Fn(bool) + Send + Sync + 'a>;
+
+/// Adapts a per-packet handler for drivers which deliver in batches
+pub fn unbatch<'a>(on_recv: Handler<'a>) -> BatchHandler<'a> {
+  Box::new(move |packets: Vec<Packet>| {
+    for packet in packets {
+      (*on_recv)(packet);
+    }
+  })
+}
 
 #[derive(Clone, Copy,
          PartialEq, Eq,
@@ -34,12 +48,42 @@ pub trait Interface<'a>: i::Interface {
   /// Send packet with specified body
   fn send(&self, packet: Packet) -> self::Result<(), Self::Error>;
 
+  /// Send packet to the neighbor with the given IPv4 address. Only links with
+  /// more than one neighbor need to care, so by default this is just `send`.
+  fn send_to(&self, _next_hop: [u8; 4], packet: Packet) -> self::Result<(), Self::Error> {
+    self.send(packet)
+  }
+
+  /// The largest packet, in bytes, which the link can carry in one go
+  fn mtu(&self) -> usize;
+
   /// Update the function called on an arriving packet
   fn update_recv_handler<'b>(&'b self, on_recv: Handler<'a>) where 'a: 'b;
 
+  /// Like `update_recv_handler`, but the function may be given several
+  /// packets at once. Drivers which receive one packet at a time needn't
+  /// bother, so by default each packet is handed over alone.
+  fn update_recv_batch_handler<'b>(&'b self, on_recv: BatchHandler<'a>) where 'a: 'b {
+    self.update_recv_handler(Box::new(move |packet: Packet| (*on_recv)(vec![packet])));
+  }
+
   //fn new(on_receive: |Vec<u8>| -> ()) -> Self;
 
-  //fn stock(&mut Self, bufs: Box<[Vec<u8>]>);
+  /// Give the driver a pool to draw receive buffers from, and to return
+  /// buffers to once sent. Drivers which don't allocate may ignore it, as
+  /// they do by default.
+  fn stock(&self, _pool: Arc<Pool>) {}
+
+  /// Update the function called when the driver notices the underlying link
+  /// fail or recover. Drivers which can't tell may ignore it, as they do by
+  /// default.
+  fn update_status_handler<'b>(&'b self, _on_status: StatusHandler<'a>) where 'a: 'b {}
+
+  /// How many received packets were dropped because the interface was
+  /// disabled, for drivers which keep count
+  fn dropped_disabled(&self) -> usize {
+    0
+  }
 
   fn enable(&mut self);
   fn disable(&mut self);
@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use dl;
+use misc::interface as root;
+use misc::pool::Pool;
+
+use file;
+use file::Writer;
+
+
+type SharedWriter = Arc<Mutex<Writer<Box<Write + Send>>>>;
+
+fn record(writer: &SharedWriter, packet: &[u8]) {
+  match writer.lock().unwrap().write_packet(file::now(), packet) {
+    Ok(())  => (),
+    Err(e)  => debug!("could not record packet in capture: {}", e),
+  }
+}
+
+
+/// Wraps another interface, recording every packet sent or received over it
+pub struct Interface<'a, E> {
+  inner:  Box<dl::Interface<'a, Error=E> + Send + Sync + 'a>,
+  writer: SharedWriter,
+}
+
+impl<'a, E> Interface<'a, E> {
+  /// Sent packets are recorded once the inner interface accepts them.
+  /// Received ones are only recorded once `update_recv_handler` or
+  /// `update_recv_batch_handler` has been called on this wrapper, as the
+  /// handler the inner interface already has bypasses it.
+  pub fn new(inner:  Box<dl::Interface<'a, Error=E> + Send + Sync + 'a>,
+             writer: Writer<Box<Write + Send>>)
+             -> Interface<'a, E>
+  {
+    Interface {
+      inner:  inner,
+      writer: Arc::new(Mutex::new(writer)),
+    }
+  }
+
+  /// Captures to a new file at `path`, assuming the link carries bare IP
+  pub fn create<P>(inner: Box<dl::Interface<'a, Error=E> + Send + Sync + 'a>,
+                   path:  P)
+                   -> io::Result<Interface<'a, E>>
+    where P: AsRef<Path>
+  {
+    let file: Box<Write + Send> = box BufWriter::new(File::create(path)?);
+    Ok(Interface::new(inner, Writer::new(file, file::LINKTYPE_RAW)?))
+  }
+}
+
+impl<'a, E> root::Interface for Interface<'a, E> {
+  type Error = E;
+}
+
+impl<'a, E> dl::Interface<'a> for Interface<'a, E> {
+  fn send(&self, packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    // the inner interface takes the buffer, but only what it actually sends
+    // is recorded
+    let copy = packet.clone();
+    let result = self.inner.send(packet);
+    if result.is_ok() {
+      record(&self.writer, &copy[..]);
+    }
+    result
+  }
+
+  fn send_to(&self, next_hop: [u8; 4], packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    let copy = packet.clone();
+    let result = self.inner.send_to(next_hop, packet);
+    if result.is_ok() {
+      record(&self.writer, &copy[..]);
+    }
+    result
+  }
+
+  fn stock(&self, pool: Arc<Pool>) {
+    self.inner.stock(pool);
+  }
+
+  fn mtu(&self) -> usize {
+    self.inner.mtu()
+  }
+
+  fn update_recv_handler<'b>(&'b self, on_recv: dl::Handler<'a>)
+    where 'a: 'b
+  {
+    let writer = self.writer.clone();
+    self.inner.update_recv_handler(box move |packet: dl::Packet| {
+      record(&writer, &packet[..]);
+      (*on_recv)(packet);
+    });
+  }
+
+  fn update_recv_batch_handler<'b>(&'b self, on_recv: dl::BatchHandler<'a>)
+    where 'a: 'b
+  {
+    let writer = self.writer.clone();
+    self.inner.update_recv_batch_handler(box move |packets: Vec<dl::Packet>| {
+      for packet in packets.iter() {
+        record(&writer, &packet[..]);
+      }
+      (*on_recv)(packets);
+    });
+  }
+
+  fn update_status_handler<'b>(&'b self, on_status: dl::StatusHandler<'a>)
+    where 'a: 'b
+  {
+    self.inner.update_status_handler(on_status);
+  }
+
+  fn dropped_disabled(&self) -> usize {
+    self.inner.dropped_disabled()
+  }
+
+  fn enable(&mut self) {
+    self.inner.enable();
+  }
+
+  fn disable(&mut self) {
+    self.inner.disable();
+  }
+
+  fn get_status(&self) -> bool {
+    self.inner.get_status()
+  }
+}
@@ -0,0 +1,193 @@
+//! The classic libpcap file format, see
+//! https://wiki.wireshark.org/Development/LibpcapFileFormat
+
+use std::io;
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const MAGIC:       u32 = 0xa1b2c3d4;
+/// Same format, but with nanosecond rather than microsecond timestamps
+pub const MAGIC_NANOS: u32 = 0xa1b23c4d;
+
+pub const VERSION_MAJOR: u16 = 2;
+pub const VERSION_MINOR: u16 = 4;
+
+/// Frames are bare IPv4 or IPv6 packets
+pub const LINKTYPE_RAW:      u32 = 101;
+/// Frames are Ethernet II frames
+pub const LINKTYPE_ETHERNET: u32 = 1;
+/// Frames are bare IPv4 packets
+pub const LINKTYPE_IPV4:     u32 = 228;
+
+/// We never truncate what we capture
+pub const SNAPLEN: u32 = 65535;
+
+pub const GLOBAL_HDR_LEN: usize = 24;
+pub const RECORD_HDR_LEN: usize = 16;
+
+// everything is written little endian, readers detect the order by the magic
+#[inline]
+fn le_u16(v: u16) -> [u8; 2] {
+  [v as u8, (v >> 8) as u8]
+}
+
+#[inline]
+fn le_u32(v: u32) -> [u8; 4] {
+  [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+#[inline]
+fn read_u16(buf: &[u8], big_endian: bool) -> u16 {
+  if big_endian {
+    (buf[0] as u16) << 8 | buf[1] as u16
+  } else {
+    (buf[1] as u16) << 8 | buf[0] as u16
+  }
+}
+
+#[inline]
+fn read_u32(buf: &[u8], big_endian: bool) -> u32 {
+  if big_endian {
+    (read_u16(&buf[0..2], true) as u32) << 16 | read_u16(&buf[2..4], true) as u32
+  } else {
+    (read_u16(&buf[2..4], false) as u32) << 16 | read_u16(&buf[0..2], false) as u32
+  }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Time since the epoch, which is how captures are stamped
+pub fn now() -> Duration {
+  SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0))
+}
+
+
+pub struct Writer<W> {
+  inner: W,
+}
+
+impl<W> Writer<W> where W: Write {
+  /// Writes the global header straight away
+  pub fn new(mut inner: W, linktype: u32) -> io::Result<Writer<W>> {
+    inner.write_all(&le_u32(MAGIC))?;
+    inner.write_all(&le_u16(VERSION_MAJOR))?;
+    inner.write_all(&le_u16(VERSION_MINOR))?;
+    inner.write_all(&le_u32(0))?; // GMT
+    inner.write_all(&le_u32(0))?; // accuracy of timestamps, everybody says 0
+    inner.write_all(&le_u32(SNAPLEN))?;
+    inner.write_all(&le_u32(linktype))?;
+    inner.flush()?;
+    Ok(Writer { inner: inner })
+  }
+
+  /// Appends one record. Flushes so that the capture can be watched live.
+  pub fn write_packet(&mut self, timestamp: Duration, packet: &[u8]) -> io::Result<()> {
+    let incl_len = ::std::cmp::min(packet.len(), SNAPLEN as usize);
+    self.inner.write_all(&le_u32(timestamp.as_secs() as u32))?;
+    self.inner.write_all(&le_u32(timestamp.subsec_nanos() / 1000))?;
+    self.inner.write_all(&le_u32(incl_len as u32))?;
+    self.inner.write_all(&le_u32(packet.len() as u32))?;
+    self.inner.write_all(&packet[..incl_len])?;
+    self.inner.flush()
+  }
+
+  pub fn into_inner(self) -> W {
+    self.inner
+  }
+}
+
+
+#[derive(PartialEq, Eq, PartialOrd, Ord,
+         Clone, Hash, Debug)]
+pub struct Record {
+  pub timestamp: Duration,
+  /// Length on the wire, which is more than `data.len()` if truncated
+  pub orig_len:  u32,
+  pub data:      Vec<u8>,
+}
+
+/// Reads captures of either byte order
+pub struct Reader<R> {
+  inner:      R,
+  big_endian: bool,
+  nanos:      bool,
+  linktype:   u32,
+}
+
+impl<R> Reader<R> where R: Read {
+  /// Reads and checks the global header straight away
+  pub fn new(mut inner: R) -> io::Result<Reader<R>> {
+    let mut hdr = [0u8; GLOBAL_HDR_LEN];
+    inner.read_exact(&mut hdr)?;
+
+    let (big_endian, nanos) = match read_u32(&hdr[0..4], false) {
+      m if m == MAGIC                    => (false, false),
+      m if m == MAGIC_NANOS              => (false, true),
+      m if m == MAGIC.swap_bytes()       => (true,  false),
+      m if m == MAGIC_NANOS.swap_bytes() => (true,  true),
+      _ => return Err(invalid("not a pcap file")),
+    };
+
+    if read_u16(&hdr[4..6], big_endian) != VERSION_MAJOR {
+      return Err(invalid("unsupported pcap version"));
+    }
+
+    Ok(Reader {
+      inner:      inner,
+      big_endian: big_endian,
+      nanos:      nanos,
+      linktype:   read_u32(&hdr[20..24], big_endian),
+    })
+  }
+
+  pub fn linktype(&self) -> u32 {
+    self.linktype
+  }
+
+  /// `None` at a clean end of file
+  pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+    let mut hdr = [0u8; RECORD_HDR_LEN];
+    // distinguish a clean end from a truncated record header
+    match self.inner.read(&mut hdr[..1])? {
+      0 => return Ok(None),
+      _ => self.inner.read_exact(&mut hdr[1..])?,
+    };
+
+    let secs     = read_u32(&hdr[0..4],   self.big_endian);
+    let frac     = read_u32(&hdr[4..8],   self.big_endian);
+    let incl_len = read_u32(&hdr[8..12],  self.big_endian);
+    let orig_len = read_u32(&hdr[12..16], self.big_endian);
+
+    let nanos = if self.nanos { frac } else { frac.saturating_mul(1000) };
+    if nanos >= 1_000_000_000 {
+      return Err(invalid("pcap record timestamp out of range"));
+    }
+    // guard against allocating whatever garbage says
+    if incl_len > SNAPLEN.saturating_mul(4) {
+      return Err(invalid("pcap record is implausibly large"));
+    }
+
+    let mut data = vec![0; incl_len as usize];
+    self.inner.read_exact(&mut data[..])?;
+
+    Ok(Some(Record {
+      timestamp: Duration::new(secs as u64, nanos),
+      orig_len:  orig_len,
+      data:      data,
+    }))
+  }
+}
+
+impl<R> Iterator for Reader<R> where R: Read {
+  type Item = io::Result<Record>;
+
+  fn next(&mut self) -> Option<io::Result<Record>> {
+    match self.next_record() {
+      Ok(None)    => None,
+      Ok(Some(r)) => Some(Ok(r)),
+      Err(e)      => Some(Err(e)),
+    }
+  }
+}
@@ -0,0 +1,12 @@
+#![feature(box_syntax)]
+#![feature(question_mark)]
+
+#[macro_use]
+extern crate log;
+
+extern crate misc;
+extern crate interface as dl;
+
+pub mod file;
+pub mod capture;
+pub mod replay;
@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+use dl;
+use misc::interface as root;
+
+use file;
+use file::Reader;
+
+
+const ETHERNET_HDR_LEN: usize = 14;
+const ETHERTYPE_IPV4:   u16   = 0x0800;
+
+/// Same as Ethernet, as that is where most captures come from
+pub const DEFAULT_MTU: usize = 1500;
+
+/// Replaying cannot fail
+#[derive(PartialEq, Eq,
+         Copy, Clone, Hash, Debug)]
+pub enum Error {}
+
+struct Shared<'a> {
+  pending: Mutex<VecDeque<dl::Packet>>,
+  sent:    Mutex<Vec<dl::Packet>>,
+  handler: RwLock<(bool, dl::BatchHandler<'a>)>,
+}
+
+/// Reads every record of a capture, converted to bare IP packets.
+///
+/// Ethernet captures are unwrapped, skipping anything that isn't IPv4.
+pub fn read_packets<R>(reader: Reader<R>) -> io::Result<Vec<dl::Packet>>
+  where R: Read
+{
+  let linktype = reader.linktype();
+  let mut packets = Vec::new();
+  for record in reader {
+    let data = record?.data;
+    match linktype {
+      file::LINKTYPE_RAW | file::LINKTYPE_IPV4 => packets.push(data),
+      file::LINKTYPE_ETHERNET => {
+        if data.len() < ETHERNET_HDR_LEN {
+          debug!("skipping truncated ethernet frame");
+          continue;
+        }
+        let ethertype = (data[12] as u16) << 8 | data[13] as u16;
+        if ethertype != ETHERTYPE_IPV4 {
+          debug!("skipping non-IPv4 ethernet frame, type {:04x}", ethertype);
+          continue;
+        }
+        packets.push(data[ETHERNET_HDR_LEN..].to_vec());
+      },
+      _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                     "unsupported pcap link type")),
+    }
+  }
+  Ok(packets)
+}
+
+
+/// A link layer interface whose "remote end" is a recording: each recorded
+/// packet is handed to the receive handler when the `Player` says so, and
+/// whatever is sent is kept for inspection.
+pub struct Interface<'a> {
+  shared:        Arc<Shared<'a>>,
+  mtu:           usize,
+  cached_status: bool,
+}
+
+/// Drives an `Interface` after it has been handed to the network layer
+#[derive(Clone)]
+pub struct Player<'a> {
+  shared: Arc<Shared<'a>>,
+}
+
+impl<'a> Interface<'a> {
+  pub fn new(packets: Vec<dl::Packet>, on_recv: dl::Handler<'a>) -> Interface<'a> {
+    Interface {
+      shared:        Arc::new(Shared {
+        pending: Mutex::new(packets.into_iter().collect()),
+        sent:    Mutex::new(Vec::new()),
+        handler: RwLock::new((true, dl::unbatch(on_recv))),
+      }),
+      mtu:           DEFAULT_MTU,
+      cached_status: true,
+    }
+  }
+
+  pub fn from_reader<R>(reader: R, on_recv: dl::Handler<'a>) -> io::Result<Interface<'a>>
+    where R: Read
+  {
+    let packets = read_packets(Reader::new(reader)?)?;
+    Ok(Interface::new(packets, on_recv))
+  }
+
+  pub fn open<P>(path: P, on_recv: dl::Handler<'a>) -> io::Result<Interface<'a>>
+    where P: AsRef<Path>
+  {
+    Interface::from_reader(BufReader::new(File::open(path)?), on_recv)
+  }
+
+  pub fn set_mtu(&mut self, mtu: usize) {
+    self.mtu = mtu;
+  }
+
+  pub fn player(&self) -> Player<'a> {
+    Player { shared: self.shared.clone() }
+  }
+}
+
+impl<'a> Shared<'a> {
+  fn deliver(&self, packets: Vec<dl::Packet>) {
+    match *self.handler.read().unwrap() {
+      (true, ref on_recv) => (**on_recv)(packets),
+      (false, _)          => debug!("Replay interface is not enabled, dropping packet(s)"),
+    };
+  }
+}
+
+impl<'a> Player<'a> {
+  /// Delivers the next recorded packet. Returns false if there was none.
+  ///
+  /// The packet is used up even if the interface is disabled, as it would be
+  /// on a real link.
+  pub fn step(&self) -> bool {
+    let next = self.shared.pending.lock().unwrap().pop_front();
+    match next {
+      None         => false,
+      Some(packet) => {
+        self.shared.deliver(vec![packet]);
+        true
+      },
+    }
+  }
+
+  /// Delivers everything left in one batch, returning how many packets that
+  /// was
+  pub fn play(&self) -> usize {
+    let packets: Vec<_> = self.shared.pending.lock().unwrap().drain(..).collect();
+    let count = packets.len();
+    if count != 0 {
+      self.shared.deliver(packets);
+    }
+    count
+  }
+
+  pub fn remaining(&self) -> usize {
+    self.shared.pending.lock().unwrap().len()
+  }
+
+  /// Everything sent so far, oldest first
+  pub fn sent(&self) -> Vec<dl::Packet> {
+    self.shared.sent.lock().unwrap().clone()
+  }
+
+  /// Like `sent`, but also forgets those packets
+  pub fn take_sent(&self) -> Vec<dl::Packet> {
+    mem::replace(&mut *self.shared.sent.lock().unwrap(), Vec::new())
+  }
+}
+
+impl<'a> root::Interface for Interface<'a> {
+  type Error = Error;
+}
+
+impl<'a> dl::Interface<'a> for Interface<'a> {
+  fn send(&self, packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    if self.cached_status == false {
+      Err(dl::Error::Disabled)?;
+    }
+    self.shared.sent.lock().unwrap().push(packet);
+    Ok(())
+  }
+
+  fn mtu(&self) -> usize {
+    self.mtu
+  }
+
+  fn update_recv_handler<'b>(&'b self, on_recv: dl::Handler<'a>)
+    where 'a: 'b
+  {
+    self.shared.handler.write().unwrap().1 = dl::unbatch(on_recv);
+  }
+
+  fn update_recv_batch_handler<'b>(&'b self, on_recv: dl::BatchHandler<'a>)
+    where 'a: 'b
+  {
+    self.shared.handler.write().unwrap().1 = on_recv;
+  }
+
+  fn enable(&mut self) {
+    self.cached_status = true;
+    self.shared.handler.write().unwrap().0 = true;
+  }
+
+  fn disable(&mut self) {
+    self.cached_status = false;
+    self.shared.handler.write().unwrap().0 = false;
+  }
+
+  fn get_status(&self) -> bool {
+    self.cached_status
+  }
+}
@@ -0,0 +1,62 @@
+#![feature(box_syntax)]
+
+extern crate misc;
+extern crate interface as dl;
+extern crate channel;
+extern crate pcap;
+
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use pcap::file::{self, Writer};
+use pcap::capture::Interface;
+
+/// So we can look at what was written after handing the writer off
+#[derive(Clone)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuf {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.lock().unwrap().write(buf)
+  }
+  fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+#[test]
+fn records_both_directions() {
+  let buf = SharedBuf(Arc::new(Mutex::new(Vec::new())));
+
+  let (i1, i2) = channel::link(box |_| {}, box |_| {});
+  let writer = Writer::new(box buf.clone() as Box<Write + Send>, file::LINKTYPE_RAW).unwrap();
+  let captured = Interface::new(box i1, writer);
+  dl::Interface::update_recv_handler(&captured, box |_| {});
+
+  dl::Interface::send(&captured, vec![1, 2, 3]).unwrap();
+  dl::Interface::send(&i2, vec![4, 5]).unwrap();
+
+  let bytes = buf.0.lock().unwrap().clone();
+  assert_eq!(bytes.len(), file::GLOBAL_HDR_LEN
+                          + file::RECORD_HDR_LEN + 3
+                          + file::RECORD_HDR_LEN + 2);
+  assert_eq!(&bytes[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+  assert_eq!(&bytes[bytes.len() - 2..], &[4, 5]);
+}
+
+#[test]
+fn failed_sends_not_recorded() {
+  let buf = SharedBuf(Arc::new(Mutex::new(Vec::new())));
+
+  let (i1, i2) = channel::link(box |_| {}, box |_| {});
+  i2.set_carrier(false);
+  let writer = Writer::new(box buf.clone() as Box<Write + Send>, file::LINKTYPE_RAW).unwrap();
+  let mut captured = Interface::new(box i1, writer);
+
+  assert_eq!(dl::Interface::send(&captured, vec![1, 2, 3]),
+             Err(dl::Error::External(channel::Error::NoCarrier)));
+  i2.set_carrier(true);
+  dl::Interface::disable(&mut captured);
+  assert_eq!(dl::Interface::send(&captured, vec![1, 2, 3]), Err(dl::Error::Disabled));
+
+  assert_eq!(buf.0.lock().unwrap().len(), file::GLOBAL_HDR_LEN);
+}
@@ -0,0 +1,85 @@
+#![feature(box_syntax)]
+
+extern crate misc;
+extern crate interface as dl;
+extern crate pcap;
+
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use misc::SenderClosure;
+use pcap::file::{self, Reader, Writer};
+use pcap::replay::Interface;
+
+fn capture(linktype: u32, packets: &[&[u8]]) -> Vec<u8> {
+  let mut writer = Writer::new(Vec::new(), linktype).unwrap();
+  for (i, p) in packets.iter().enumerate() {
+    writer.write_packet(Duration::new(i as u64, 5000), p).unwrap();
+  }
+  writer.into_inner()
+}
+
+#[test]
+fn round_trip() {
+  let bytes = capture(file::LINKTYPE_RAW, &[&[1, 2, 3], &[]]);
+  let mut reader = Reader::new(&bytes[..]).unwrap();
+  assert_eq!(reader.linktype(), file::LINKTYPE_RAW);
+
+  let r = reader.next_record().unwrap().unwrap();
+  assert_eq!(r.data, vec![1, 2, 3]);
+  assert_eq!(r.orig_len, 3);
+  assert_eq!(r.timestamp, Duration::new(0, 5000));
+
+  let r = reader.next_record().unwrap().unwrap();
+  assert_eq!(r.data, vec![]);
+  assert_eq!(r.timestamp, Duration::new(1, 5000));
+
+  assert!(reader.next_record().unwrap().is_none());
+}
+
+#[test]
+fn truncated_record_is_error() {
+  let bytes = capture(file::LINKTYPE_RAW, &[&[1, 2, 3]]);
+  let mut reader = Reader::new(&bytes[..bytes.len() - 1]).unwrap();
+  assert!(reader.next_record().is_err());
+}
+
+#[test]
+fn replays_and_records() {
+  let bytes = capture(file::LINKTYPE_RAW, &[&[1], &[2]]);
+
+  let (tx, rx) = channel::<(dl::Packet,)>();
+  let i = Interface::from_reader(&bytes[..], box SenderClosure::new(tx)).unwrap();
+  let player = i.player();
+
+  assert_eq!(player.remaining(), 2);
+  assert!(player.step());
+  assert_eq!(rx.try_recv().unwrap().0, vec![1]);
+  assert!(rx.try_recv().is_err());
+
+  dl::Interface::send(&i, vec![9]).unwrap();
+
+  assert_eq!(player.play(), 1);
+  assert_eq!(rx.try_recv().unwrap().0, vec![2]);
+  assert_eq!(player.take_sent(), vec![vec![9]]);
+  assert!(player.sent().is_empty());
+}
+
+#[test]
+fn ethernet_is_unwrapped() {
+  let ipv4  = [0x08, 0x00];
+  let arp   = [0x08, 0x06];
+  let mut f1 = vec![0xff; 12];
+  f1.extend_from_slice(&ipv4);
+  f1.extend_from_slice(&[7, 7]);
+  let mut f2 = vec![0xff; 12];
+  f2.extend_from_slice(&arp);
+  f2.extend_from_slice(&[8, 8]);
+
+  let bytes = capture(file::LINKTYPE_ETHERNET, &[&f1[..], &f2[..]]);
+  let (tx, rx) = channel::<(dl::Packet,)>();
+  let i = Interface::from_reader(&bytes[..], box SenderClosure::new(tx)).unwrap();
+
+  assert_eq!(i.player().play(), 1);
+  assert_eq!(rx.try_recv().unwrap().0, vec![7, 7]);
+}
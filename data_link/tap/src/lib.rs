@@ -0,0 +1,410 @@
+#![feature(question_mark)]
+
+#[macro_use]
+extern crate log;
+extern crate libc;
+
+extern crate misc;
+extern crate interface as dl;
+
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+
+use misc::interface as root;
+use misc::pool::Pool;
+
+
+const RECV_BUF_SIZE: usize = 64 * 1024;
+
+/// The most frames a reader takes off the device before handing them over
+const MAX_BATCH: usize = 64;
+
+/// What the kernel gives new devices
+pub const DEFAULT_MTU: usize = 1500;
+const ETHERNET_HDR_LEN: usize = 14;
+
+/// How many spare buffers an interface keeps by default
+pub const DEFAULT_POOL_SIZE: usize = 64;
+
+// from <linux/if.h> and <linux/if_tun.h>
+const IFNAMSIZ:  usize         = 16;
+const IFF_TUN:   libc::c_short = 0x0001;
+const IFF_TAP:   libc::c_short = 0x0002;
+const IFF_NO_PI: libc::c_short = 0x1000;
+const TUNSETIFF: libc::c_ulong = 0x4004_54ca;
+
+type SharedHandler<'a> = Arc<RwLock<(bool, dl::BatchHandler<'a>)>>;
+type SharedPool = Arc<RwLock<Arc<Pool>>>;
+type SharedStatusHandler<'a> = Arc<RwLock<dl::StatusHandler<'a>>>;
+
+/// What kind of frames the device exchanges with the kernel
+#[derive(PartialEq, Eq,
+         Copy, Clone, Hash, Debug)]
+pub enum Mode {
+  /// Bare IP packets -- what the network layer expects
+  Tun,
+  /// Ethernet frames
+  Tap,
+}
+
+/// `struct ifreq`, with the union cut down to the flags we use
+#[repr(C)]
+struct IfReq {
+  name:  [u8; IFNAMSIZ],
+  flags: libc::c_short,
+  _pad:  [u8; 22],
+}
+
+/// Binds a freshly opened clone device to a tun/tap interface, returning the
+/// name the kernel settled on
+fn attach(device: &File, name: &str, mode: Mode) -> io::Result<String> {
+  let name = name.as_bytes();
+  // leave room for the nul terminator
+  if name.len() >= IFNAMSIZ {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                              "tun/tap interface name is too long"));
+  }
+
+  let mut req = IfReq {
+    name:  [0; IFNAMSIZ],
+    flags: IFF_NO_PI | match mode {
+      Mode::Tun => IFF_TUN,
+      Mode::Tap => IFF_TAP,
+    },
+    _pad:  [0; 22],
+  };
+  req.name[..name.len()].copy_from_slice(name);
+
+  if unsafe { libc::ioctl(device.as_raw_fd(), TUNSETIFF, &mut req as *mut IfReq) } < 0 {
+    return Err(io::Error::last_os_error());
+  }
+
+  let len = req.name.iter().position(|&b| b == 0).unwrap_or(IFNAMSIZ);
+  Ok(String::from_utf8_lossy(&req.name[..len]).into_owned())
+}
+
+/// Makes reads fail with `WouldBlock` rather than wait, for every handle on the
+/// device
+fn set_nonblocking(device: &File) -> io::Result<()> {
+  let fd = device.as_raw_fd();
+  let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+  if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+/// The read and write ends of a new pipe
+fn pipe() -> io::Result<(File, File)> {
+  let mut fds = [0; 2];
+  if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+/// Waits until the device has a frame to read, or until something is written
+/// to `stop`, in which case `false`
+fn wait_readable(device: &File, stop: &File) -> io::Result<bool> {
+  let mut fds = [
+    libc::pollfd {
+      fd:      device.as_raw_fd(),
+      events:  libc::POLLIN,
+      revents: 0,
+    },
+    libc::pollfd {
+      fd:      stop.as_raw_fd(),
+      events:  libc::POLLIN,
+      revents: 0,
+    },
+  ];
+  if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(fds[1].revents == 0)
+}
+
+/// Takes whatever frames are waiting, up to `MAX_BATCH`, off the non-blocking
+/// device
+fn drain(mut device: &File, buf: &mut [u8], pool: &SharedPool) -> io::Result<Vec<dl::Packet>> {
+  let mut frames = Vec::new();
+  while frames.len() < MAX_BATCH {
+    // each read yields exactly one frame
+    match device.read(buf) {
+      // empty, or another reader beat us to it
+      Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e),
+      Ok(len) => {
+        let mut frame = pool.read().unwrap().take();
+        frame.extend_from_slice(&buf[..len]);
+        frames.push(frame);
+      },
+    }
+  }
+  Ok(frames)
+}
+
+
+// which reader, if any, the current thread is: the address of its `Readers`'
+// stop pipe, and its index among them
+thread_local!(static CURRENT_READER: Cell<Option<(usize, usize)>> = Cell::new(None));
+
+/// The read loop threads, which are stopped and joined with the interface
+struct Readers {
+  // never read, so once written to it wakes every reader for good
+  stop:    File,
+  threads: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Readers {
+  /// If dropped from one of the readers, e.g. as a handler drops the
+  /// interface, that reader is left to notice the stop pipe by itself
+  fn drop(&mut self) {
+    if let Err(e) = (&self.stop).write(&[0]) {
+      debug!("could not tell tap readers to stop, leaving them be: {}", e);
+      return;
+    }
+    let id = &self.stop as *const File as usize;
+    let current = CURRENT_READER.with(|r| r.get());
+    for (n, thread) in self.threads.drain(..).enumerate() {
+      if current == Some((id, n)) {
+        continue;
+      }
+      if thread.join().is_err() {
+        debug!("tap reader thread had panicked");
+      }
+    }
+  }
+}
+
+/// A link layer interface backed by a Linux TUN/TAP device. Dropping it
+/// stops and joins its readers.
+pub struct Interface<'a> {
+  device:        File,
+  name:          String,
+  mtu:           usize,
+  handler:       SharedHandler<'a>,
+  pool:          SharedPool,
+  on_status:     SharedStatusHandler<'a>,
+  dropped:       Arc<AtomicUsize>,
+  // only here to be dropped with the interface
+  _readers:      Box<Readers>,
+  cached_status: bool,
+}
+
+impl Interface<'static> {
+  /// Attaches to the device called `name`, creating it if need be. `name` may
+  /// also be a pattern like "tun%d", in which case the kernel picks.
+  ///
+  /// Creating devices requires CAP_NET_ADMIN.
+  pub fn new(name:        &str,
+             mode:        Mode,
+             num_threads: usize,
+             on_recv:     dl::Handler<'static>)
+             -> io::Result<Interface<'static>>
+  {
+    assert!(num_threads > 0);
+
+    let device = OpenOptions::new().read(true).write(true).open("/dev/net/tun")?;
+    let name   = attach(&device, name, mode)?;
+    debug!("attached to tun/tap device {}", name);
+    // readers wait with `poll`, and then drain the device without blocking
+    set_nonblocking(&device)?;
+
+    let handler: SharedHandler = Arc::new(RwLock::new((true, dl::unbatch(on_recv))));
+    let pool:    SharedPool    = Arc::new(RwLock::new(Arc::new(
+      Pool::new(DEFAULT_MTU + ETHERNET_HDR_LEN, DEFAULT_POOL_SIZE))));
+    let on_status: SharedStatusHandler = Arc::new(RwLock::new(Box::new(|_: bool| ())));
+    let dropped = Arc::new(AtomicUsize::new(0));
+    // so that only the first reader to fail says so
+    let link_up = Arc::new(AtomicBool::new(true));
+    let (stop_rx, stop_tx) = pipe()?;
+    // boxed, so the write end's address identifies the readers
+    let mut readers = Box::new(Readers {
+      stop:    stop_tx,
+      threads: Vec::with_capacity(num_threads),
+    });
+    let id = &readers.stop as *const File as usize;
+
+    for n in 0..num_threads {
+      let device     = device.try_clone()?;
+      let stop       = stop_rx.try_clone()?;
+      let handler    = handler.clone();
+      let pool       = pool.clone();
+      let on_status  = on_status.clone();
+      let dropped    = dropped.clone();
+      let link_up    = link_up.clone();
+      readers.threads.push(thread::spawn(move || {
+        CURRENT_READER.with(|r| r.set(Some((id, n))));
+        let mut buf: [u8; RECV_BUF_SIZE] = unsafe { std::mem::uninitialized() };
+        loop {
+          let frames = match wait_readable(&device, &stop) {
+            Ok(false) => break,
+            Ok(true)  => drain(&device, &mut buf[..], &pool),
+            Err(e)    => Err(e),
+          };
+          let frames = match frames {
+            Ok(frames) => frames,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+              // unlike a socket, errors here mean the device is gone
+              debug!("OS error when trying to read frame, stopping reader: {}", e);
+              if link_up.swap(false, Ordering::SeqCst) {
+                (**on_status.read().unwrap())(false);
+              }
+              break;
+            },
+          };
+          if frames.is_empty() {
+            continue;
+          }
+          match *handler.read().unwrap() {
+            (true, ref on_recv) => {
+              debug!("Received {} frames", frames.len());
+              (**on_recv)(frames);
+            },
+            (false, _) => {
+              debug!("Tap interface is not enabled, dropping {} frames", frames.len());
+              dropped.fetch_add(frames.len(), Ordering::Relaxed);
+              let pool = pool.read().unwrap();
+              for frame in frames {
+                pool.give(frame);
+              }
+            },
+          }
+        }
+      }));
+    }
+
+    Ok(Interface {
+      device:        device,
+      name:          name,
+      mtu:           match mode {
+        Mode::Tun => DEFAULT_MTU,
+        // frames carry their Ethernet header on top
+        Mode::Tap => DEFAULT_MTU + ETHERNET_HDR_LEN,
+      },
+      handler:       handler,
+      pool:          pool,
+      on_status:     on_status,
+      dropped:       dropped,
+      _readers:      readers,
+      cached_status: true,
+    })
+  }
+}
+
+impl<'a> Interface<'a> {
+  /// The name of the kernel's side of the device, e.g. "tun0"
+  pub fn name(&self) -> &str {
+    &self.name[..]
+  }
+
+  /// Should match what the kernel's side of the device is configured with
+  pub fn set_mtu(&mut self, mtu: usize) {
+    self.mtu = mtu;
+  }
+}
+
+impl<'a> root::Interface for Interface<'a> {
+  type Error = io::Error;
+}
+
+impl<'a> dl::Interface<'a> for Interface<'a> {
+  fn send(&self, packet: dl::Packet) -> dl::Result<(), Self::Error> {
+    if self.cached_status == false {
+      Err(dl::Error::Disabled)?;
+    }
+    let sent = (&self.device).write(&packet[..])?;
+    if sent != packet.len() {
+      return Err(From::from(io::Error::new(
+        io::ErrorKind::WriteZero,
+        "The frame could not be written in whole")));
+    } else {
+      self.pool.read().unwrap().give(packet);
+      Ok(())
+    }
+  }
+
+  fn stock(&self, pool: Arc<Pool>) {
+    *self.pool.write().unwrap() = pool;
+  }
+
+  fn mtu(&self) -> usize {
+    self.mtu
+  }
+
+  fn update_recv_handler<'b>(&'b self, on_recv: dl::Handler<'a>)
+    where 'a: 'b
+  {
+    self.handler.write().unwrap().1 = dl::unbatch(on_recv);
+  }
+
+  /// Whatever a reader finds waiting on the device is handed over at once
+  fn update_recv_batch_handler<'b>(&'b self, on_recv: dl::BatchHandler<'a>)
+    where 'a: 'b
+  {
+    self.handler.write().unwrap().1 = on_recv;
+  }
+
+  fn update_status_handler<'b>(&'b self, on_status: dl::StatusHandler<'a>)
+    where 'a: 'b
+  {
+    *self.on_status.write().unwrap() = on_status;
+  }
+
+  fn dropped_disabled(&self) -> usize {
+    self.dropped.load(Ordering::Relaxed)
+  }
+
+  fn enable(&mut self) {
+    self.cached_status = true;
+    self.handler.write().unwrap().0 = true;
+  }
+
+  fn disable(&mut self) {
+    self.cached_status = false;
+    self.handler.write().unwrap().0 = false;
+  }
+
+  fn get_status(&self) -> bool {
+    self.cached_status
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::fs::File;
+  use std::io;
+  use std::mem::size_of;
+
+  use super::{attach, IfReq, Mode, IFNAMSIZ};
+
+  #[test]
+  fn name_too_long() {
+    // the check comes before the device is touched
+    let device = File::open("/dev/null").unwrap();
+    let name: String = (0..IFNAMSIZ).map(|_| 'x').collect();
+    let err = attach(&device, &name[..], Mode::Tap).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+  }
+
+  #[test]
+  fn if_req_layout() {
+    // as `struct ifreq` on Linux
+    assert_eq!(size_of::<IfReq>(), 40);
+    let req = IfReq { name: [0; IFNAMSIZ], flags: 0, _pad: [0; 22] };
+    let start = &req as *const IfReq as usize;
+    assert_eq!(&req.name  as *const _ as usize - start, 0);
+    assert_eq!(&req.flags as *const _ as usize - start, IFNAMSIZ);
+  }
+}
@@ -2,10 +2,12 @@
 
 #[macro_use]
 extern crate log;
+extern crate libc;
 
 extern crate misc;
 extern crate interface as dl;
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::io;
@@ -16,70 +18,232 @@ use std::net::{
   Ipv4Addr,
   ToSocketAddrs,
 };
-use std::sync::Arc;
-use std::sync::RwLock;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
+use std::thread::JoinHandle;
 
 use misc::interface as root;
+use misc::pool::Pool;
 
 
 const RECV_BUF_SIZE: usize = 64 * 1024;
 
+/// How long a reader may take to notice it should stop
+const STOP_POLL_PERIOD_MS: u64 = 100;
+
+/// The most datagrams a reader takes off the socket before handing them over
+const MAX_BATCH: usize = 64;
+
+/// Keeps datagrams, IP header included, clear of fragmentation on a typical
+/// Ethernet path
+pub const DEFAULT_MTU: usize = 1400;
+
+/// How many spare buffers a listener keeps by default
+pub const DEFAULT_POOL_SIZE: usize = 64;
+
+// the counter is of packets dropped while disabled
 type SharedHandlerMap<'a> = Arc<RwLock<HashMap<SocketAddr,
-                                               (bool, dl::Handler<'a>)>>>;
+                                               (bool,
+                                                dl::BatchHandler<'a>,
+                                                Arc<AtomicUsize>)>>>;
+
+type SharedPool = Arc<RwLock<Arc<Pool>>>;
+
+/// Waits up to `timeout_ms`, or forever if negative, for `events` on the
+/// socket
+fn wait_for(socket:     &UdpSocket,
+            events:     libc::c_short,
+            timeout_ms: libc::c_int)
+            -> io::Result<bool>
+{
+  let mut fd = libc::pollfd {
+    fd:      socket.as_raw_fd(),
+    events:  events,
+    revents: 0,
+  };
+  match unsafe { libc::poll(&mut fd, 1, timeout_ms) } {
+    -1 => Err(io::Error::last_os_error()),
+    0  => Ok(false),
+    _  => Ok(true),
+  }
+}
+
+/// Waits up to `timeout_ms` for the socket to have something to read
+fn wait_readable(socket: &UdpSocket, timeout_ms: u64) -> io::Result<bool> {
+  wait_for(socket, libc::POLLIN, timeout_ms as libc::c_int)
+}
+
+/// Waits for room in the socket's send buffer, for as long as it takes
+fn wait_writable(socket: &UdpSocket) -> io::Result<()> {
+  wait_for(socket, libc::POLLOUT, -1).map(|_| ())
+}
+
+/// Takes whatever datagrams are waiting, up to `MAX_BATCH`, off the
+/// non-blocking socket, grouped by who sent them
+fn drain(socket: &UdpSocket,
+         buf:    &mut [u8],
+         pool:   &SharedPool)
+         -> HashMap<SocketAddr, Vec<dl::Packet>>
+{
+  let mut batches = HashMap::new();
+  for _ in 0..MAX_BATCH {
+    match socket.recv_from(buf) {
+      // empty, or another reader beat us to it
+      Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => {
+        // maybe it will work next time...
+        debug!("OS error when trying to receive packet: {}", e);
+        break;
+      },
+      Ok((len, src_addr)) => {
+        let mut packet = pool.read().unwrap().take();
+        packet.extend_from_slice(&buf[..len]);
+        batches.entry(src_addr).or_insert_with(Vec::new).push(packet);
+      },
+    }
+  }
+  batches
+}
+
+/// Hands each sender's packets to its interface's handler in one go
+fn deliver<'a>(handlers: &SharedHandlerMap<'a>,
+               pool:     &SharedPool,
+               batches:  HashMap<SocketAddr, Vec<dl::Packet>>)
+{
+  let handlers = handlers.read().unwrap();
+  for (src_addr, packets) in batches {
+    match handlers.get(&src_addr) {
+      Some(&(true, ref on_recv, _)) => {
+        debug!("Received {} packets", packets.len());
+        (**on_recv)(packets);
+        continue;
+      },
+      Some(&(false, _, ref dropped)) => {
+        debug!("Virtual Interface is not enabled, dropping {} packets", packets.len());
+        dropped.fetch_add(packets.len(), Ordering::Relaxed);
+      },
+      None => (), // drop those packets!
+    }
+    let pool = pool.read().unwrap();
+    for packet in packets {
+      pool.give(packet);
+    }
+  }
+}
+
+// which reader, if any, the current thread is: the address of its `Readers`'
+// stop flag, and its index among them
+thread_local!(static CURRENT_READER: Cell<Option<(usize, usize)>> = Cell::new(None));
+
+/// The read loop threads, which are stopped and joined once every listener
+/// and interface sharing them is gone
+struct Readers {
+  stop:    Arc<AtomicBool>,
+  threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Readers {
+  fn id(&self) -> usize {
+    &*self.stop as *const AtomicBool as usize
+  }
+
+  /// If called from one of the readers, e.g. as a handler drops the last
+  /// interface, that reader is left to notice the stop flag by itself
+  fn stop(&self) {
+    self.stop.store(true, Ordering::SeqCst);
+    let current = CURRENT_READER.with(|r| r.get());
+    for (n, thread) in self.threads.lock().unwrap().drain(..).enumerate() {
+      if current == Some((self.id(), n)) {
+        continue;
+      }
+      if thread.join().is_err() {
+        debug!("udp mock reader thread had panicked");
+      }
+    }
+  }
+}
+
+impl Drop for Readers {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
 
 /// The backing listening socket / read loop for a bunch of UDP-backed mock link
 /// neighbors
 pub struct Listener<'a> {
   socket:   UdpSocket,
   handlers: SharedHandlerMap<'a>,
+  pool:     SharedPool,
+  readers:  Arc<Readers>,
 }
 
 impl Listener<'static>
 {
   pub fn new<A>(listen_addr: A, num_threads: usize) -> io::Result<Listener<'static>>
     where A: ToSocketAddrs
+  {
+    let pool = Arc::new(Pool::new(DEFAULT_MTU, DEFAULT_POOL_SIZE));
+    Listener::with_pool(listen_addr, num_threads, pool)
+  }
+
+  /// Like `new`, but received packets are put in buffers from the given pool
+  pub fn with_pool<A>(listen_addr: A,
+                      num_threads: usize,
+                      pool:        Arc<Pool>)
+                      -> io::Result<Listener<'static>>
+    where A: ToSocketAddrs
   {
     assert!(num_threads > 0);
 
     let socket = UdpSocket::bind(listen_addr)?;
+    // readers wait with `poll`, so that they get to check whether they should
+    // stop, and then drain the socket without blocking. This holds for every
+    // clone, so sends wait for room themselves.
+    socket.set_nonblocking(true)?;
 
     let handlers: SharedHandlerMap = Arc::new(RwLock::new(HashMap::new()));
+    let pool:     SharedPool       = Arc::new(RwLock::new(pool));
+    let stop = Arc::new(AtomicBool::new(false));
+    let id   = &*stop as *const AtomicBool as usize;
+    let mut threads = Vec::with_capacity(num_threads);
 
-    for _ in 0..num_threads {
+    for n in 0..num_threads {
       let socket   = socket.try_clone()?;
       let handlers = handlers.clone();
-      thread::spawn(move || {
+      let pool     = pool.clone();
+      let stop     = stop.clone();
+      threads.push(thread::spawn(move || {
+        CURRENT_READER.with(|r| r.set(Some((id, n))));
         let mut buf: [u8; RECV_BUF_SIZE] = unsafe { std::mem::uninitialized() };
-        loop {
-          // TODO: someway to kill task eventually
-          match socket.recv_from(&mut buf[..]) {
+        while !stop.load(Ordering::SeqCst) {
+          match wait_readable(&socket, STOP_POLL_PERIOD_MS) {
+            Ok(true)  => (),
+            Ok(false) => continue,
             Err(e) => {
               // maybe it will work next time...
               debug!("OS error when trying to wait for packet: {}", e);
+              continue;
             },
-            Ok((len, src_addr)) => match handlers.read().unwrap().get(&src_addr) {
-              None          => continue, // drop that packet!
-              Some(&(is_enabled, ref on_recv)) => {
-                debug!("Received packet");
-                if is_enabled {
-                  debug!("Virtual Interface is enabled");
-                  let args = buf[..len].to_vec();
-                  (**on_recv)(args);
-                } else {
-                  debug!("Virtual Interface is not enabled, dropping packet");
-                }
-              },
-            }
-          };
-          // )
+          }
+          let batches = drain(&socket, &mut buf[..], &pool);
+          deliver(&handlers, &pool, batches);
         }
-      });
+      }));
     }
 
     Ok(Listener {
       socket:   socket,
       handlers: handlers,
+      pool:     pool,
+      readers:  Arc::new(Readers {
+        stop:    stop,
+        threads: Mutex::new(threads),
+      }),
     })
   }
 
@@ -101,8 +265,26 @@ impl<'a> Listener<'a>
     Ok(Listener {
       socket: self.socket.try_clone()?,
       handlers: self.handlers.clone(),
+      pool: self.pool.clone(),
+      readers: self.readers.clone(),
     })
   }
+
+  /// Stops and joins the reader threads, and forgets every interface's
+  /// handler. Afterwards nothing more is received, though interfaces can
+  /// still send.
+  ///
+  /// The handlers often own the interfaces (e.g. through the IP layer's
+  /// state), which keep the readers going, so this is needed to free
+  /// everything. It must not be called from a handler, as it takes the
+  /// handler table's write lock.
+  pub fn shutdown(&self) {
+    self.readers.stop();
+    // drop the handlers only once the lock is released, as dropping them may
+    // drop interfaces in turn
+    let handlers = mem::replace(&mut *self.handlers.write().unwrap(), HashMap::new());
+    drop(handlers);
+  }
 }
 
 
@@ -110,6 +292,11 @@ impl<'a> Listener<'a>
 pub struct Interface<'a> {
   listener:    Listener<'a>,
   remote_addr: SocketAddr,
+  mtu:         usize,
+  dropped:     Arc<AtomicUsize>,
+  /// Whether the last send got through, which is all we know of the link
+  link_up:     AtomicBool,
+  on_status:   RwLock<dl::StatusHandler<'a>>,
   cached_status: bool,
 }
 
@@ -119,14 +306,44 @@ impl<'a> Interface<'a> {
              remote_addr: SocketAddr,
              on_recv:     dl::Handler<'a>) -> Interface<'a>
   {
-    listener.handlers.write().unwrap().insert(remote_addr, (true, on_recv));
+    let dropped = Arc::new(AtomicUsize::new(0));
+    listener.handlers.write().unwrap()
+      .insert(remote_addr, (true, dl::unbatch(on_recv), dropped.clone()));
 
     Interface {
       listener:      listener.try_clone().unwrap(),
       remote_addr:   remote_addr,
+      mtu:           DEFAULT_MTU,
+      dropped:       dropped,
+      link_up:       AtomicBool::new(true),
+      on_status:     RwLock::new(Box::new(|_: bool| ())),
       cached_status: true,
     }
   }
+
+  pub fn set_mtu(&mut self, mtu: usize) {
+    self.mtu = mtu;
+  }
+
+  /// Whether received packets are handed over, or counted and dropped
+  fn set_receiving(&self, enabled: bool) {
+    let mut map = self.listener.handlers.write().unwrap();
+    match map.entry(self.remote_addr) {
+      // the listener was shut down
+      Vacant(_) => debug!("udp mock interface has no entry in table, nothing to receive"),
+      Occupied(mut entry) => {
+        entry.get_mut().0 = enabled;
+      },
+    }
+  }
+
+  /// Tells the status handler if this differs from the last send
+  fn set_link_up(&self, up: bool) {
+    if self.link_up.swap(up, Ordering::SeqCst) != up {
+      debug!("udp mock link to {} is now {}", self.remote_addr, if up { "up" } else { "down" });
+      (**self.on_status.read().unwrap())(up);
+    }
+  }
 }
 
 impl<'a> root::Interface for Interface<'a> {
@@ -138,45 +355,90 @@ impl<'a> dl::Interface<'a> for Interface<'a> {
     if self.cached_status == false {
       Err(dl::Error::Disabled)?;
     }
-    let sent = self.listener
-      .socket.try_clone()?
-      .send_to(&packet[..], self.remote_addr)?;
+    if packet.len() > self.mtu {
+      return Err(From::from(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The packet is larger than the MTU")));
+    }
+    let sent;
+    loop {
+      match self.listener.socket.send_to(&packet[..], self.remote_addr) {
+        Ok(n) => {
+          self.set_link_up(true);
+          sent = n;
+          break;
+        },
+        // the socket is non-blocking, and its send buffer is full, so block
+        // as a blocking socket would
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+          wait_writable(&self.listener.socket)?;
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+        Err(e) => {
+          // e.g. the remote end's port is closed
+          self.set_link_up(false);
+          return Err(From::from(e));
+        },
+      }
+    }
     if sent != packet.len() {
       return Err(From::from(io::Error::new(
         io::ErrorKind::WriteZero,
         "The packet could not be sent in whole")));
     } else {
+      self.listener.pool.read().unwrap().give(packet);
       Ok(())
     }
   }
 
+  /// Note this changes the pool for every interface on the same listener
+  fn stock(&self, pool: Arc<Pool>) {
+    *self.listener.pool.write().unwrap() = pool;
+  }
+
+  fn mtu(&self) -> usize {
+    self.mtu
+  }
+
   fn update_recv_handler<'b>(&'b self, on_recv: dl::Handler<'a>)
     where 'a: 'b
   {
-    self.listener.handlers.write().unwrap()
-      .insert(self.remote_addr, (true, on_recv));
+    self.update_recv_batch_handler(dl::unbatch(on_recv));
   }
 
-  fn enable(&mut self) {
+  /// Whatever a reader finds waiting on the socket from the neighbor is handed
+  /// over at once. A disabled interface stays so.
+  fn update_recv_batch_handler<'b>(&'b self, on_recv: dl::BatchHandler<'a>)
+    where 'a: 'b
+  {
     let mut map = self.listener.handlers.write().unwrap();
-    self.cached_status = true;
     match map.entry(self.remote_addr) {
-      Vacant(_) => panic!("udp mock interface should already have entry in table"),
-      Occupied(mut entry) => {
-        entry.get_mut().0 = true;
+      Occupied(mut entry) => entry.get_mut().1 = on_recv,
+      // the listener was shut down
+      Vacant(entry)       => {
+        entry.insert((self.cached_status, on_recv, self.dropped.clone()));
       },
     }
   }
 
+  fn update_status_handler<'b>(&'b self, on_status: dl::StatusHandler<'a>)
+    where 'a: 'b
+  {
+    *self.on_status.write().unwrap() = on_status;
+  }
+
+  fn dropped_disabled(&self) -> usize {
+    self.dropped.load(Ordering::Relaxed)
+  }
+
+  fn enable(&mut self) {
+    self.cached_status = true;
+    self.set_receiving(true);
+  }
+
   fn disable(&mut self) {
-    let mut map = self.listener.handlers.write().unwrap();
     self.cached_status = false;
-    match map.entry(self.remote_addr) {
-      Vacant(_) => panic!("udp mock interface should already have entry in table"),
-      Occupied(mut entry) => {
-        entry.get_mut().0 = false;
-      },
-    }
+    self.set_receiving(false);
   }
 
   fn get_status(&self) -> bool {
@@ -9,6 +9,7 @@ extern crate interface as dl;
 extern crate udp_mock;
 
 use std::io;
+use std::net::UdpSocket;
 
 use std::sync::{Arc, Barrier};
 use std::str::from_utf8;
@@ -110,3 +111,114 @@ fn disable_then_cant_send() {
   }
   inner().unwrap();
 }
+
+#[test]
+fn drop_releases_socket() {
+  let (l, a) = Listener::new_loopback(4).unwrap();
+  let i = Interface::new(&l, a, box |_| {});
+  drop(l);
+  drop(i);
+  // the readers have all been joined, so nobody holds the port
+  UdpSocket::bind(a).unwrap();
+}
+
+#[test]
+fn interface_outlives_listener() {
+  use std::sync::mpsc::*;
+
+  let (l, a) = Listener::new_loopback(1).unwrap();
+  let (tx, rx) = channel::<(dl::Packet,)>();
+  let i = Interface::new(&l, a, box SenderClosure::new(tx));
+  drop(l);
+
+  // the interface keeps the readers going
+  dl::Interface::send(&i, vec![1]).unwrap();
+  let (packet,) = rx.recv().unwrap();
+  assert_eq!(packet, vec![1]);
+
+  drop(i);
+  UdpSocket::bind(a).unwrap();
+}
+
+#[test]
+fn shutdown_frees_interface_in_handler() {
+  let (l, a) = Listener::new_loopback(4).unwrap();
+  let i = Arc::new(Interface::new(&l, a, box |_| {}));
+  // the handler owns the interface, as the IP layer's would
+  let owned = i.clone();
+  dl::Interface::update_recv_handler(&*i, box move |_| { let _ = &owned; });
+  drop(i);
+  // which keeps the readers going until the cycle is broken
+  l.shutdown();
+  drop(l);
+  UdpSocket::bind(a).unwrap();
+}
+
+#[test]
+fn shutdown_then_no_receive() {
+  use std::sync::mpsc::*;
+
+  let (l, a) = Listener::new_loopback(1).unwrap();
+  let (tx, rx) = channel::<(dl::Packet,)>();
+  let mut i = Interface::new(&l, a, box SenderClosure::new(tx));
+
+  l.shutdown();
+  // the handler, and so the sender, is gone
+  assert!(rx.recv().is_err());
+
+  // sending still works, it just goes nowhere
+  dl::Interface::send(&i, vec![1]).unwrap();
+  // no entry to toggle anymore
+  dl::Interface::disable(&mut i);
+  dl::Interface::enable(&mut i);
+}
+
+#[test]
+fn batch_handler_gets_everything() {
+  use std::sync::mpsc::*;
+
+  let (l1, a1) = Listener::new_loopback(1).unwrap();
+  let (l2, a2) = Listener::new_loopback(1).unwrap();
+
+  let (tx, rx) = channel::<(Vec<dl::Packet>,)>();
+
+  let interface1 = Interface::new(&l1, a2, box |_| {});
+  let interface2 = Interface::new(&l2, a1, box |_| {});
+  dl::Interface::update_recv_batch_handler(&interface2, box SenderClosure::new(tx));
+
+  for i in 0..10 {
+    dl::Interface::send(&interface1, vec![i]).unwrap();
+  }
+
+  // however the reader happened to split them up, they come in order
+  let mut received = Vec::new();
+  while received.len() < 10 {
+    let (packets,) = rx.recv().unwrap();
+    assert!(!packets.is_empty());
+    received.extend(packets);
+  }
+  assert_eq!(received, (0..10).map(|i| vec![i]).collect::<Vec<_>>());
+}
+
+#[test]
+fn new_handler_keeps_disabled() {
+  use std::sync::mpsc::*;
+  use std::thread;
+  use std::time::Duration;
+
+  let (l1, a1) = Listener::new_loopback(1).unwrap();
+  let (l2, a2) = Listener::new_loopback(1).unwrap();
+
+  let (tx, rx) = channel::<(Vec<dl::Packet>,)>();
+
+  let interface1 = Interface::new(&l1, a2, box |_| {});
+  let mut interface2 = Interface::new(&l2, a1, box |_| {});
+  dl::Interface::disable(&mut interface2);
+  dl::Interface::update_recv_batch_handler(&interface2, box SenderClosure::new(tx));
+
+  dl::Interface::send(&interface1, vec![1]).unwrap();
+  while dl::Interface::dropped_disabled(&interface2) == 0 {
+    thread::sleep(Duration::from_millis(10));
+  }
+  assert!(rx.try_recv().is_err());
+}
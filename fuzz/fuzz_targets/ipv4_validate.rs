@@ -0,0 +1,39 @@
+//! Whatever comes in off the wire must be rejected or accepted, never panic
+//! on. Then the input is also used as the body of a packet, which must come
+//! back out as it went in.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate network;
+
+use network::ipv4::Addr;
+use network::ipv4::packet::{self, Ipv4Packet};
+
+fuzz_target!(|data: &[u8]| {
+  if let Ok(view) = Ipv4Packet::new_checked(data) {
+    view.payload();
+    view.options().count();
+    view.is_checksum_valid();
+  }
+
+  if packet::validate(data).is_ok() {
+    // everything after validation trusts the packet
+    let p = packet::A::new(data);
+    p.get_payload();
+    p.options().count();
+    format!("{}", p);
+    packet::fragment(p, 68);
+  }
+
+  if data.is_empty() || data.len() > 0xffff - packet::MIN_HDR_LEN_8S as usize {
+    return;
+  }
+  let (_, built) = packet::V::new_with_builder(Addr([10, 0, 0, 1]), 17, None, |p| {
+    p.as_mut_vec().extend_from_slice(data);
+    Ok::<(), ()>(())
+  }).unwrap();
+  packet::validate(built.borrow().as_slice()).unwrap();
+  assert_eq!(built.borrow().get_payload(), data);
+});
@@ -0,0 +1,29 @@
+//! RIP packets come straight from neighbors, who may be lying or broken.
+//! Whatever parses as a response must also survive being written back out
+//! and parsed again.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate brown_rip;
+
+use brown_rip::packet::{self, Entry, Packet};
+
+fuzz_target!(|data: &[u8]| {
+  let entries: Vec<Entry> = match packet::parse(data) {
+    Ok(Packet::Response(entries)) => entries.collect(),
+    _                             => return,
+  };
+  // empty responses are never written, and the limit splits long ones
+  if entries.is_empty() || entries.len() > 64 {
+    return;
+  }
+
+  let mut buf = Vec::new();
+  packet::write_response(&mut entries.iter().cloned())(&mut buf).unwrap();
+  match packet::parse(&buf[..]) {
+    Ok(Packet::Response(again)) => assert_eq!(again.collect::<Vec<Entry>>(), entries),
+    _                           => panic!("wrote a response which does not parse"),
+  }
+});
@@ -0,0 +1,50 @@
+//! TCP only ever sees what IP has validated, so the input is wrapped in a
+//! valid IP packet first. Arbitrary segments must be rejected or accepted,
+//! never panicked on.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate network;
+extern crate tcp;
+
+use network::ipv4::Addr;
+use network::ipv4::packet::{self, IpOption};
+use tcp::packet::TcpPacket;
+
+fuzz_target!(|data: &[u8]| {
+  // the first byte says how much room IP options take, which TCP must not
+  // mistake for part of the segment
+  let (slots, segment) = match data.split_first() {
+    Some((&slots, segment)) => (slots as usize, segment),
+    None                    => return,
+  };
+  let slots = slots % (packet::MAX_RECORD_ROUTE_SLOTS + 1);
+  if segment.is_empty() && slots == 0 {
+    return;
+  }
+  if segment.len() > 0xffff - 60 {
+    return;
+  }
+
+  let (_, ip) = packet::V::new_with_builder(Addr([10, 0, 0, 2]), tcp::PROTOCOL, None, |p| {
+    p.borrow_mut().set_source(Addr([10, 0, 0, 1]));
+    p.as_mut_vec().extend_from_slice(segment);
+    if slots > 0 {
+      p.set_options(&[IpOption::record_route(slots).unwrap()])
+    } else {
+      Ok(())
+    }
+  }).unwrap();
+  packet::validate(ip.borrow().as_slice()).unwrap();
+
+  if let Ok(segment) = TcpPacket::validate(ip) {
+    segment.get_src_port();
+    segment.get_dst_port();
+    segment.get_seq_num();
+    segment.get_ack_num();
+    segment.get_window_size();
+    segment.get_payload();
+  }
+});
@@ -0,0 +1,86 @@
+//! Pings a virtual IP from a node, to check it can be reached.
+//!
+//! ```text
+//! ping <node.lnx> <virtual IP> [-c count] [-s size] [-i interval ms] [-W timeout ms] [-t ttl]
+//!      [-r routes]
+//! ```
+//!
+//! This becomes the node described, so the node must not already be running,
+//! though its neighbors must be. Only neighbors and what they route to can be
+//! reached, unless a file of static routes is given.
+
+extern crate lnx;
+extern crate network;
+extern crate static_routing;
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use lnx::cli::{self, Usage};
+use network::ipv4;
+use network::ipv4::ping::{self, Outcome};
+use static_routing::StaticTable;
+
+const USAGE: Usage = Usage(
+  "usage: ping <node.lnx> <virtual IP> [-c count] [-s size] [-i interval ms] [-W timeout ms] [-t ttl] [-r routes]");
+
+fn millis(d: Duration) -> f64 {
+  ping::to_nanos(d) as f64 / 1_000_000.0
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.len() < 2 {
+    USAGE.fail();
+  }
+  let dst: ipv4::Addr = USAGE.parse("virtual IP", args.get(1));
+
+  let mut options = ping::Options::default();
+  let mut routes = None;
+  let mut flags = args[2..].iter();
+  while let Some(flag) = flags.next() {
+    match &flag[..] {
+      "-c" => options.count    = USAGE.parse("count", flags.next()),
+      "-s" => options.size     = USAGE.parse("size", flags.next()),
+      "-i" => options.interval = Duration::from_millis(USAGE.parse("interval", flags.next())),
+      "-W" => options.timeout  = Duration::from_millis(USAGE.parse("timeout", flags.next())),
+      "-t" => options.ttl      = USAGE.parse("ttl", flags.next()),
+      "-r" => routes           = Some(USAGE.parse::<String>("routes", flags.next())),
+      _    => USAGE.fail(),
+    }
+  }
+
+  let node = lnx::Node::from_file(&args[0]).unwrap_or_else(|e| cli::fail(&format!("{}: {}", args[0], e)));
+  let (listener, state) = node.build::<StaticTable>(1).unwrap_or_else(|e| cli::fail(&format!("{}", e)));
+  if let Some(path) = routes {
+    if let Err(e) = state.routes.load(&path) {
+      listener.shutdown();
+      cli::fail(&format!("{}: {}", path, e));
+    }
+  }
+
+  println!("PING {} {} bytes of data.", dst, options.size);
+  let stats = ping::ping(&*state, dst, &options, |seq, outcome| match *outcome {
+    Outcome::Reply { from, bytes, ttl, rtt } =>
+      println!("{} bytes from {}: icmp_seq={} ttl={} time={:.3} ms",
+               bytes, from, seq, ttl, millis(rtt)),
+    Outcome::Error { from, kind, code, .. } =>
+      println!("From {} icmp_seq={} type {} code {}", from, seq, kind, code),
+    Outcome::Timeout =>
+      println!("no reply for icmp_seq={}", seq),
+    Outcome::NotSent(ref e) =>
+      println!("could not send icmp_seq={}: {:?}", seq, e),
+  });
+
+  println!("--- {} ping statistics ---", dst);
+  println!("{} packets transmitted, {} received, {} errors, {}% packet loss",
+           stats.transmitted, stats.received, stats.errors, stats.loss());
+  if stats.received > 0 {
+    println!("rtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+             millis(stats.min), millis(stats.avg), millis(stats.max), millis(stats.mdev));
+  }
+
+  listener.shutdown();
+  process::exit(if stats.received > 0 { 0 } else { 1 });
+}
@@ -0,0 +1,78 @@
+//! Prints the path from a node to a virtual IP, hop by hop.
+//!
+//! ```text
+//! traceroute <node.lnx> <virtual IP> [-m max hops] [-q probes] [-W timeout ms]
+//!            [-r routes]
+//! ```
+//!
+//! As with `ping`, this becomes the node described, whose neighbors must be
+//! running.
+
+extern crate lnx;
+extern crate network;
+extern crate static_routing;
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use lnx::cli::{self, Usage};
+use network::ipv4;
+use network::ipv4::ping::{self, Outcome};
+use network::ipv4::traceroute;
+use static_routing::StaticTable;
+
+const USAGE: Usage = Usage(
+  "usage: traceroute <node.lnx> <virtual IP> [-m max hops] [-q probes] [-W timeout ms] [-r routes]");
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.len() < 2 {
+    USAGE.fail();
+  }
+  let dst: ipv4::Addr = USAGE.parse("virtual IP", args.get(1));
+
+  let mut options = traceroute::Options::default();
+  let mut routes = None;
+  let mut flags = args[2..].iter();
+  while let Some(flag) = flags.next() {
+    match &flag[..] {
+      "-m" => options.max_hops = USAGE.parse("max hops", flags.next()),
+      "-q" => options.probes   = USAGE.parse("probes", flags.next()),
+      "-W" => options.timeout  = Duration::from_millis(USAGE.parse("timeout", flags.next())),
+      "-r" => routes           = Some(USAGE.parse::<String>("routes", flags.next())),
+      _    => USAGE.fail(),
+    }
+  }
+
+  let node = lnx::Node::from_file(&args[0]).unwrap_or_else(|e| cli::fail(&format!("{}: {}", args[0], e)));
+  let (listener, state) = node.build::<StaticTable>(1).unwrap_or_else(|e| cli::fail(&format!("{}", e)));
+  if let Some(path) = routes {
+    if let Err(e) = state.routes.load(&path) {
+      listener.shutdown();
+      cli::fail(&format!("{}: {}", path, e));
+    }
+  }
+
+  println!("traceroute to {}, {} hops max", dst, options.max_hops);
+  let hops = traceroute::traceroute(&*state, dst, &options, |hop| {
+    let mut line = format!("{:2} ", hop.ttl);
+    match hop.addr() {
+      Some(addr) => line.push_str(&format!(" {} ", addr)),
+      None       => (),
+    };
+    for probe in hop.probes.iter() {
+      line.push_str(&match *probe {
+        Outcome::Reply { rtt, .. } | Outcome::Error { rtt, .. } =>
+          format!(" {:.3} ms", ping::to_nanos(rtt) as f64 / 1_000_000.0),
+        Outcome::Timeout                                        => " *".to_string(),
+        Outcome::NotSent(ref e)                                 => format!(" {:?}", e),
+      });
+    }
+    println!("{}", line);
+  });
+
+  listener.shutdown();
+  let reached = hops.last().map_or(false, |h| h.addr() == Some(dst));
+  process::exit(if reached { 0 } else { 1 });
+}
@@ -0,0 +1,30 @@
+//! What `ping` and `traceroute` share for handling their arguments
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::str::FromStr;
+
+/// Prints the message to stderr, and exits as for bad arguments
+pub fn fail(msg: &str) -> ! {
+  let _ = writeln!(io::stderr(), "{}", msg);
+  process::exit(2)
+}
+
+/// A tool's usage message, to fail with when its arguments won't do
+pub struct Usage(pub &'static str);
+
+impl Usage {
+  pub fn fail(&self) -> ! {
+    fail(self.0)
+  }
+
+  /// Parses the argument, failing with what was bad and the usage if it is
+  /// missing or malformed
+  pub fn parse<T: FromStr>(&self, what: &str, arg: Option<&String>) -> T {
+    match arg.map(|s| T::from_str(&s[..])) {
+      Some(Ok(x)) => x,
+      _           => fail(&format!("bad {}\n{}", what, self.0)),
+    }
+  }
+}
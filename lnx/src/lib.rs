@@ -0,0 +1,242 @@
+//! Node descriptions in the ".lnx" format, and wiring up the IP layer
+//! according to them.
+//!
+//! The first line gives the host and port of the node's own UDP socket. Each
+//! further line is a link: the host and port of the neighbor's socket, then
+//! our virtual IP on that link, then the neighbor's. E.g.
+//!
+//! ```text
+//! localhost 17000
+//! localhost 17001 10.0.0.1 10.0.0.2
+//! localhost 17002 10.0.1.1 10.0.1.2
+//! ```
+//!
+//! Blank lines, and anything after a `#`, are ignored.
+
+#![feature(box_syntax)]
+#![feature(question_mark)]
+
+#[macro_use]
+extern crate log;
+
+extern crate udp_mock;
+extern crate network;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use network::ipv4;
+use network::ipv4::strategy::RoutingTable;
+use udp_mock::{Interface, Listener};
+
+pub mod cli;
+
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Link {
+  pub host:       String,
+  pub port:       u16,
+  pub local_vip:  ipv4::Addr,
+  pub remote_vip: ipv4::Addr,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Node {
+  pub host:  String,
+  pub port:  u16,
+  pub links: Vec<Link>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+  Io(io::Error),
+  /// Line number, counting from 1, and what is wrong with it
+  Parse(usize, &'static str),
+  /// The host has no IPv4 address
+  Resolve(String),
+  /// Two links lead to the same virtual IP
+  DuplicateNeighbor(ipv4::Addr),
+}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Error {
+    Error::Io(e)
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::Io(ref e)              => write!(f, "{}", e),
+      Error::Parse(line, msg)       => write!(f, "line {}: {}", line, msg),
+      Error::Resolve(ref host)      => write!(f, "could not resolve {}", host),
+      Error::DuplicateNeighbor(vip) => write!(f, "more than one link to {}", vip),
+    }
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+
+fn parse_port(line: usize, s: &str) -> Result<u16> {
+  u16::from_str(s).map_err(|_| Error::Parse(line, "bad port"))
+}
+
+fn parse_vip(line: usize, s: &str) -> Result<ipv4::Addr> {
+  ipv4::Addr::from_str(s).map_err(|_| Error::Parse(line, "bad virtual IP"))
+}
+
+/// The first IPv4 address of the host, as the UDP mock links only speak IPv4
+fn resolve(host: &str, port: u16) -> Result<SocketAddr> {
+  for addr in (host, port).to_socket_addrs()? {
+    if let SocketAddr::V4(_) = addr {
+      return Ok(addr);
+    }
+  }
+  Err(Error::Resolve(host.to_string()))
+}
+
+impl Node {
+  pub fn parse(s: &str) -> Result<Node> {
+    let mut node: Option<Node> = None;
+
+    for (i, line) in s.lines().enumerate() {
+      let line_no = i + 1;
+      let line = match line.find('#') {
+        Some(start) => &line[..start],
+        None        => line,
+      };
+      let words: Vec<&str> = line.split_whitespace().collect();
+      if words.is_empty() {
+        continue;
+      }
+
+      node = Some(match node {
+        None => match words.len() {
+          2 => Node {
+            host:  words[0].to_string(),
+            port:  parse_port(line_no, words[1])?,
+            links: Vec::new(),
+          },
+          _ => return Err(Error::Parse(line_no, "expected host and port")),
+        },
+        Some(mut node) => match words.len() {
+          4 => {
+            node.links.push(Link {
+              host:       words[0].to_string(),
+              port:       parse_port(line_no, words[1])?,
+              local_vip:  parse_vip(line_no, words[2])?,
+              remote_vip: parse_vip(line_no, words[3])?,
+            });
+            node
+          },
+          _ => return Err(Error::Parse(
+            line_no, "expected host, port, local virtual IP and remote virtual IP")),
+        },
+      });
+    }
+
+    node.ok_or(Error::Parse(1, "empty description"))
+  }
+
+  pub fn from_file<P>(path: P) -> Result<Node>
+    where P: AsRef<Path>
+  {
+    let mut s = String::new();
+    File::open(path)?.read_to_string(&mut s)?;
+    Node::parse(&s[..])
+  }
+
+  /// Binds the node's socket, and makes the IP layer with an interface for
+  /// each link.
+  ///
+  /// The listener is returned so that the caller can shut it down.
+  pub fn build<RT>(&self, num_threads: usize)
+                   -> Result<(Listener<'static>, Arc<ipv4::State<'static, RT, io::Error>>)>
+    where RT: RoutingTable<'static> + 'static
+  {
+    let listener = Listener::new(resolve(&self.host[..], self.port)?, num_threads)?;
+
+    let mut interfaces = Vec::with_capacity(self.links.len());
+    let mut neighbors  = HashMap::new();
+
+    for (i, link) in self.links.iter().enumerate() {
+      if neighbors.insert(link.remote_vip, i).is_some() {
+        return Err(Error::DuplicateNeighbor(link.remote_vip));
+      }
+      let remote = resolve(&link.host[..], link.port)?;
+      debug!("link {}: {} -> {} over {}", i, link.local_vip, link.remote_vip, remote);
+      // the IP layer registers the real handler
+      let interface = Interface::new(&listener, remote, box |_| ());
+      interfaces.push(ipv4::InterfaceRow::new(link.local_vip, box interface));
+    }
+
+    let state = ipv4::State::new(interfaces, neighbors);
+    Ok((listener, state))
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use network::ipv4::Addr;
+
+  use super::*;
+
+  #[test]
+  fn parse_node() {
+    let node = Node::parse("
+      # A
+      localhost 17000
+      localhost 17001 10.0.0.1 10.0.0.2 # to B
+
+      127.0.0.1 17002 10.0.1.1 10.0.1.2
+    ").unwrap();
+
+    assert_eq!(node, Node {
+      host:  "localhost".to_string(),
+      port:  17000,
+      links: vec![
+        Link {
+          host:       "localhost".to_string(),
+          port:       17001,
+          local_vip:  Addr([10, 0, 0, 1]),
+          remote_vip: Addr([10, 0, 0, 2]),
+        },
+        Link {
+          host:       "127.0.0.1".to_string(),
+          port:       17002,
+          local_vip:  Addr([10, 0, 1, 1]),
+          remote_vip: Addr([10, 0, 1, 2]),
+        },
+      ],
+    });
+  }
+
+  #[test]
+  fn parse_errors() {
+    match Node::parse("") {
+      Err(Error::Parse(1, _)) => (),
+      r => panic!("expected parse error, got {:?}", r),
+    }
+    match Node::parse("localhost 17000\nlocalhost 17001 10.0.0.1") {
+      Err(Error::Parse(2, _)) => (),
+      r => panic!("expected parse error, got {:?}", r),
+    }
+    match Node::parse("localhost 17000\nlocalhost 17001 10.0.0.1 10.0.0") {
+      Err(Error::Parse(2, _)) => (),
+      r => panic!("expected parse error, got {:?}", r),
+    }
+    match Node::parse("localhost 70000") {
+      Err(Error::Parse(1, _)) => (),
+      r => panic!("expected parse error, got {:?}", r),
+    }
+  }
+}
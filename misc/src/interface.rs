@@ -1,6 +1,9 @@
-// TODO: real network card may consolidate multiple packets per interrupt.
 pub type Handler<'a, Packet> = Box<Fn(Packet) + Send + Sync + 'a>;
 
+/// Like `Handler`, but for however many packets arrived together, as a real
+/// network card may consolidate multiple packets per interrupt.
+pub type BatchHandler<'a, Packet> = Box<Fn(Vec<Packet>) + Send + Sync + 'a>;
+
 pub trait Interface {
   type Error;
 }
@@ -6,6 +6,7 @@ extern crate core;
 extern crate log;
 
 pub mod interface;
+pub mod pool;
 pub mod state_machine;
 
 use core::ops::FnMut;
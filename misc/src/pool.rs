@@ -0,0 +1,55 @@
+use std::sync::Mutex;
+
+
+/// A free list of packet buffers, so that the data path can reuse them rather
+/// than allocating anew for each packet.
+///
+/// Buffers may be returned by anybody, not just whoever took them. If nobody
+/// does, nothing is lost but the reuse.
+pub struct Pool {
+  free:         Mutex<Vec<Vec<u8>>>,
+  buf_capacity: usize,
+  max_free:     usize,
+}
+
+impl Pool {
+  /// New buffers are allocated with `buf_capacity`, and no more than
+  /// `max_free` are kept around
+  pub fn new(buf_capacity: usize, max_free: usize) -> Pool {
+    Pool {
+      free:         Mutex::new(Vec::new()),
+      buf_capacity: buf_capacity,
+      max_free:     max_free,
+    }
+  }
+
+  /// An empty buffer, reused if possible
+  pub fn take(&self) -> Vec<u8> {
+    match self.free.lock().unwrap().pop() {
+      Some(buf) => buf,
+      None      => Vec::with_capacity(self.buf_capacity),
+    }
+  }
+
+  /// Hands a buffer back for reuse
+  pub fn give(&self, mut buf: Vec<u8>) {
+    // not worth keeping
+    if buf.capacity() < self.buf_capacity {
+      return;
+    }
+    buf.clear();
+    let mut free = self.free.lock().unwrap();
+    if free.len() < self.max_free {
+      free.push(buf);
+    }
+  }
+
+  /// How many buffers are waiting to be reused
+  pub fn len(&self) -> usize {
+    self.free.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
@@ -2,30 +2,49 @@ use core::fmt::Debug;
 use super::strategy;
 
 
-/// Enables the given interface
+/// Enables the given interface, and tells the routing strategy if it was
+/// disabled
 pub fn up<'a, A, E>(ip_state: &super::State<'a, A, E>, interface: usize)
                    -> Result<(), ()>
   where A: strategy::RoutingTable<'a> + 'a,
         E: Debug + 'a
 {
   // no UFCS to make this concise
-  match ip_state.get_interface(interface) {
+  let was_up = match ip_state.get_interface(interface) {
     None    => return Err(()),
-    Some(x) => x.interface.write().unwrap().enable(),
+    Some(x) => {
+      let mut x = x.interface.write().unwrap();
+      let was_up = x.get_status();
+      x.enable();
+      was_up
+    },
   };
+  // lock released, as the routing strategy may well want to send
+  if !was_up {
+    ip_state.notify_link_change(interface, true);
+  }
   Ok(())
 }
 
-/// Disables the given interface
+/// Disables the given interface, and tells the routing strategy if it was
+/// enabled
 pub fn down<'a, A, E>(ip_state: &super::State<'a, A, E>, interface: usize)
                      -> Result<(), ()>
   where A: strategy::RoutingTable<'a> + 'a,
         E: Debug + 'a
 {
-  match ip_state.get_interface(interface) {
+  let was_up = match ip_state.get_interface(interface) {
     None    => return Err(()),
-    Some(x) => x.interface.write().unwrap().disable(),
+    Some(x) => {
+      let mut x = x.interface.write().unwrap();
+      let was_up = x.get_status();
+      x.disable();
+      was_up
+    },
   };
+  if was_up {
+    ip_state.notify_link_change(interface, false);
+  }
   Ok(())
 }
 
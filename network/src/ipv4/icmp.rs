@@ -0,0 +1,396 @@
+//! ICMP, from RFC 792
+//!
+//! Echo requests are answered, and the IP layer reports the packets it could
+//! not deliver or forward back to their senders. Like other hosts and
+//! routers (RFC 1812, section 4.3.2.8), we limit how many of those reports go
+//! out, so a flood of bad packets does not become a flood of ICMP.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Instant;
+
+use super::{
+  packet,
+  send,
+  strategy,
+};
+
+use super::Addr;
+
+
+pub const PROTOCOL: u8 = 1;
+
+pub const ECHO_REPLY:       u8 = 0;
+pub const DEST_UNREACHABLE: u8 = 3;
+pub const ECHO_REQUEST:     u8 = 8;
+pub const TIME_EXCEEDED:    u8 = 11;
+
+/// Codes for `DEST_UNREACHABLE`
+pub mod unreachable {
+  pub const NET:                u8 = 0;
+  pub const HOST:               u8 = 1;
+  pub const PROTOCOL:           u8 = 2;
+  pub const PORT:               u8 = 3;
+  pub const FRAGMENTATION:      u8 = 4;
+}
+
+/// Codes for `TIME_EXCEEDED`
+pub mod exceeded {
+  pub const TTL:                u8 = 0;
+  pub const REASSEMBLY:         u8 = 1;
+}
+
+pub const HDR_LEN: usize = 8;
+
+/// How much of the offending packet's payload is sent back with an error
+pub const QUOTED_PAYLOAD: usize = 8;
+
+/// How many errors may go out in a burst
+pub const BURST: usize = 10;
+/// How many errors may go out each second, once the burst is used up
+pub const RATE:  usize = 100;
+
+
+/// An ICMP message, borrowed from the packet it came in
+#[derive(PartialEq, Eq,
+         Copy, Clone, Hash, Debug)]
+pub struct Message<'a> {
+  pub kind: u8,
+  pub code: u8,
+  /// The four bytes after the checksum, whose meaning depends on the kind
+  pub rest: [u8; 4],
+  pub data: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+  /// `None` if too short or the checksum is wrong
+  pub fn parse(buf: &'a [u8]) -> Option<Message<'a>> {
+    if buf.len() < HDR_LEN || checksum(buf) != 0 {
+      return None;
+    }
+    Some(Message {
+      kind: buf[0],
+      code: buf[1],
+      rest: [buf[4], buf[5], buf[6], buf[7]],
+      data: &buf[HDR_LEN..],
+    })
+  }
+
+  /// Identifier and sequence number, for echo requests and replies
+  pub fn echo_id_seq(&self) -> (u16, u16) {
+    ((self.rest[0] as u16) << 8 | self.rest[1] as u16,
+     (self.rest[2] as u16) << 8 | self.rest[3] as u16)
+  }
+
+  /// Whether this reports a problem, rather than being a query or reply
+  pub fn is_error(&self) -> bool {
+    is_error_kind(self.kind)
+  }
+
+  /// Appends the message, checksummed
+  pub fn write(&self, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    buf.extend_from_slice(&[self.kind, self.code, 0, 0]);
+    buf.extend_from_slice(&self.rest);
+    buf.extend_from_slice(self.data);
+    let sum = checksum(&buf[start..]);
+    buf[start + 2] = (sum >> 8) as u8;
+    buf[start + 3] = sum as u8;
+  }
+}
+
+fn is_error_kind(kind: u8) -> bool {
+  match kind {
+    // unreachable, source quench, redirect, time exceeded, parameter problem
+    3 | 4 | 5 | 11 | 12 => true,
+    _                    => false,
+  }
+}
+
+/// The Internet checksum of the whole buffer, which comes out as 0 for a
+/// message with a correct checksum
+pub fn checksum(buf: &[u8]) -> u16 {
+  packet::make_checksum(buf.chunks(2).map(|c| {
+    (c[0] as u16) << 8 | *c.get(1).unwrap_or(&0) as u16
+  }))
+}
+
+
+/// A token bucket
+pub struct RateLimiter {
+  bucket: Mutex<(usize, Instant)>,
+  burst:  usize,
+  rate:   usize,
+}
+
+impl RateLimiter {
+  pub fn new(burst: usize, rate: usize) -> RateLimiter {
+    RateLimiter {
+      bucket: Mutex::new((burst, Instant::now())),
+      burst:  burst,
+      rate:   rate,
+    }
+  }
+
+  /// Whether one more may go now
+  pub fn allow(&self) -> bool {
+    let mut bucket = self.bucket.lock().unwrap();
+    let &mut (ref mut tokens, ref mut refilled) = &mut *bucket;
+
+    let elapsed = refilled.elapsed();
+    let earned = elapsed.as_secs() as usize * self.rate
+      + elapsed.subsec_nanos() as usize / (1_000_000_000 / cmp::max(self.rate, 1));
+    if earned > 0 {
+      *tokens = cmp::min(self.burst, *tokens + earned);
+      *refilled = Instant::now();
+    }
+
+    if *tokens == 0 {
+      return false;
+    }
+    *tokens -= 1;
+    true
+  }
+}
+
+/// An ICMP message as it arrived, for watchers
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Received {
+  pub src:  Addr,
+  /// What was left of the TTL of the packet carrying it
+  pub ttl:  u8,
+  pub kind: u8,
+  pub code: u8,
+  pub rest: [u8; 4],
+  pub data: Vec<u8>,
+  pub at:   Instant,
+}
+
+impl Received {
+  pub fn message(&self) -> Message {
+    Message {
+      kind: self.kind,
+      code: self.code,
+      rest: self.rest,
+      data: &self.data[..],
+    }
+  }
+}
+
+/// The ICMP part of the IP layer's state
+pub struct Icmp {
+  pub limiter:  RateLimiter,
+  watchers:     Mutex<HashMap<usize, Sender<Received>>>,
+  next_watcher: AtomicUsize,
+}
+
+impl Icmp {
+  pub fn new() -> Icmp {
+    Icmp {
+      limiter:      RateLimiter::new(BURST, RATE),
+      watchers:     Mutex::new(HashMap::new()),
+      next_watcher: AtomicUsize::new(0),
+    }
+  }
+
+  /// Starts passing on every message which arrives, but for echo requests,
+  /// until the watcher is dropped
+  pub fn watch(&self) -> Watcher {
+    let id = self.next_watcher.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = channel();
+    self.watchers.lock().unwrap().insert(id, tx);
+    Watcher { icmp: self, id: id, rx: rx }
+  }
+
+  fn notify(&self, received: Received) {
+    let mut watchers = self.watchers.lock().unwrap();
+    // forget the ones which hung up
+    let gone: Vec<usize> = watchers.iter()
+      .filter(|&(_, tx)| tx.send(received.clone()).is_err())
+      .map(|(id, _)| *id)
+      .collect();
+    for id in gone {
+      watchers.remove(&id);
+    }
+  }
+}
+
+pub struct Watcher<'i> {
+  icmp:   &'i Icmp,
+  id:     usize,
+  pub rx: Receiver<Received>,
+}
+
+impl<'i> Watcher<'i> {
+  /// Distinct among the watchers at any one time, so handy for telling
+  /// whose echo replies are whose
+  pub fn id(&self) -> usize {
+    self.id
+  }
+}
+
+impl<'i> Drop for Watcher<'i> {
+  fn drop(&mut self) {
+    self.icmp.watchers.lock().unwrap().remove(&self.id);
+  }
+}
+
+
+/// Answers echo requests, and passes everything else on to the watchers
+pub fn make_handler<'a, A, E>(state: Weak<super::State<'a, A, E>>) -> super::Handler<'a>
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
+{
+  // weak, as the state owns its handlers
+  box move |packet: packet::V| {
+    let state = match state.upgrade() {
+      Some(state) => state,
+      None        => return,
+    };
+    receive(&*state, &packet);
+    state.pool.give(packet.to_vec());
+  }
+}
+
+fn receive<'a, A, E>(state: &super::State<'a, A, E>, packet: &packet::V)
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
+{
+  let src = packet.borrow().get_source();
+  let message = match Message::parse(packet.borrow().get_payload()) {
+    Some(m) => m,
+    None    => {
+      debug!("dropping malformed ICMP message from {}", src);
+      return;
+    },
+  };
+  debug!("ICMP from {}: type {} code {}", src, message.kind, message.code);
+
+  if message.kind == ECHO_REQUEST {
+    if message.code == 0 {
+      let reply = Message { kind: ECHO_REPLY, .. message };
+      if let Err(e) = send_message(state, src, reply) {
+        debug!("could not answer echo request from {}: {:?}", src, e);
+      }
+    }
+    return;
+  }
+
+  state.icmp.notify(Received {
+    src:  src,
+    ttl:  packet.borrow().get_time_to_live(),
+    kind: message.kind,
+    code: message.code,
+    rest: message.rest,
+    data: message.data.to_vec(),
+    at:   Instant::now(),
+  });
+}
+
+/// Sends the message in an IP packet of its own
+pub fn send_message<'a, A, E>(state:   &super::State<'a, A, E>,
+                              dst:     Addr,
+                              message: Message)
+                              -> send::Result<(), E>
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: 'a
+{
+  let len = HDR_LEN + message.data.len();
+  send::send(
+    state,
+    dst,
+    PROTOCOL,
+    Some(len as u16),
+    |packet| {
+      message.write(packet.as_mut_vec());
+      Ok(())
+    },
+    |_| Ok(()))
+}
+
+/// Tells the sender of `original` that it could not be delivered or
+/// forwarded. Per RFC 1122, nothing is sent about ICMP errors, fragments
+/// after the first, or packets whose sender can't be answered; and then only
+/// if the rate limit allows.
+pub fn send_error<'a, A, E>(state:    &super::State<'a, A, E>,
+                            original: &packet::A,
+                            kind:     u8,
+                            code:     u8,
+                            rest:     [u8; 4])
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
+{
+  let src = original.get_source();
+  let (_, offset) = original.get_flags_fragment_offset();
+  let about_error = original.get_protocol() == PROTOCOL && match original.get_payload().first() {
+    Some(&kind) => is_error_kind(kind),
+    None        => false,
+  };
+  let dst = original.get_destination();
+  let unanswerable = src.is_unspecified() || src.is_broadcast() || src.is_multicast()
+    || src.is_reserved() || src.is_loopback()
+    // nor about broadcasts or multicasts, lest everyone answer
+    || dst.is_broadcast() || dst.is_multicast() || dst.is_reserved();
+  if about_error || offset != 0 || unanswerable {
+    return;
+  }
+  if !state.icmp.limiter.allow() {
+    debug!("rate limited ICMP type {} to {}", kind, src);
+    return;
+  }
+
+  // the header, options and all, then the start of the payload
+  let quoted_len = cmp::min(original.hdr_bytes() + QUOTED_PAYLOAD,
+                            original.get_total_length() as usize);
+  let quoted_len = cmp::min(quoted_len, original.as_slice().len());
+  let message = Message {
+    kind: kind,
+    code: code,
+    rest: rest,
+    data: &original.as_slice()[..quoted_len],
+  };
+  if let Err(e) = send_message(state, src, message) {
+    debug!("could not send ICMP type {} to {}: {:?}", kind, src, e);
+  }
+}
+
+pub fn send_unreachable<'a, A, E>(state: &super::State<'a, A, E>, original: &packet::A, code: u8)
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
+{
+  send_error(state, original, DEST_UNREACHABLE, code, [0; 4]);
+}
+
+/// For transports to call on a packet for a port nobody is listening on,
+/// as UDP must per RFC 1122, section 4.1.3.1. The IP layer can't tell, as
+/// it knows nothing of ports.
+pub fn send_port_unreachable<'a, A, E>(state: &super::State<'a, A, E>, original: &packet::A)
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
+{
+  send_unreachable(state, original, unreachable::PORT);
+}
+
+/// Tells the sender that `original` would have to be fragmented to fit the
+/// next hop's MTU, but may not be. The MTU goes in the message, per RFC 1191.
+pub fn send_fragmentation_needed<'a, A, E>(state:    &super::State<'a, A, E>,
+                                           original: &packet::A,
+                                           mtu:      usize)
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
+{
+  let mtu = cmp::min(mtu, 0xffff);
+  send_error(state, original, DEST_UNREACHABLE, unreachable::FRAGMENTATION,
+             [0, 0, (mtu >> 8) as u8, mtu as u8]);
+}
+
+pub fn send_time_exceeded<'a, A, E>(state: &super::State<'a, A, E>, original: &packet::A, code: u8)
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
+{
+  send_error(state, original, TIME_EXCEEDED, code, [0; 4]);
+}
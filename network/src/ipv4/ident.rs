@@ -0,0 +1,51 @@
+//! Identification values for outgoing packets
+//!
+//! Per RFC 6864, values only need to be unique among the packets with the
+//! same source, destination and protocol which could be in flight at once.
+//! So rather than all packets sharing one counter, they are hashed into a
+//! number of counters, which keeps unrelated flows from eating through each
+//! other's values.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Addr;
+
+
+/// How many counters flows are spread over
+pub const BUCKETS: usize = 256;
+
+pub struct Identifiers {
+  counters: Vec<AtomicUsize>,
+}
+
+impl Identifiers {
+  /// The counters start from the clock, so that a restarted node is
+  /// unlikely to pick up where the last one left off
+  pub fn new() -> Identifiers {
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+      .map(|d| d.subsec_nanos() as usize ^ d.as_secs() as usize)
+      .unwrap_or(0);
+    Identifiers {
+      counters: (0..BUCKETS)
+        .map(|i| AtomicUsize::new(seed.wrapping_mul(i + 1)))
+        .collect(),
+    }
+  }
+
+  /// The next value for a packet of the given flow
+  pub fn next(&self, src: Addr, dst: Addr, protocol: u8) -> u16 {
+    let counter = &self.counters[bucket(src, dst, protocol)];
+    counter.fetch_add(1, Ordering::Relaxed) as u16
+  }
+}
+
+/// FNV-1a, which is plenty to spread addresses around
+fn bucket(Addr(src): Addr, Addr(dst): Addr, protocol: u8) -> usize {
+  let mut hash: u32 = 0x811c9dc5;
+  for &b in src.iter().chain(dst.iter()).chain(Some(protocol).iter()) {
+    hash ^= b as u32;
+    hash = hash.wrapping_mul(0x01000193);
+  }
+  hash as usize % BUCKETS
+}
@@ -1,23 +1,95 @@
 use std::collections::hash_map::HashMap;
+use std::error;
 use std::fmt;
+use std::net;
+use std::ops;
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use data_link::interface as dl;
+use misc::pool::Pool;
 
 use self::strategy::RoutingTable;
 
 pub mod control;
+pub mod icmp;
+pub mod ident;
 pub mod packet;
+pub mod ping;
+pub mod prefix;
+pub mod reassembly;
 pub mod send;
 pub mod receive;
+pub mod stats;
 pub mod strategy;
+pub mod traceroute;
 
 
 #[derive(PartialEq, PartialOrd, Eq, Ord,
          Copy, Clone, Hash, Debug)]
 pub struct Addr(pub [u8; 4]);
 
+/// `0.0.0.0`, for when there is no address yet
+pub const UNSPECIFIED: Addr = Addr([0; 4]);
+/// `255.255.255.255`, everyone on the link
+pub const BROADCAST:   Addr = Addr([255; 4]);
+
+impl Addr {
+  /// The first `len` bits set, e.g. `255.255.255.0` for 24. Panics if `len`
+  /// is over 32.
+  pub fn netmask(len: u8) -> Addr {
+    assert!(len <= 32, "a netmask is at most 32 bits long");
+    Addr::from(if len == 0 { 0 } else { !0u32 << (32 - len as u32) })
+  }
+
+  /// Just the first `len` bits of the address
+  pub fn mask(self, len: u8) -> Addr {
+    self & Addr::netmask(len)
+  }
+
+  pub fn octets(&self) -> [u8; 4] {
+    self.0
+  }
+
+  pub fn is_unspecified(&self) -> bool {
+    *self == UNSPECIFIED
+  }
+
+  pub fn is_broadcast(&self) -> bool {
+    *self == BROADCAST
+  }
+
+  /// `127.0.0.0/8`
+  pub fn is_loopback(&self) -> bool {
+    self.0[0] == 127
+  }
+
+  /// `224.0.0.0/4`
+  pub fn is_multicast(&self) -> bool {
+    self.0[0] & 0xf0 == 224
+  }
+
+  /// `240.0.0.0/4`, reserved for future use, but for the broadcast address
+  pub fn is_reserved(&self) -> bool {
+    self.0[0] & 0xf0 == 240 && !self.is_broadcast()
+  }
+
+  /// `10.0.0.0/8`, `172.16.0.0/12` or `192.168.0.0/16`, from RFC 1918
+  pub fn is_private(&self) -> bool {
+    match self.0 {
+      [10, _, _, _]                    => true,
+      [172, b, _, _] if b & 0xf0 == 16 => true,
+      [192, 168, _, _]                 => true,
+      _                                => false,
+    }
+  }
+
+  /// `169.254.0.0/16`, from RFC 3927
+  pub fn is_link_local(&self) -> bool {
+    self.0[0] == 169 && self.0[1] == 254
+  }
+}
+
 
 impl fmt::Display for Addr {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -26,21 +98,120 @@ impl fmt::Display for Addr {
   }
 }
 
-impl FromStr for Addr {
-  type Err = ();
+/// Why a string isn't an address
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum AddrParseError {
+  /// There weren't exactly four parts between the dots. Holds how many
+  /// there were.
+  Parts(usize),
+  /// The part at this index isn't a decimal number from 0 to 255
+  Octet(usize),
+}
+
+impl fmt::Display for AddrParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      AddrParseError::Parts(n) => write!(f, "expected 4 dotted parts, found {}", n),
+      AddrParseError::Octet(i) => write!(f, "part {} is not a number from 0 to 255", i + 1),
+    }
+  }
+}
+
+impl error::Error for AddrParseError {
+  fn description(&self) -> &str {
+    match *self {
+      AddrParseError::Parts(_) => "wrong number of parts in IPv4 address",
+      AddrParseError::Octet(_) => "bad octet in IPv4 address",
+    }
+  }
+}
 
-  fn from_str(s: &str) -> Result<Addr, ()> {
-    let mut quad: [Result<u8, ()>; 4] = [Err(()); 4];
-    let iter = s.trim().split('.').map(FromStr::from_str).map(|r| r.map_err(|_| ()));
+/// One part of a dotted quad: up to three digits, with no sign, and no
+/// leading zero lest it be read as octal.
+fn parse_octet(s: &str) -> Option<u8> {
+  let digits = s.len() >= 1 && s.len() <= 3 && s.bytes().all(|b| b >= b'0' && b <= b'9');
+  if !digits || (s.len() > 1 && s.starts_with('0')) {
+    return None;
+  }
+  u8::from_str(s).ok()
+}
 
-    for (mut ptr, val) in quad.iter_mut().zip(iter)
-    {
-      *ptr = val;
+impl FromStr for Addr {
+  type Err = AddrParseError;
+
+  /// Dotted-quad notation, e.g. `10.0.0.1`. Surrounding whitespace is
+  /// ignored.
+  fn from_str(s: &str) -> Result<Addr, AddrParseError> {
+    let parts: Vec<&str> = s.trim().split('.').collect();
+    if parts.len() != 4 {
+      return Err(AddrParseError::Parts(parts.len()));
     }
+    let mut quad = [0; 4];
+    for (i, part) in parts.into_iter().enumerate() {
+      quad[i] = parse_octet(part).ok_or(AddrParseError::Octet(i))?;
+    }
+    Ok(Addr(quad))
+  }
+}
 
-    let [a0, a1, a2, a3] = quad;
+impl From<[u8; 4]> for Addr {
+  fn from(octets: [u8; 4]) -> Addr {
+    Addr(octets)
+  }
+}
 
-    Ok(Addr([a0?, a1?, a2?, a3?]))
+impl From<Addr> for [u8; 4] {
+  fn from(Addr(octets): Addr) -> [u8; 4] {
+    octets
+  }
+}
+
+impl From<net::Ipv4Addr> for Addr {
+  fn from(ip: net::Ipv4Addr) -> Addr {
+    Addr(ip.octets())
+  }
+}
+
+impl From<Addr> for net::Ipv4Addr {
+  fn from(Addr([a, b, c, d]): Addr) -> net::Ipv4Addr {
+    net::Ipv4Addr::new(a, b, c, d)
+  }
+}
+
+/// Big-endian, so the first octet is the most significant byte
+impl From<u32> for Addr {
+  fn from(x: u32) -> Addr {
+    Addr([(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8])
+  }
+}
+
+impl From<Addr> for u32 {
+  fn from(Addr([a, b, c, d]): Addr) -> u32 {
+    (a as u32) << 24 | (b as u32) << 16 | (c as u32) << 8 | d as u32
+  }
+}
+
+impl ops::BitAnd for Addr {
+  type Output = Addr;
+
+  fn bitand(self, rhs: Addr) -> Addr {
+    Addr::from(u32::from(self) & u32::from(rhs))
+  }
+}
+
+impl ops::BitOr for Addr {
+  type Output = Addr;
+
+  fn bitor(self, rhs: Addr) -> Addr {
+    Addr::from(u32::from(self) | u32::from(rhs))
+  }
+}
+
+impl ops::Not for Addr {
+  type Output = Addr;
+
+  fn not(self) -> Addr {
+    Addr::from(!u32::from(self))
   }
 }
 
@@ -50,11 +221,23 @@ pub fn parse_addr(&[a, b, c, d]: &[u8; 4]) -> Addr {
   Addr([a, b, c, d])
 }
 
+/// The address in the first four bytes. Panics if there are fewer; see
+/// `parse_addr_checked`.
 #[inline]
 pub fn parse_addr_unsafe(b: &[u8]) -> Addr {
   Addr([b[0], b[1], b[2], b[3]])
 }
 
+/// The address in the first four bytes, if there are that many
+#[inline]
+pub fn parse_addr_checked(b: &[u8]) -> Option<Addr> {
+  if b.len() < 4 {
+    None
+  } else {
+    Some(parse_addr_unsafe(b))
+  }
+}
+
 // TODO: remove
 #[inline]
 pub fn write_addr(Addr(slice): Addr) -> [u8; 4] {
@@ -65,27 +248,60 @@ pub fn write_addr(Addr(slice): Addr) -> [u8; 4] {
 
 // key:    adjacent ip (next hop)
 // value:  index to InterfaceRow (see below)
+// Several neighbors may share an interface if its link can tell them apart,
+// e.g. Ethernet.
 pub type InterfaceTable = HashMap<Addr, usize>;
 
 pub struct InterfaceRow<'a, E> {
   pub local_ip:  Addr,
   pub interface: RwLock<Box<dl::Interface<'a, Error=E> + Send + Sync + 'a>>,
+  pub counters:  stats::Counters,
+}
+
+impl<'a, E> InterfaceRow<'a, E> {
+  pub fn new(local_ip:  Addr,
+             interface: Box<dl::Interface<'a, Error=E> + Send + Sync + 'a>)
+             -> InterfaceRow<'a, E>
+  {
+    InterfaceRow {
+      local_ip:  local_ip,
+      interface: RwLock::new(interface),
+      counters:  stats::Counters::new(),
+    }
+  }
+
+  /// The counters, plus what the driver dropped on receipt while disabled
+  pub fn stats(&self) -> stats::Stats {
+    let mut stats = self.counters.snapshot();
+    stats.dropped_disabled += self.interface.read().unwrap().dropped_disabled();
+    stats
+  }
 }
 
 // TODO: use Box<[u8]> instead of Vec<u8>
-// TODO: real network card may consolidate multiple packets per interrupt
 pub type Handler<'a> = super::misc::interface::Handler<'a, packet::V>;
 
 pub type ProtocolTable<'a> = Vec<Vec<Handler<'a>>>;
 
+/// Capacity of the buffers in a default pool, enough for most links
+pub const POOL_BUF_CAPACITY: usize = 1500;
+/// How many spare buffers a default pool keeps
+pub const POOL_SIZE:         usize = 256;
+
 pub struct State<'a, A, E> where A: RoutingTable<'a> + 'a
 {
   pub interfaces:        Vec<InterfaceRow<'a, E>>,
   pub neighbors:         InterfaceTable,
   pub routes:            A,
   pub protocol_handlers: RwLock<ProtocolTable<'a>>,
-  // Identification counter? increased with each packet sent out,
-  // used in Identification header for fragmentation purposes
+  /// Packet buffers shared with the drivers. Whoever is done with a packet
+  /// should give its buffer back here.
+  pub pool:              Arc<Pool>,
+  /// Fragments of datagrams for us, waiting on the rest
+  pub reassembly:        Mutex<reassembly::Reassembler>,
+  /// Where the Identification header of packets sent out comes from
+  pub identifiers:       ident::Identifiers,
+  pub icmp:              icmp::Icmp,
 }
 
 impl<'a, RT, DE> State<'a, RT, DE>
@@ -96,6 +312,18 @@ impl<'a, RT, DE> State<'a, RT, DE>
   pub fn new(interfaces: Vec<InterfaceRow<'a, DE>>,
              neighbors: InterfaceTable)
              -> Arc<State<'a, RT, DE>>
+  {
+    State::with_pool(interfaces,
+                     neighbors,
+                     Arc::new(Pool::new(POOL_BUF_CAPACITY, POOL_SIZE)))
+  }
+
+  /// Like `new`, but with the given buffer pool, with which every interface
+  /// is stocked
+  pub fn with_pool(interfaces: Vec<InterfaceRow<'a, DE>>,
+                   neighbors:  InterfaceTable,
+                   pool:       Arc<Pool>)
+                   -> Arc<State<'a, RT, DE>>
   {
     let routes: RT = RoutingTable::init(neighbors.keys().map(|x| *x));
 
@@ -103,6 +331,10 @@ impl<'a, RT, DE> State<'a, RT, DE>
       routes:            routes,
       neighbors:         neighbors,
       interfaces:        interfaces,
+      pool:              pool,
+      reassembly:        Mutex::new(reassembly::Reassembler::new()),
+      identifiers:       ident::Identifiers::new(),
+      icmp:              icmp::Icmp::new(),
       // handlers are not clonable, so the nice ways of doing this do not work
       protocol_handlers: RwLock::new(vec![
         vec![], vec![], vec![], vec![],   vec![], vec![], vec![], vec![],
@@ -147,12 +379,23 @@ impl<'a, RT, DE> State<'a, RT, DE>
         vec![], vec![], vec![], vec![],   vec![], vec![], vec![], vec![]]),
     });
 
-    for &InterfaceRow { ref interface, .. } in state.interfaces.iter() {
-      use self::receive::make_receive_callback;
-      interface.write().unwrap()
-        .update_recv_handler(make_receive_callback::<RT, DE>(state.clone()));
+    for (index, &InterfaceRow { ref interface, .. }) in state.interfaces.iter().enumerate() {
+      use self::receive::make_batch_receive_callback;
+      let interface = interface.write().unwrap();
+      interface.stock(state.pool.clone());
+      interface.update_recv_batch_handler(
+        make_batch_receive_callback::<RT, DE>(state.clone(), index));
+      interface.update_status_handler({
+        let state = state.clone();
+        box move |up: bool| state.notify_link_change(index, up)
+      });
     }
 
+    control::register_protocol_handler(
+      &*state,
+      icmp::PROTOCOL,
+      icmp::make_handler(Arc::downgrade(&state)));
+
     RoutingTable::monitor(state.clone());
 
     state
@@ -164,4 +407,20 @@ impl<'a, RT, DE> State<'a, RT, DE>
   {
     self.interfaces.as_slice().get(interface_ix)
   }
+
+  /// Tells the routing strategy that the given interface went up or down
+  pub fn notify_link_change(&self, interface_ix: usize, up: bool) {
+    debug!("interface {} is now {}", interface_ix, if up { "up" } else { "down" });
+    RoutingTable::on_link_change(self, interface_ix, up);
+  }
+
+  /// Snapshots the counters of the requested interface
+  pub fn interface_stats(&self, interface_ix: usize) -> Option<stats::Stats> {
+    self.get_interface(interface_ix).map(|row| row.stats())
+  }
+
+  /// Snapshots the counters of every interface, in order
+  pub fn stats(&self) -> Vec<stats::Stats> {
+    self.interfaces.iter().map(|row| row.stats()).collect()
+  }
 }
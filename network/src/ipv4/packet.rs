@@ -1,5 +1,6 @@
+use std::cmp;
+use std::error;
 use std::fmt;
-use std::mem::transmute;
 //use std::num::Int;
 use std::vec::Vec;
 
@@ -10,7 +11,10 @@ use super::write_addr;
 #[derive(PartialEq, PartialOrd, Eq, Ord, Clone, Debug)]
 pub struct V { buf: Vec<u8> }
 
+/// Only ever made from a `[u8]` by `new` or `new_mut`, which rely on the two
+/// having the same layout
 #[derive(PartialEq, PartialOrd, Eq, Ord)]//, Debug)]
+#[repr(transparent)]
 pub struct A { buf:    [u8] }
 
 
@@ -20,37 +24,30 @@ impl V {
   }
 
   /// NOT CHECKSUMED!
-  fn new_with_header(ip:                 Addr,
+  fn new_with_header(mut buf:            Vec<u8>,
+                     ip:                 Addr,
                      protocol:           u8,
                      expected_body_size: Option<u16>) -> V
   {
-    let mut buf: Vec<u8> = Vec::with_capacity(MIN_HDR_LEN_8S as usize
-                                              + expected_body_size.unwrap_or(0) as usize);
-    unsafe { buf.set_len(MIN_HDR_LEN_8S as usize); }
+    buf.clear();
+    buf.reserve(MIN_HDR_LEN_8S as usize + expected_body_size.unwrap_or(0) as usize);
+    // zeroed, as the version and header length share a byte
+    buf.resize(MIN_HDR_LEN_8S as usize, 0);
     let mut packet = V::new(buf);
     {
       let s = packet.borrow_mut();
-      const SENTINAL8:  u8  = 0b_1000_0001;
       const SENTINAL16: u16 = 0b_1100_1000_0000_0011;
-      const SENTINAL32: u32 = 0b_1110_0000_0000_0000_0000_0000_0000_0111;
-      *(s.cast_h_mut()) = Header {
-        version_ihl:           SENTINAL8,  // SET LATER
-        ///////////////////////////////////// Internet header length
-        type_of_service:       SENTINAL8,  // SET LATER
-        total_length:          SENTINAL16, // DO NOT SET
-        identification:        SENTINAL16,
-        flags_fragment_offset: SENTINAL16, // SET LATER
-        ///////////////////////////////////// Fragment Offset
-        time_to_live:          128,
-        protocol:              protocol,
-        header_checksum:       SENTINAL16, // DO NOT SET
-        source_address:        SENTINAL32, // DO NOT SET
-        destination_address:   SENTINAL32, // SET LATER
-      };
+      const SENTINAL32: Addr = Addr([0b_1110_0000, 0, 0, 0b_0000_0111]);
+      s.set_total_length(SENTINAL16);      // DO NOT SET
+      s.set_identification(0);             // SET LATER, if sent normally
+      s.set_time_to_live(128);
+      s.set_protocol(protocol);
+      s.set_header_checksum(SENTINAL16);   // DO NOT SET
+      s.set_source(SENTINAL32);            // DO NOT SET
       s.set_version(4);
       s.set_header_length(MIN_HDR_LEN_32S);
       s.set_type_of_service(Precedence::Routine, ServiceFlags::empty());
-      s.set_flags_fragment_offset(DONT_FRAGMENT, 0);
+      s.set_flags_fragment_offset(IpFlags::empty(), 0);
       s.set_destination(ip);
     }
     packet
@@ -65,7 +62,22 @@ impl V {
      -> Result<(Accum, V), Err>
     where F: for<'a> FnOnce(&'a mut V) -> Result<Accum, Err>
   {
-    let mut packet = V::new_with_header(ip, protocol, expected_body_size);
+    V::new_with_builder_in(Vec::new(), ip, protocol, expected_body_size, builder)
+  }
+
+  /// Like `new_with_builder`, but reuses the given buffer, e.g. one from a
+  /// pool. Whatever it held is discarded.
+  pub fn new_with_builder_in
+    <Err, Accum, F>
+    (buf:                Vec<u8>,
+     ip:                 Addr,
+     protocol:           u8,
+     expected_body_size: Option<u16>,
+     builder:            F)
+     -> Result<(Accum, V), Err>
+    where F: for<'a> FnOnce(&'a mut V) -> Result<Accum, Err>
+  {
+    let mut packet = V::new_with_header(buf, ip, protocol, expected_body_size);
 
     let accum = try!(builder(&mut packet));
 
@@ -78,21 +90,50 @@ impl V {
     // now fix header and checksum
     {
       let s = packet.borrow_mut();
-      s.cast_h_mut().total_length = len.to_be();
+      s.set_total_length(len);
       s.update_checksum();
     }
     Ok((accum, packet))
   }
 
+  /// Replaces the options, padding them out and growing the header to fit.
+  /// Anything after the header is moved along, so this can be called from a
+  /// builder before or after the body is written.
+  pub fn set_options(&mut self, options: &[IpOption]) -> Result<(), BadPacket> {
+    let mut bytes = Vec::new();
+    for o in options {
+      o.write(&mut bytes)?;
+    }
+    while bytes.len() % 4 != 0 {
+      bytes.push(option::END);
+    }
+    if bytes.len() > MAX_OPTIONS_LEN {
+      return Err(BadPacket::BadOptions);
+    }
+
+    let old_end = self.borrow().hdr_bytes();
+    let new_end = MIN_HDR_LEN_8S as usize + bytes.len();
+    let body = self.buf.split_off(old_end);
+    self.buf.truncate(MIN_HDR_LEN_8S as usize);
+    self.buf.extend_from_slice(&bytes[..]);
+    self.buf.extend_from_slice(&body[..]);
+
+    let s = self.borrow_mut();
+    s.set_header_length((new_end / 4) as u8);
+    let len = s.as_slice().len() as u16;
+    s.set_total_length(len);
+    Ok(())
+  }
+
   pub fn as_vec(&self) -> &Vec<u8> { &self.buf }
 
   pub fn as_mut_vec(&mut self) -> &mut Vec<u8> { &mut self.buf }
 
   pub fn to_vec(self) -> Vec<u8> { self.buf }
 
-  pub fn borrow(&self) -> &A { unsafe { transmute(self.buf.as_slice()) } }
+  pub fn borrow(&self) -> &A { A::new(self.buf.as_slice()) }
 
-  pub fn borrow_mut(&mut self) -> &mut A { unsafe { transmute(self.buf.as_mut_slice()) } }
+  pub fn borrow_mut(&mut self) -> &mut A { A::new_mut(self.buf.as_mut_slice()) }
 }
 
 pub const MIN_HDR_LEN_1S:  u32 = MIN_HDR_LEN_32S as u32 * 32;
@@ -100,41 +141,23 @@ pub const MIN_HDR_LEN_8S:  u16 = MIN_HDR_LEN_32S as u16 * 4;
 pub const MIN_HDR_LEN_16S: u16 = MIN_HDR_LEN_32S as u16 * 2;
 pub const MIN_HDR_LEN_32S: u8  = 5;
 
-///   From RFC 791
-///
-///    0                   1                   2                   3
-///    0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
-///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-///   |Version|  IHL  |Type of Service|          Total Length         |
-///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-///   |         Identification        |Flags|      Fragment Offset    |
-///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-///   |  Time to Live |    Protocol   |         Header Checksum       |
-///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-///   |                       Source Address                          |
-///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-///   |                    Destination Address                        |
-///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-///   |                    Options                    |    Padding    |
-///   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-
-#[repr(packed)]
-#[derive(PartialEq, PartialOrd, Eq, Ord,
-         Copy, Clone, Hash, Debug)]
-pub struct Header {
-  pub version_ihl:           u8,   // IP version (= 4)
-  /////////////////////////////////// Internet header length
-  pub type_of_service:       u8,   // Type of service
-  pub total_length:          u16,  // Total length in octets
-  pub identification:        u16,  // Identification
-  pub flags_fragment_offset: u16,  // 3-bits Flags
-  /////////////////////////////////// Fragment Offset
-  pub time_to_live:          u8,   // Time To Live
-  pub protocol:              u8,   // Protocol
-  pub header_checksum:       u16,  // Checksum
-  pub source_address:        u32,  // Source Address
-  pub destination_address:   u32,  // Destination Address
-}
+//   From RFC 791
+//
+//    0                   1                   2                   3
+//    0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//   |Version|  IHL  |Type of Service|          Total Length         |
+//   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//   |         Identification        |Flags|      Fragment Offset    |
+//   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//   |  Time to Live |    Protocol   |         Header Checksum       |
+//   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//   |                       Source Address                          |
+//   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//   |                    Destination Address                        |
+//   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//   |                    Options                    |    Padding    |
+//   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 
 
 #[repr(u8)]
@@ -151,6 +174,22 @@ pub enum Precedence {
   Routine             = 0b_000_00000,
 }
 
+impl Precedence {
+  /// The precedence in the top three bits of a type of service byte
+  pub fn from_tos(tos: u8) -> Precedence {
+    match tos >> 5 {
+      0b111 => Precedence::NetworkControl,
+      0b110 => Precedence::InternetworkControl,
+      0b101 => Precedence::CriticEpc,
+      0b100 => Precedence::FlashOverride,
+      0b011 => Precedence::Flash,
+      0b010 => Precedence::Immediate,
+      0b001 => Precedence::Priority,
+      _     => Precedence::Routine,
+    }
+  }
+}
+
 
 bitflags! {
   pub flags ServiceFlags: u8 {
@@ -168,38 +207,236 @@ bitflags! {
 }
 
 
-struct CastHelper {
-  header: Header,
-  _rest: [u8], // used to make it a DST
+/// Option types, from RFC 791 and RFC 2113. The top bit says whether the
+/// option is copied into every fragment.
+pub mod option {
+  pub const END:                 u8 = 0;
+  pub const NOP:                 u8 = 1;
+  pub const RECORD_ROUTE:        u8 = 7;
+  pub const TIMESTAMP:           u8 = 68;
+  pub const LOOSE_SOURCE_ROUTE:  u8 = 131;
+  pub const STRICT_SOURCE_ROUTE: u8 = 137;
+  pub const ROUTER_ALERT:        u8 = 148;
+
+  pub const COPIED:              u8 = 0b1000_0000;
 }
 
-impl A {
+/// Options fill out the header, which can be at most 60 bytes
+pub const MAX_OPTIONS_LEN: usize = 40;
 
-  pub fn as_slice(&self) -> &[u8] {
-    unsafe { transmute(self) }
+/// The most addresses a Record Route option can make room for, as it must
+/// fit among the options along with its 3 byte preamble
+pub const MAX_RECORD_ROUTE_SLOTS: usize = (MAX_OPTIONS_LEN - 3) / 4;
+
+#[derive(PartialEq, Eq,
+         Copy, Clone, Hash, Debug)]
+pub enum IpOption<'a> {
+  End,
+  Nop,
+  /// `pointer` is where, counting from the start of the option, the next
+  /// address goes, and `route` the room for addresses
+  RecordRoute       { pointer: u8, route: &'a [u8] },
+  Timestamp         { pointer: u8, overflow: u8, flags: u8, data: &'a [u8] },
+  LooseSourceRoute  { pointer: u8, route: &'a [u8] },
+  StrictSourceRoute { pointer: u8, route: &'a [u8] },
+  /// The value is 0 for "examine packet"
+  RouterAlert(u16),
+  Unknown           { kind: u8, data: &'a [u8] },
+}
+
+static NO_ADDRESSES: [u8; MAX_OPTIONS_LEN] = [0; MAX_OPTIONS_LEN];
+
+impl<'a> IpOption<'a> {
+  /// An empty Record Route option, with room for `slots` addresses. None if
+  /// that is more than `MAX_RECORD_ROUTE_SLOTS`.
+  pub fn record_route(slots: usize) -> Option<IpOption<'static>> {
+    if slots > MAX_RECORD_ROUTE_SLOTS {
+      return None;
+    }
+    Some(IpOption::RecordRoute { pointer: 4, route: &NO_ADDRESSES[..slots * 4] })
   }
 
-  pub fn as_mut_slice(&mut self) -> &mut [u8] {
-    unsafe { transmute(self) }
+  pub fn kind(&self) -> u8 {
+    match *self {
+      IpOption::End                        => option::END,
+      IpOption::Nop                        => option::NOP,
+      IpOption::RecordRoute { .. }         => option::RECORD_ROUTE,
+      IpOption::Timestamp { .. }           => option::TIMESTAMP,
+      IpOption::LooseSourceRoute { .. }    => option::LOOSE_SOURCE_ROUTE,
+      IpOption::StrictSourceRoute { .. }   => option::STRICT_SOURCE_ROUTE,
+      IpOption::RouterAlert(_)             => option::ROUTER_ALERT,
+      IpOption::Unknown { kind, .. }       => kind,
+    }
+  }
+
+  /// How many bytes the option takes up
+  pub fn len(&self) -> usize {
+    match *self {
+      IpOption::End | IpOption::Nop                  => 1,
+      IpOption::RecordRoute { route, .. }
+        | IpOption::LooseSourceRoute { route, .. }
+        | IpOption::StrictSourceRoute { route, .. }  => 3 + route.len(),
+      IpOption::Timestamp { data, .. }               => 4 + data.len(),
+      IpOption::RouterAlert(_)                       => 4,
+      IpOption::Unknown { data, .. }                 => 2 + data.len(),
+    }
+  }
+
+  /// Whether the option belongs in every fragment, or just the first
+  pub fn is_copied(&self) -> bool {
+    self.kind() & option::COPIED != 0
   }
 
-  pub fn cast_h(&self) -> &Header {
-    &    unsafe { transmute::<_,&CastHelper>(self) }.header
+  /// The addresses recorded in, or to route through, a route option
+  pub fn addresses(&self) -> Vec<Addr> {
+    match *self {
+      IpOption::RecordRoute { route, .. }
+        | IpOption::LooseSourceRoute { route, .. }
+        | IpOption::StrictSourceRoute { route, .. }
+        => route.chunks(4).map(parse_addr_unsafe).collect(),
+      _ => Vec::new(),
+    }
   }
 
-  pub fn cast_h_mut(&mut self) -> &mut Header {
-    &mut unsafe { transmute::<_,&mut CastHelper>(self) }.header
+  /// Appends the option, unless it is too long for its length byte to say
+  pub fn write(&self, buf: &mut Vec<u8>) -> Result<(), BadPacket> {
+    if self.len() > ::std::u8::MAX as usize {
+      return Err(BadPacket::BadOptions);
+    }
+    let kind = self.kind();
+    match *self {
+      IpOption::End | IpOption::Nop => buf.push(kind),
+      IpOption::RecordRoute { pointer, route }
+        | IpOption::LooseSourceRoute { pointer, route }
+        | IpOption::StrictSourceRoute { pointer, route } => {
+          buf.extend_from_slice(&[kind, 3 + route.len() as u8, pointer]);
+          buf.extend_from_slice(route);
+        },
+      IpOption::Timestamp { pointer, overflow, flags, data } => {
+        buf.extend_from_slice(&[kind, 4 + data.len() as u8, pointer,
+                                overflow << 4 | flags & 0b1111]);
+        buf.extend_from_slice(data);
+      },
+      IpOption::RouterAlert(value) =>
+        buf.extend_from_slice(&[kind, 4, (value >> 8) as u8, value as u8]),
+      IpOption::Unknown { data, .. } => {
+        buf.extend_from_slice(&[kind, 2 + data.len() as u8]);
+        buf.extend_from_slice(data);
+      },
+    }
+    Ok(())
   }
+}
+
+/// The options of a header, in order, up to and including any End
+pub struct Options<'a> {
+  buf: &'a [u8],
+}
 
+impl<'a> Iterator for Options<'a> {
+  type Item = Result<IpOption<'a>, BadPacket>;
 
+  fn next(&mut self) -> Option<Result<IpOption<'a>, BadPacket>> {
+    if self.buf.is_empty() {
+      return None;
+    }
+    let kind = self.buf[0];
+    match kind {
+      option::END => {
+        // nothing after counts
+        self.buf = &self.buf[self.buf.len()..];
+        return Some(Ok(IpOption::End));
+      },
+      option::NOP => {
+        self.buf = &self.buf[1..];
+        return Some(Ok(IpOption::Nop));
+      },
+      _ => (),
+    };
+
+    let len = match self.buf.get(1) {
+      Some(&len) if len >= 2 && len as usize <= self.buf.len() => len as usize,
+      _ => {
+        self.buf = &self.buf[self.buf.len()..];
+        return Some(Err(BadPacket::BadOptions));
+      },
+    };
+    let (body, rest) = self.buf.split_at(len);
+    self.buf = rest;
+
+    let parsed = match kind {
+      option::RECORD_ROUTE | option::LOOSE_SOURCE_ROUTE | option::STRICT_SOURCE_ROUTE => {
+        if len < 3 || (len - 3) % 4 != 0 || body[2] < 4 {
+          None
+        } else {
+          let (pointer, route) = (body[2], &body[3..]);
+          Some(match kind {
+            option::RECORD_ROUTE       => IpOption::RecordRoute { pointer: pointer, route: route },
+            option::LOOSE_SOURCE_ROUTE => IpOption::LooseSourceRoute { pointer: pointer, route: route },
+            _                          => IpOption::StrictSourceRoute { pointer: pointer, route: route },
+          })
+        }
+      },
+      option::TIMESTAMP => if len < 4 || body[2] < 5 {
+        None
+      } else {
+        Some(IpOption::Timestamp {
+          pointer:  body[2],
+          overflow: body[3] >> 4,
+          flags:    body[3] & 0b1111,
+          data:     &body[4..],
+        })
+      },
+      option::ROUTER_ALERT => if len != 4 {
+        None
+      } else {
+        Some(IpOption::RouterAlert((body[2] as u16) << 8 | body[3] as u16))
+      },
+      _ => Some(IpOption::Unknown { kind: kind, data: &body[2..] }),
+    };
+
+    Some(parsed.ok_or(BadPacket::BadOptions))
+  }
+}
+
+
+impl A {
+
+  pub fn as_slice(&self) -> &[u8] {
+    &self.buf
+  }
+
+  pub fn as_mut_slice(&mut self) -> &mut [u8] {
+    &mut self.buf
+  }
+
+  /// The header fields are read without checks, so the buffer must hold at
+  /// least a header without options. `Ipv4Packet::new_checked` checks the
+  /// rest.
+  ///
+  /// Panics if the buffer is shorter than that.
   pub fn new(buf: &[u8]) -> &A {
-    unsafe { transmute(buf) }
+    assert!(buf.len() >= MIN_HDR_LEN_8S as usize);
+    // `A` is just a `[u8]`, and the pointer keeps the length
+    unsafe { &*(buf as *const [u8] as *const A) }
   }
 
+  /// Like `new`, and panics likewise
   pub fn new_mut(buf: &mut [u8]) -> &mut A {
-    unsafe { transmute(buf) }
+    assert!(buf.len() >= MIN_HDR_LEN_8S as usize);
+    unsafe { &mut *(buf as *mut [u8] as *mut A) }
+  }
+
+  #[inline]
+  fn u16_at(&self, i: usize) -> u16 {
+    (self.buf[i] as u16) << 8 | self.buf[i + 1] as u16
   }
 
+  #[inline]
+  fn set_u16_at(&mut self, i: usize, v: u16) {
+    self.buf[i]     = (v >> 8) as u8;
+    self.buf[i + 1] = v as u8;
+  }
 
 
   pub fn get_version(&self) -> u8 { self.buf[0] >> 4 }
@@ -220,45 +457,45 @@ impl A {
 
   pub fn hdr_bytes(&self) -> usize { self.get_header_length() as usize * 4 }
 
-  pub fn get_total_length(&    self) -> u16 { u16::from_be(self.cast_h()    .total_length) }
-  pub fn set_total_length(&mut self, v: u16)             { self.cast_h_mut().total_length = v.to_be(); }
+  pub fn get_total_length(&    self) -> u16 { self.u16_at(2) }
+  pub fn set_total_length(&mut self, v: u16) { self.set_u16_at(2, v); }
 
 
   pub fn get_type_of_service(&self) -> (Precedence, ServiceFlags) {
     const MASK: u8 = 0b111_00000;
-    let tos = self.cast_h().type_of_service;
-    ( unsafe { ::std::mem::transmute(tos & MASK) },
+    let tos = self.buf[1];
+    ( Precedence::from_tos(tos),
       ServiceFlags { bits: tos & !MASK } )
   }
   pub fn set_type_of_service(&mut self, prec: Precedence, flags: ServiceFlags) {
-    self.cast_h_mut().type_of_service = prec as u8 | flags.bits;
+    self.buf[1] = prec as u8 | flags.bits;
   }
 
 
-  pub fn get_identification(&    self) -> u16 { u16::from_be(self.cast_h()    .identification) }
-  pub fn set_identification(&mut self, v: u16)             { self.cast_h_mut().identification = v.to_be(); }
+  pub fn get_identification(&    self) -> u16 { self.u16_at(4) }
+  pub fn set_identification(&mut self, v: u16) { self.set_u16_at(4, v); }
 
 
   pub fn get_flags_fragment_offset(&self) -> (IpFlags, u16) {
-    let ffo = u16::from_be(self.cast_h().flags_fragment_offset);
+    let ffo = self.u16_at(6);
     const MASK: u16 = 0b111_00000_00000000;
-    ( unsafe { ::std::mem::transmute(ffo & MASK) },
+    ( IpFlags::from_bits_truncate(ffo & MASK),
       ffo & !MASK)
   }
   pub fn set_flags_fragment_offset(&mut self, flags: IpFlags, offset: u16) {
     assert!(0 == (offset & 0b111_00000_00000000));
-    self.cast_h_mut().flags_fragment_offset = (flags.bits | offset).to_be();
+    self.set_u16_at(6, flags.bits | offset);
   }
 
 
-  pub fn get_time_to_live(&    self) -> u8  { self.cast_h()    .time_to_live }
-  pub fn set_time_to_live(&mut self, v: u8) { self.cast_h_mut().time_to_live = v; }
+  pub fn get_time_to_live(&    self) -> u8  { self.buf[8] }
+  pub fn set_time_to_live(&mut self, v: u8) { self.buf[8] = v; }
 
-  pub fn get_protocol(&    self) -> u8  { self.cast_h()    .protocol }
-  pub fn set_protocol(&mut self, v: u8) { self.cast_h_mut().protocol = v; }
+  pub fn get_protocol(&    self) -> u8  { self.buf[9] }
+  pub fn set_protocol(&mut self, v: u8) { self.buf[9] = v; }
 
-  pub fn get_header_checksum(&    self) -> u16 { u16::from_be(self.cast_h()    .header_checksum) }
-  pub fn set_header_checksum(&mut self, v: u16)             { self.cast_h_mut().header_checksum = v.to_be(); }
+  pub fn get_header_checksum(&    self) -> u16 { self.u16_at(10) }
+  pub fn set_header_checksum(&mut self, v: u16) { self.set_u16_at(10, v); }
 
   pub fn get_source(&self) -> Addr { parse_addr_unsafe(&self.buf[12..16]) }
   pub fn set_source(&mut self, a: Addr) {
@@ -279,8 +516,38 @@ impl A {
     self.buf[19] = d;
   }
 
-  // Eh, todo. Iterator over IpOptions?
-  //pub fn options(&self) -> ... {  }
+  /// The options, which must have been validated first
+  pub fn options(&self) -> Options {
+    let end = ::std::cmp::min(self.hdr_bytes(), self.buf.len());
+    Options { buf: &self.buf[MIN_HDR_LEN_8S as usize..end] }
+  }
+
+  /// Adds the address to the Record Route option, if there is one with room
+  /// left. The checksum is left to the caller.
+  pub fn record_route(&mut self, a: Addr) -> bool {
+    let mut at = MIN_HDR_LEN_8S as usize;
+    let mut found = None;
+    for o in self.options() {
+      match o {
+        Ok(IpOption::RecordRoute { pointer, route }) => {
+          found = Some((at, pointer as usize, route.len()));
+          break;
+        },
+        Ok(o)  => at += o.len(),
+        Err(_) => return false,
+      }
+    }
+    match found {
+      // the pointer counts from the start of the option, from 1
+      Some((start, pointer, room)) if pointer <= room => {
+        let slot = start + pointer - 1;
+        self.buf[slot..slot + 4].copy_from_slice(&write_addr(a));
+        self.buf[start + 2] += 4;
+        true
+      },
+      _ => false,
+    }
+  }
 
   pub fn get_payload(&self) -> &[u8] {
     if self.get_total_length() as usize > self.buf.len() {
@@ -302,18 +569,7 @@ impl A {
 
   /// returns native endian
   pub fn make_header_checksum(&self) -> u16 {
-    let u16s: &[u16] = unsafe { cast_slice(&self.as_slice()[..self.as_slice().len() & !1]) };
-
-    // TODO: Factor out singleton iterator
-    let temp: [u16; 1] = [0]; // for checkum field itself
-
-    // [..12] to make sure body is excluded,
-    // and also because length might be incorrect from transmute
-    let iter = u16s[0..5].iter()
-      .chain(temp.iter())
-      .chain(u16s[6..(self.hdr_bytes() / 2)].iter());
-
-    make_checksum(iter.map(|x| u16::from_be(*x)))
+    header_checksum(&self.as_slice()[..self.hdr_bytes()])
   }
 
   pub fn update_checksum(&mut self) {
@@ -328,7 +584,7 @@ impl fmt::Display for A {
            "Ip  | ver {} | {} | Tos {} | Len {}  |\n    | FId {}    |   off {} |\n    | ttl {} | proto {} | sum {} |\n    | Src {}   | Dst {} |",
            self.get_version(),
            self.get_header_length(),
-           self.cast_h().type_of_service,
+           self.buf[1],
            self.get_total_length(),
 
            self.get_identification(),
@@ -363,52 +619,327 @@ pub enum BadPacket {
 
   BadChecksum(u16, u16),
   BadOptions,
+
+  FragmentTooFar(u16, usize),  // offset and payload length end past 65535 bytes
 }
 
 
-pub fn validate(buf: &[u8]) -> Result<(), BadPacket>
-{
-  // have to check this first to avoid out-of-bounds panic on version check
-  if buf.len() < 1 {
-    return Err(BadPacket::TooShort(buf.len()))
+impl fmt::Display for BadPacket {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      BadPacket::TooShort(len)           => write!(f, "{} bytes is too short for an IPv4 header", len),
+      BadPacket::BadVersion(v)           => write!(f, "IP version {}, not 4", v),
+      BadPacket::BadPacketLength(len, t) => write!(f, "{} bytes, but total length says {}", len, t),
+      BadPacket::HeaderTooLong(hdr, len) => write!(f, "{} byte header in {} byte packet", hdr, len),
+      BadPacket::HeaderTooShort(hdr)     => write!(f, "{} byte header is shorter than the minimum", hdr),
+      BadPacket::BadChecksum(exp, got)   => write!(f, "checksum {:04x}, expected {:04x}", got, exp),
+      BadPacket::BadOptions              => write!(f, "malformed options"),
+      BadPacket::FragmentTooFar(o, len)  => write!(f, "{} byte fragment at offset {} ends past 65535 bytes", len, o),
+    }
   }
+}
 
-  let packet = A::new(buf);
+impl error::Error for BadPacket {
+  fn description(&self) -> &str {
+    match *self {
+      BadPacket::TooShort(_)           => "too short for an IPv4 header",
+      BadPacket::BadVersion(_)         => "not IPv4",
+      BadPacket::BadPacketLength(_, _) => "total length is wrong",
+      BadPacket::HeaderTooLong(_, _)   => "header longer than packet",
+      BadPacket::HeaderTooShort(_)     => "header shorter than minimum",
+      BadPacket::BadChecksum(_, _)     => "bad header checksum",
+      BadPacket::BadOptions            => "malformed options",
+      BadPacket::FragmentTooFar(_, _)  => "fragment ends past the largest datagram",
+    }
+  }
+}
 
-  // try to do this early as possible in case is other type of packet
-  if packet.get_version() != 4 {
-    return Err(BadPacket::BadVersion(packet.get_version()))
-  };
 
-  // then this so other header indexing doesn't panic
-  if packet.as_slice().len() != packet.get_total_length() as usize {
-    return Err(BadPacket::BadPacketLength(packet.as_slice().len(),
-                                          packet.get_total_length()))
-  };
+/// A view of an IPv4 packet in someone else's buffer, e.g. `&[u8]`,
+/// `&mut [u8]` or `Vec<u8>`.
+///
+/// Unlike `A`, which only checks there is room for a header without
+/// options, this is checked on construction, so none of the accessors can
+/// read out of bounds or panic. Only the shape of the header is checked,
+/// not its checksum or options, and the total length may be more than the
+/// buffer holds, as with the start of a packet quoted by ICMP: `payload`
+/// returns whatever of it is there. `validate` checks the rest.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Ipv4Packet<B> {
+  buf: B,
+}
 
+impl<B> Ipv4Packet<B> where B: AsRef<[u8]> {
+  pub fn new_checked(buf: B) -> Result<Ipv4Packet<B>, BadPacket> {
+    {
+      let b = buf.as_ref();
+      // the version first, in case this is some other kind of packet
+      match b.first() {
+        None                    => return Err(BadPacket::TooShort(0)),
+        Some(&v) if v >> 4 != 4 => return Err(BadPacket::BadVersion(v >> 4)),
+        Some(_)                 => (),
+      };
+      if b.len() < MIN_HDR_LEN_8S as usize {
+        return Err(BadPacket::TooShort(b.len()));
+      }
+      let hdr_bytes = (b[0] & 0b0000_1111) as usize * 4;
+      if hdr_bytes < MIN_HDR_LEN_8S as usize {
+        return Err(BadPacket::HeaderTooShort(hdr_bytes));
+      }
+      if hdr_bytes > b.len() {
+        return Err(BadPacket::HeaderTooLong(hdr_bytes, b.len()));
+      }
+    }
+    Ok(Ipv4Packet { buf: buf })
+  }
 
-  if packet.hdr_bytes() > packet.as_slice().len()
-  {
-    return Err(BadPacket::HeaderTooLong(packet.hdr_bytes(),
-                                        packet.as_slice().len()))
-  };
-  if packet.hdr_bytes() < MIN_HDR_LEN_8S as usize
-  {
-    return Err(BadPacket::HeaderTooShort(packet.hdr_bytes()))
+  pub fn into_inner(self) -> B {
+    self.buf
+  }
+
+  pub fn as_slice(&self) -> &[u8] {
+    self.buf.as_ref()
+  }
+
+  #[inline]
+  fn u16_at(&self, i: usize) -> u16 {
+    let b = self.buf.as_ref();
+    (b[i] as u16) << 8 | b[i + 1] as u16
+  }
+
+  #[inline]
+  fn addr_at(&self, i: usize) -> Addr {
+    let b = self.buf.as_ref();
+    Addr([b[i], b[i + 1], b[i + 2], b[i + 3]])
+  }
+
+  pub fn version(&self) -> u8 { self.buf.as_ref()[0] >> 4 }
+
+  /// In 32-bit words
+  pub fn header_length(&self) -> u8 { self.buf.as_ref()[0] & 0b0000_1111 }
+
+  pub fn hdr_bytes(&self) -> usize { self.header_length() as usize * 4 }
+
+  pub fn type_of_service(&self) -> (Precedence, ServiceFlags) {
+    let tos = self.buf.as_ref()[1];
+    (Precedence::from_tos(tos), ServiceFlags::from_bits_truncate(tos))
+  }
+
+  pub fn total_length(&self) -> u16 { self.u16_at(2) }
+
+  pub fn identification(&self) -> u16 { self.u16_at(4) }
+
+  pub fn flags_fragment_offset(&self) -> (IpFlags, u16) {
+    let ffo = self.u16_at(6);
+    (IpFlags::from_bits_truncate(ffo), ffo & 0b000_11111_11111111)
+  }
+
+  pub fn time_to_live(&self) -> u8 { self.buf.as_ref()[8] }
+
+  pub fn protocol(&self) -> u8 { self.buf.as_ref()[9] }
+
+  pub fn header_checksum(&self) -> u16 { self.u16_at(10) }
+
+  pub fn source(&self) -> Addr { self.addr_at(12) }
+
+  pub fn destination(&self) -> Addr { self.addr_at(16) }
+
+  /// The header, options and all
+  pub fn header(&self) -> &[u8] {
+    &self.buf.as_ref()[..self.hdr_bytes()]
+  }
+
+  pub fn options(&self) -> Options {
+    Options { buf: &self.buf.as_ref()[MIN_HDR_LEN_8S as usize..self.hdr_bytes()] }
+  }
+
+  /// Up to the total length, or the end of the buffer if that comes first
+  pub fn payload(&self) -> &[u8] {
+    let b = self.buf.as_ref();
+    let end = cmp::max(cmp::min(self.total_length() as usize, b.len()), self.hdr_bytes());
+    &b[self.hdr_bytes()..end]
+  }
+
+  /// What the header checksum should be, in native byte order
+  pub fn make_header_checksum(&self) -> u16 {
+    header_checksum(self.header())
+  }
+
+  pub fn is_checksum_valid(&self) -> bool {
+    self.make_header_checksum() == self.header_checksum()
+  }
+}
+
+impl<B> Ipv4Packet<B> where B: AsRef<[u8]> + AsMut<[u8]> {
+  #[inline]
+  fn set_u16_at(&mut self, i: usize, v: u16) {
+    let b = self.buf.as_mut();
+    b[i]     = (v >> 8) as u8;
+    b[i + 1] = v as u8;
+  }
+
+  #[inline]
+  fn set_addr_at(&mut self, i: usize, Addr(a): Addr) {
+    self.buf.as_mut()[i..i + 4].copy_from_slice(&a);
+  }
+
+  pub fn set_type_of_service(&mut self, prec: Precedence, flags: ServiceFlags) {
+    self.buf.as_mut()[1] = prec as u8 | flags.bits;
+  }
+
+  pub fn set_identification(&mut self, v: u16) { self.set_u16_at(4, v) }
+
+  /// Only the low 13 bits of the offset fit, and the rest are dropped
+  pub fn set_flags_fragment_offset(&mut self, flags: IpFlags, offset: u16) {
+    self.set_u16_at(6, flags.bits | offset & 0b000_11111_11111111)
+  }
+
+  pub fn set_time_to_live(&mut self, v: u8) { self.buf.as_mut()[8] = v }
+
+  pub fn set_protocol(&mut self, v: u8) { self.buf.as_mut()[9] = v }
+
+  pub fn set_header_checksum(&mut self, v: u16) { self.set_u16_at(10, v) }
+
+  pub fn set_source(&mut self, a: Addr) { self.set_addr_at(12, a) }
+
+  pub fn set_destination(&mut self, a: Addr) { self.set_addr_at(16, a) }
+
+  pub fn payload_mut(&mut self) -> &mut [u8] {
+    let (start, len) = (self.hdr_bytes(), self.payload().len());
+    &mut self.buf.as_mut()[start..start + len]
+  }
+
+  pub fn update_checksum(&mut self) {
+    let cs = self.make_header_checksum();
+    self.set_header_checksum(cs);
+  }
+}
+
+/// The checksum of a whole header, options included, as if its checksum
+/// field were zero. Native byte order.
+fn header_checksum(header: &[u8]) -> u16 {
+  make_checksum(header.chunks(2).enumerate().map(|(i, c)| {
+    // the checksum field itself
+    if i == 5 { 0 } else { (c[0] as u16) << 8 | *c.get(1).unwrap_or(&0) as u16 }
+  }))
+}
+
+
+/// Checks the packet is IPv4, all there with nothing after, and has a
+/// right checksum and well-formed options. Fragments must not reach past
+/// the largest datagram.
+pub fn validate(buf: &[u8]) -> Result<(), BadPacket>
+{
+  let packet = Ipv4Packet::new_checked(buf)?;
+
+  if packet.as_slice().len() != packet.total_length() as usize {
+    return Err(BadPacket::BadPacketLength(packet.as_slice().len(),
+                                          packet.total_length()))
   };
 
   {
     let expected = packet.make_header_checksum();
-    let got      = packet.get_header_checksum();
+    let got      = packet.header_checksum();
     if expected != got
     {
       return Err(BadPacket::BadChecksum(expected, got));
     }
   };
 
+  {
+    let (_, offset) = packet.flags_fragment_offset();
+    let len = packet.payload().len();
+    if !fragment_fits(offset, len) {
+      return Err(BadPacket::FragmentTooFar(offset, len));
+    }
+  };
+
+  for option in packet.options() {
+    option?;
+  }
+
   Ok(())
 }
 
+/// Splits the packet into fragments of at most `mtu` bytes each, per RFC 791.
+///
+/// Every fragment gets a copy of the header, and so the same identification,
+/// though only the first keeps the options which are not to be copied.
+/// Offsets are relative to the original datagram, so fragments can be split
+/// again. `None` if `DONT_FRAGMENT` is set, if the MTU leaves no room for
+/// even 8 bytes of payload, or if the packet already ends past the largest
+/// datagram, as then some offset would not fit in its 13 bits.
+pub fn fragment(packet: &A, mtu: usize) -> Option<Vec<V>>
+{
+  let (flags, offset) = packet.get_flags_fragment_offset();
+  if flags.contains(DONT_FRAGMENT) {
+    return None;
+  }
+  if !fragment_fits(offset, packet.get_payload().len()) {
+    return None;
+  }
+
+  let first_header = &packet.as_slice()[..packet.hdr_bytes()];
+  let rest_header  = copied_header(packet);
+  let payload = packet.get_payload();
+  // all but the last fragment must carry a multiple of 8 bytes. The later
+  // headers are never longer, so the same amount fits in them.
+  let chunk = mtu.saturating_sub(first_header.len()) & !7;
+  if chunk == 0 {
+    return None;
+  }
+
+  let fragments = payload.chunks(chunk).enumerate().map(|(i, piece)| {
+    let header = if i == 0 { first_header } else { &rest_header[..] };
+    let mut buf = Vec::with_capacity(header.len() + piece.len());
+    buf.extend_from_slice(header);
+    buf.extend_from_slice(piece);
+
+    let mut fragment = V::new(buf);
+    {
+      let s = fragment.borrow_mut();
+      let start = i * chunk;
+      // the last piece keeps whatever the original had
+      let flags = if start + piece.len() < payload.len() {
+        flags | MORE_FRAGMENTS
+      } else {
+        flags
+      };
+      s.set_flags_fragment_offset(flags, offset + (start / 8) as u16);
+      let len = s.as_slice().len() as u16;
+      s.set_total_length(len);
+      s.update_checksum();
+    }
+    fragment
+  }).collect();
+  Some(fragments)
+}
+
+/// Whether a payload of `len` bytes at `offset` (in 8 byte units) ends
+/// within the largest datagram
+fn fragment_fits(offset: u16, len: usize) -> bool {
+  offset as usize * 8 + len <= 0xffff
+}
+
+/// The header with only the options to be copied into every fragment
+fn copied_header(packet: &A) -> Vec<u8> {
+  let mut header = packet.as_slice()[..MIN_HDR_LEN_8S as usize].to_vec();
+  for o in packet.options() {
+    match o {
+      Ok(o)  => if o.is_copied() {
+        o.write(&mut header).expect("options read from a header fit in one");
+      },
+      Err(_) => break,
+    }
+  }
+  while header.len() % 4 != 0 {
+    header.push(option::END);
+  }
+  let words = (header.len() / 4) as u8;
+  A::new_mut(&mut header[..]).set_header_length(words);
+  header
+}
+
 /// assumes and returns native byte order
 pub fn make_checksum<I>(iter: I) -> u16
   where I: Iterator<Item=u16>
@@ -428,14 +959,3 @@ pub fn make_checksum<I>(iter: I) -> u16
 
   !(sum as u16)
 }
-
-/// Adjusts len
-pub unsafe fn cast_slice<T, U>(src: &[T]) -> &[U]
-{
-  use core::mem::size_of;
-  use core::slice;
-
-  assert_eq!(0, src.len() % size_of::<U>());
-
-  slice::from_raw_parts(src.as_ptr() as _, src.len() / size_of::<U>())
-}
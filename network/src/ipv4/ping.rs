@@ -0,0 +1,272 @@
+//! Sending echo requests and timing the replies, like `ping`
+//!
+//! One request is outstanding at a time: the next goes out once the reply to
+//! the last has come, or it has timed out, and the interval has passed.
+
+use std::cmp;
+use std::fmt::Debug;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{
+  icmp,
+  packet,
+  send,
+  strategy,
+};
+
+use super::Addr;
+
+
+pub struct Options {
+  /// Bytes of payload in each request, after the ICMP header
+  pub size:     usize,
+  pub count:    usize,
+  /// From sending one request to sending the next
+  pub interval: Duration,
+  /// How long to wait on each reply
+  pub timeout:  Duration,
+  pub ttl:      u8,
+}
+
+impl Default for Options {
+  fn default() -> Options {
+    Options {
+      size:     56,
+      count:    4,
+      interval: Duration::from_secs(1),
+      timeout:  Duration::from_secs(1),
+      ttl:      64,
+    }
+  }
+}
+
+/// What became of one echo request
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Outcome<E> {
+  Reply {
+    from:  Addr,
+    /// Bytes of ICMP message, header included
+    bytes: usize,
+    ttl:   u8,
+    rtt:   Duration,
+  },
+  /// An ICMP error came back instead, e.g. from a router with no route on
+  Error {
+    from:  Addr,
+    kind:  u8,
+    code:  u8,
+    rtt:   Duration,
+  },
+  Timeout,
+  NotSent(send::Error<E>),
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Stats {
+  pub transmitted: usize,
+  pub received:    usize,
+  /// Requests answered with ICMP errors
+  pub errors:      usize,
+  /// Round trip times of the replies: zero if there were none
+  pub min:         Duration,
+  pub avg:         Duration,
+  pub max:         Duration,
+  /// What ping(8) calls the mean deviation, though it is really the standard
+  /// deviation: sqrt(mean(x²) - mean(x)²)
+  pub mdev:        Duration,
+}
+
+impl Stats {
+  fn new(transmitted: usize, errors: usize, rtts: &[Duration]) -> Stats {
+    let nanos: Vec<u64> = rtts.iter().map(|d| to_nanos(*d)).collect();
+    let n = cmp::max(nanos.len() as u64, 1);
+    let avg = nanos.iter().fold(0, |a, b| a + b) / n;
+    // in floating point, as the squares of a few seconds' nanoseconds
+    // overflow
+    let mean    = nanos.iter().fold(0.0, |a, &x| a + x as f64) / n as f64;
+    let mean_sq = nanos.iter().fold(0.0, |a, &x| a + x as f64 * x as f64) / n as f64;
+    // rounding may leave it a hair below zero
+    let mdev = (mean_sq - mean * mean).max(0.0).sqrt();
+    Stats {
+      transmitted: transmitted,
+      received:    rtts.len(),
+      errors:      errors,
+      min:         from_nanos(nanos.iter().cloned().min().unwrap_or(0)),
+      avg:         from_nanos(avg),
+      max:         from_nanos(nanos.iter().cloned().max().unwrap_or(0)),
+      mdev:        from_nanos(mdev as u64),
+    }
+  }
+
+  /// Percentage of requests which got no reply
+  pub fn loss(&self) -> usize {
+    if self.transmitted == 0 {
+      0
+    } else {
+      (self.transmitted - self.received) * 100 / self.transmitted
+    }
+  }
+}
+
+pub fn to_nanos(d: Duration) -> u64 {
+  d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64
+}
+
+pub fn from_nanos(n: u64) -> Duration {
+  Duration::new(n / 1_000_000_000, (n % 1_000_000_000) as u32)
+}
+
+
+/// Sends `options.count` echo requests to `dst`, calling `each` with the
+/// sequence number and outcome of each
+pub fn ping<'a, A, E, F>(state:   &super::State<'a, A, E>,
+                         dst:     Addr,
+                         options: &Options,
+                         mut each: F)
+                         -> Stats
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a,
+        F: FnMut(u16, &Outcome<E>)
+{
+  let watcher = state.icmp.watch();
+  let id = watcher.id() as u16;
+  let payload: Vec<u8> = (0..options.size).map(|i| i as u8).collect();
+
+  let (mut transmitted, mut errors, mut rtts) = (0, 0, Vec::new());
+
+  for i in 0..options.count {
+    let seq = i as u16;
+    let started = Instant::now();
+
+    let outcome = match send_echo(state, dst, id, seq, options.ttl, &payload[..]) {
+      Err(e) => Outcome::NotSent(e),
+      Ok(()) => {
+        transmitted += 1;
+        wait_for(&watcher, id, seq, started, options.timeout)
+      },
+    };
+    match outcome {
+      Outcome::Reply { rtt, .. } => rtts.push(rtt),
+      Outcome::Error { .. }      => errors += 1,
+      _                          => (),
+    };
+    each(seq, &outcome);
+
+    if i + 1 < options.count {
+      let elapsed = started.elapsed();
+      if elapsed < options.interval {
+        thread::sleep(options.interval - elapsed);
+      }
+    }
+  }
+
+  Stats::new(transmitted, errors, &rtts[..])
+}
+
+/// Sends one echo request, with the given TTL
+pub fn send_echo<'a, A, E>(state:   &super::State<'a, A, E>,
+                           dst:     Addr,
+                           id:      u16,
+                           seq:     u16,
+                           ttl:     u8,
+                           payload: &[u8])
+                           -> send::Result<(), E>
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: 'a
+{
+  let message = icmp::Message {
+    kind: icmp::ECHO_REQUEST,
+    code: 0,
+    rest: [(id >> 8) as u8, id as u8, (seq >> 8) as u8, seq as u8],
+    data: payload,
+  };
+  send::send(
+    state,
+    dst,
+    icmp::PROTOCOL,
+    Some((icmp::HDR_LEN + payload.len()) as u16),
+    |packet| {
+      message.write(packet.as_mut_vec());
+      packet.borrow_mut().set_time_to_live(ttl);
+      Ok(())
+    },
+    |_| Ok(()))
+}
+
+/// Waits for the reply to, or an error about, the given echo request
+pub fn wait_for<E>(watcher: &icmp::Watcher,
+                   id:      u16,
+                   seq:     u16,
+                   started: Instant,
+                   timeout: Duration)
+                   -> Outcome<E>
+{
+  loop {
+    let elapsed = started.elapsed();
+    if elapsed >= timeout {
+      return Outcome::Timeout;
+    }
+    let received = match watcher.rx.recv_timeout(timeout - elapsed) {
+      Ok(r)                               => r,
+      Err(RecvTimeoutError::Timeout)      => return Outcome::Timeout,
+      Err(RecvTimeoutError::Disconnected) => return Outcome::Timeout,
+    };
+    let message = received.message();
+
+    if message.kind == icmp::ECHO_REPLY && message.echo_id_seq() == (id, seq) {
+      return Outcome::Reply {
+        from:  received.src,
+        bytes: icmp::HDR_LEN + message.data.len(),
+        ttl:   received.ttl,
+        rtt:   received.at.duration_since(started),
+      };
+    }
+    if message.is_error() && quotes_echo(message.data, id, seq) {
+      return Outcome::Error {
+        from:  received.src,
+        kind:  message.kind,
+        code:  message.code,
+        rtt:   received.at.duration_since(started),
+      };
+    }
+    // somebody else's, or late
+  }
+}
+
+/// Whether the packet quoted by an ICMP error is the given echo request
+fn quotes_echo(quoted: &[u8], id: u16, seq: u16) -> bool {
+  // only the start of the packet is quoted, so it is short of its length
+  let original = match packet::Ipv4Packet::new_checked(quoted) {
+    Ok(original) => original,
+    Err(_)       => return false,
+  };
+  let echo = original.payload();
+  if original.protocol() != icmp::PROTOCOL || echo.len() < icmp::HDR_LEN {
+    return false;
+  }
+  echo[0] == icmp::ECHO_REQUEST
+    && ((echo[4] as u16) << 8 | echo[5] as u16) == id
+    && ((echo[6] as u16) << 8 | echo[7] as u16) == seq
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::time::Duration;
+
+  use super::Stats;
+
+  #[test]
+  fn mdev_is_standard_deviation() {
+    let rtts = [Duration::from_millis(1), Duration::from_millis(3)];
+    let stats = Stats::new(3, 0, &rtts[..]);
+    assert_eq!(stats.avg, Duration::from_millis(2));
+    assert_eq!(stats.mdev, Duration::from_millis(1));
+    assert_eq!(stats.loss(), 33);
+
+    let stats = Stats::new(1, 0, &[Duration::from_millis(5)][..]);
+    assert_eq!(stats.mdev, Duration::from_millis(0));
+  }
+}
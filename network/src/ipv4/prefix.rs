@@ -0,0 +1,295 @@
+//! CIDR prefixes, and tables keyed on them which find the longest prefix
+//! matching an address, as routing tables want
+
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+use super::Addr;
+
+
+/// An address and how many of its leading bits matter, e.g. `10.0.0.0/8`
+#[derive(PartialEq, PartialOrd, Eq, Ord,
+         Copy, Clone, Hash, Debug)]
+pub struct Prefix {
+  addr: Addr,
+  len:  u8,
+}
+
+impl Prefix {
+  /// The bits of `addr` past `len` are cleared. `None` if `len` is over 32.
+  pub fn new(addr: Addr, len: u8) -> Option<Prefix> {
+    if len > 32 {
+      return None;
+    }
+    Some(Prefix { addr: addr.mask(len), len: len })
+  }
+
+  /// Just the one address
+  pub fn host(addr: Addr) -> Prefix {
+    Prefix { addr: addr, len: 32 }
+  }
+
+  /// Every address
+  pub fn any() -> Prefix {
+    Prefix { addr: super::UNSPECIFIED, len: 0 }
+  }
+
+  pub fn addr(&self) -> Addr {
+    self.addr
+  }
+
+  pub fn len(&self) -> u8 {
+    self.len
+  }
+
+  /// The netmask, e.g. `255.255.255.0` for a /24
+  pub fn mask(&self) -> Addr {
+    Addr::netmask(self.len)
+  }
+
+  pub fn contains(&self, addr: Addr) -> bool {
+    addr.mask(self.len) == self.addr
+  }
+
+  /// Bit `i` of the address, counting from the most significant
+  fn bit(&self, i: u8) -> usize {
+    (u32::from(self.addr) >> (31 - i as u32)) as usize & 1
+  }
+}
+
+impl fmt::Display for Prefix {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}/{}", self.addr, self.len)
+  }
+}
+
+impl FromStr for Prefix {
+  type Err = ();
+
+  /// `a.b.c.d/n`, or a bare address for a /32. Set host bits are an error,
+  /// as they usually mean a typo.
+  fn from_str(s: &str) -> Result<Prefix, ()> {
+    let s = s.trim();
+    let (addr, len) = match s.find('/') {
+      None        => (s, 32),
+      Some(slash) => (&s[..slash], u8::from_str(&s[slash + 1..]).map_err(|_| ())?),
+    };
+    let addr = Addr::from_str(addr).map_err(|_| ())?;
+    match Prefix::new(addr, len) {
+      Some(prefix) if prefix.addr == addr => Ok(prefix),
+      _                                   => Err(()),
+    }
+  }
+}
+
+
+#[derive(Debug)]
+struct Node<V> {
+  value:    Option<V>,
+  children: [Option<Box<Node<V>>>; 2],
+}
+
+impl<V> Node<V> {
+  fn new() -> Node<V> {
+    Node { value: None, children: [None, None] }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.value.is_none() && self.children[0].is_none() && self.children[1].is_none()
+  }
+}
+
+/// A map from prefixes, as a binary trie with a level per bit
+#[derive(Debug)]
+pub struct PrefixMap<V> {
+  root: Node<V>,
+  len:  usize,
+}
+
+impl<V> PrefixMap<V> {
+  pub fn new() -> PrefixMap<V> {
+    PrefixMap { root: Node::new(), len: 0 }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Returns the value the prefix had before, if any
+  pub fn insert(&mut self, prefix: Prefix, value: V) -> Option<V> {
+    let old = {
+      let mut node = &mut self.root;
+      for i in 0..prefix.len {
+        // moved out of, so that it can be reassigned
+        let parent = node;
+        let child = &mut parent.children[prefix.bit(i)];
+        if child.is_none() {
+          *child = Some(box Node::new());
+        }
+        node = &mut **child.as_mut().unwrap();
+      }
+      mem::replace(&mut node.value, Some(value))
+    };
+    if old.is_none() {
+      self.len += 1;
+    }
+    old
+  }
+
+  pub fn remove(&mut self, prefix: Prefix) -> Option<V> {
+    let old = remove(&mut self.root, prefix, 0);
+    if old.is_some() {
+      self.len -= 1;
+    }
+    old
+  }
+
+  /// The value for exactly this prefix
+  pub fn get(&self, prefix: Prefix) -> Option<&V> {
+    let mut node = &self.root;
+    for i in 0..prefix.len {
+      match node.children[prefix.bit(i)] {
+        Some(ref child) => node = &**child,
+        None            => return None,
+      }
+    }
+    node.value.as_ref()
+  }
+
+  /// The longest prefix containing the address, and its value
+  pub fn lookup(&self, addr: Addr) -> Option<(Prefix, &V)> {
+    let key = Prefix::host(addr);
+    let mut node = &self.root;
+    let mut best = node.value.as_ref().map(|v| (0, v));
+    for i in 0..32 {
+      match node.children[key.bit(i)] {
+        Some(ref child) => node = &**child,
+        None            => break,
+      }
+      if let Some(ref v) = node.value {
+        best = Some((i + 1, v));
+      }
+    }
+    best.map(|(len, v)| (Prefix::new(addr, len).unwrap(), v))
+  }
+
+  /// Every entry, shortest prefixes first and then by address
+  pub fn entries(&self) -> Vec<(Prefix, &V)> {
+    let mut entries = Vec::with_capacity(self.len);
+    collect(&self.root, 0, 0, &mut entries);
+    entries.sort_by(|a, b| (a.0.len, a.0.addr).cmp(&(b.0.len, b.0.addr)));
+    entries
+  }
+}
+
+fn remove<V>(node: &mut Node<V>, prefix: Prefix, depth: u8) -> Option<V> {
+  if depth == prefix.len {
+    return node.value.take();
+  }
+  let bit = prefix.bit(depth);
+  let (old, prune) = match node.children[bit] {
+    None            => return None,
+    Some(ref mut c) => {
+      let old = remove(c, prefix, depth + 1);
+      (old, c.is_empty())
+    },
+  };
+  // don't leave dead branches behind
+  if prune {
+    node.children[bit] = None;
+  }
+  old
+}
+
+fn collect<'a, V>(node: &'a Node<V>, bits: u32, depth: u8, out: &mut Vec<(Prefix, &'a V)>) {
+  if let Some(ref v) = node.value {
+    out.push((Prefix { addr: Addr::from(bits), len: depth }, v));
+  }
+  for b in 0..2 {
+    if let Some(ref child) = node.children[b] {
+      let bits = if b == 1 { bits | 1 << (31 - depth as u32) } else { bits };
+      collect(child, bits, depth + 1, out);
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::str::FromStr;
+
+  use super::super::Addr;
+  use super::*;
+
+  fn p(s: &str) -> Prefix {
+    Prefix::from_str(s).unwrap()
+  }
+
+  #[test]
+  fn parse_prefix() {
+    assert_eq!(p("10.0.0.0/8"), Prefix::new(Addr([10, 0, 0, 0]), 8).unwrap());
+    assert_eq!(p("10.1.2.3"), Prefix::host(Addr([10, 1, 2, 3])));
+    assert_eq!(p("0.0.0.0/0"), Prefix::any());
+    assert_eq!(p("192.168.1.0/24").to_string(), "192.168.1.0/24");
+    assert_eq!(p("192.168.1.0/24").mask(), Addr([255, 255, 255, 0]));
+
+    assert!(Prefix::from_str("10.0.0.1/8").is_err());
+    assert!(Prefix::from_str("10.0.0.0/33").is_err());
+    assert!(Prefix::from_str("10.0.0.0/").is_err());
+    assert!(Prefix::from_str("10.0.0/8").is_err());
+  }
+
+  #[test]
+  fn contains() {
+    assert!(p("10.0.0.0/8").contains(Addr([10, 200, 3, 4])));
+    assert!(!p("10.0.0.0/8").contains(Addr([11, 0, 0, 0])));
+    assert!(p("0.0.0.0/0").contains(Addr([1, 2, 3, 4])));
+    assert!(p("1.2.3.4/32").contains(Addr([1, 2, 3, 4])));
+    assert!(!p("1.2.3.4/32").contains(Addr([1, 2, 3, 5])));
+  }
+
+  #[test]
+  fn longest_match() {
+    let mut m = PrefixMap::new();
+    m.insert(p("0.0.0.0/0"), 'd');
+    m.insert(p("10.0.0.0/8"), 'a');
+    m.insert(p("10.1.0.0/16"), 'b');
+    m.insert(p("10.1.2.3/32"), 'c');
+    assert_eq!(m.len(), 4);
+
+    assert_eq!(m.lookup(Addr([10, 1, 2, 3])), Some((p("10.1.2.3/32"), &'c')));
+    assert_eq!(m.lookup(Addr([10, 1, 2, 4])), Some((p("10.1.0.0/16"), &'b')));
+    assert_eq!(m.lookup(Addr([10, 2, 0, 0])), Some((p("10.0.0.0/8"), &'a')));
+    assert_eq!(m.lookup(Addr([11, 0, 0, 0])), Some((p("0.0.0.0/0"), &'d')));
+
+    assert_eq!(m.remove(p("0.0.0.0/0")), Some('d'));
+    assert_eq!(m.lookup(Addr([11, 0, 0, 0])), None);
+    assert_eq!(m.remove(p("10.1.0.0/16")), Some('b'));
+    assert_eq!(m.remove(p("10.1.0.0/16")), None);
+    assert_eq!(m.lookup(Addr([10, 1, 2, 4])), Some((p("10.0.0.0/8"), &'a')));
+    assert_eq!(m.get(p("10.1.2.3/32")), Some(&'c'));
+    assert_eq!(m.len(), 2);
+  }
+
+  #[test]
+  fn entries_in_order() {
+    let mut m = PrefixMap::new();
+    for (i, s) in ["10.1.0.0/16", "10.0.0.0/8", "9.0.0.0/8", "10.1.2.3/32"].iter().enumerate() {
+      assert_eq!(m.insert(p(s), i), None);
+    }
+    assert_eq!(m.insert(p("9.0.0.0/8"), 7), Some(2));
+
+    let entries: Vec<(Prefix, usize)> = m.entries().into_iter().map(|(p, v)| (p, *v)).collect();
+    assert_eq!(entries, vec![
+      (p("9.0.0.0/8"), 7),
+      (p("10.0.0.0/8"), 1),
+      (p("10.1.0.0/16"), 0),
+      (p("10.1.2.3/32"), 3),
+    ]);
+  }
+}
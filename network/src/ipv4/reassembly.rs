@@ -0,0 +1,313 @@
+//! Putting fragmented datagrams back together, per RFC 791 and RFC 815
+//!
+//! Where fragments overlap, the bytes which arrived first are kept. A
+//! datagram which is not complete within the timeout is dropped, as are the
+//! oldest datagrams when the fragments held would exceed the memory limit.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use misc::pool::Pool;
+
+use super::Addr;
+use super::icmp;
+use super::packet::{self, A, V};
+
+
+/// How long a datagram may take to arrive in full, in seconds
+pub const TIMEOUT:   u64   = 30;
+/// How many bytes of fragments may be held at once
+pub const MAX_BYTES: usize = 256 * 1024;
+
+/// The longest a datagram can be, header included
+const MAX_LEN: usize = 0xffff;
+
+/// Fragments belong to the same datagram iff these are all the same
+#[derive(PartialEq, Eq, Copy, Clone, Hash, Debug)]
+pub struct Key {
+  pub src:      Addr,
+  pub dst:      Addr,
+  pub protocol: u8,
+  pub id:       u16,
+}
+
+impl Key {
+  pub fn of(packet: &A) -> Key {
+    Key {
+      src:      packet.get_source(),
+      dst:      packet.get_destination(),
+      protocol: packet.get_protocol(),
+      id:       packet.get_identification(),
+    }
+  }
+}
+
+/// Whether the packet is only part of a datagram
+pub fn is_fragment(packet: &A) -> bool {
+  let (flags, offset) = packet.get_flags_fragment_offset();
+  offset != 0 || flags.contains(packet::MORE_FRAGMENTS)
+}
+
+struct Partial {
+  started: Instant,
+  /// The header of the first fragment, once it has arrived
+  header:  Option<Vec<u8>>,
+  /// Payload pieces by where they start. They never overlap.
+  pieces:  BTreeMap<usize, Vec<u8>>,
+  /// Length of the whole payload, once the last fragment has arrived
+  total:   Option<usize>,
+  /// Bytes held, headers included
+  bytes:   usize,
+}
+
+impl Partial {
+  fn new() -> Partial {
+    Partial {
+      started: Instant::now(),
+      header:  None,
+      pieces:  BTreeMap::new(),
+      total:   None,
+      bytes:   0,
+    }
+  }
+
+  /// The parts of `start..end` no piece yet covers
+  fn gaps(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut gaps = Vec::new();
+    let mut at = start;
+    for (&s, piece) in self.pieces.iter() {
+      let e = s + piece.len();
+      if e <= at {
+        continue;
+      }
+      if s >= end {
+        break;
+      }
+      if s > at {
+        gaps.push((at, s));
+      }
+      at = e;
+    }
+    if at < end {
+      gaps.push((at, end));
+    }
+    gaps
+  }
+
+  /// Whether the first fragment's header and the whole payload would be
+  /// longer than the total length can say
+  fn is_too_long(&self) -> bool {
+    match (&self.header, self.total) {
+      (&Some(ref header), Some(total)) => header.len() + total > MAX_LEN,
+      _                                => false,
+    }
+  }
+
+  fn is_complete(&self) -> bool {
+    match (&self.header, self.total) {
+      (&Some(_), Some(total)) =>
+        self.pieces.values().map(|p| p.len()).fold(0, |a, b| a + b) == total,
+      _ => false,
+    }
+  }
+
+  /// Joins the pieces after the first fragment's header, which is fixed up
+  /// to describe the whole datagram
+  fn assemble(self, pool: &Pool) -> V {
+    let mut buf = self.header.expect("only complete datagrams are assembled");
+    for (_, piece) in self.pieces {
+      buf.extend_from_slice(&piece[..]);
+      pool.give(piece);
+    }
+    let mut datagram = V::new(buf);
+    {
+      let s = datagram.borrow_mut();
+      let (flags, _) = s.get_flags_fragment_offset();
+      s.set_flags_fragment_offset(flags - packet::MORE_FRAGMENTS, 0);
+      let len = s.as_slice().len() as u16;
+      s.set_total_length(len);
+      s.update_checksum();
+    }
+    datagram
+  }
+
+  /// The header and the start of the payload, as ICMP errors quote them,
+  /// if the first fragment has arrived
+  fn start(&self) -> Option<Vec<u8>> {
+    self.header.as_ref().map(|header| {
+      let mut start = header.clone();
+      if let Some(piece) = self.pieces.get(&0) {
+        start.extend_from_slice(&piece[..cmp::min(piece.len(), icmp::QUOTED_PAYLOAD)]);
+      }
+      start
+    })
+  }
+
+  fn release(self, pool: &Pool) {
+    if let Some(header) = self.header {
+      pool.give(header);
+    }
+    for (_, piece) in self.pieces {
+      pool.give(piece);
+    }
+  }
+}
+
+/// Datagrams still waiting on some of their fragments
+pub struct Reassembler {
+  partials:  HashMap<Key, Partial>,
+  bytes:     usize,
+  max_bytes: usize,
+  timeout:   Duration,
+}
+
+impl Reassembler {
+  pub fn new() -> Reassembler {
+    Reassembler::with_limits(MAX_BYTES, Duration::from_secs(TIMEOUT))
+  }
+
+  pub fn with_limits(max_bytes: usize, timeout: Duration) -> Reassembler {
+    Reassembler {
+      partials:  HashMap::new(),
+      bytes:     0,
+      max_bytes: max_bytes,
+      timeout:   timeout,
+    }
+  }
+
+  /// How many datagrams are incomplete
+  pub fn len(&self) -> usize {
+    self.partials.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.partials.is_empty()
+  }
+
+  /// How many bytes of fragments are held
+  pub fn bytes(&self) -> usize {
+    self.bytes
+  }
+
+  /// Takes a fragment, returning the whole datagram if this was the last
+  /// missing piece of it. Buffers no longer needed, the fragment's included,
+  /// are given to `pool`.
+  pub fn add(&mut self, fragment: V, pool: &Pool) -> Option<V> {
+    self.expire(pool);
+
+    let key = Key::of(fragment.borrow());
+    let (more, start, hdr_len, len) = {
+      let f = fragment.borrow();
+      let (flags, offset) = f.get_flags_fragment_offset();
+      (flags.contains(packet::MORE_FRAGMENTS),
+       offset as usize * 8,
+       f.hdr_bytes(),
+       f.get_payload().len())
+    };
+    let end = start + len;
+
+    // all but the last fragment carry a multiple of 8 bytes
+    if (more && len % 8 != 0) || hdr_len + end > MAX_LEN {
+      debug!("dropping malformed fragment of {:?}", key);
+      pool.give(fragment.to_vec());
+      return None;
+    }
+
+    let mut partial = self.partials.remove(&key).unwrap_or_else(Partial::new);
+    self.bytes -= partial.bytes;
+
+    // the last fragment decides the length, and the others must fit in it
+    let bad_end = match partial.total {
+      Some(total) => end > total || (!more && end != total),
+      None        => !more && partial.pieces.iter().any(|(&s, p)| s + p.len() > end),
+    };
+    if bad_end {
+      debug!("dropping {:?}, as its fragments disagree on its length", key);
+      partial.release(pool);
+      pool.give(fragment.to_vec());
+      return None;
+    }
+    if !more {
+      partial.total = Some(end);
+    }
+
+    if start == 0 && partial.header.is_none() {
+      let mut header = pool.take();
+      header.extend_from_slice(&fragment.as_vec()[..hdr_len]);
+      partial.bytes += header.len();
+      partial.header = Some(header);
+    }
+    for (s, e) in partial.gaps(start, end) {
+      let mut piece = pool.take();
+      piece.extend_from_slice(&fragment.borrow().get_payload()[s - start..e - start]);
+      partial.bytes += piece.len();
+      partial.pieces.insert(s, piece);
+    }
+    pool.give(fragment.to_vec());
+
+    // the first fragment's header may be longer than the one checked above
+    if partial.is_too_long() {
+      debug!("dropping {:?}, as it would be too long in one piece", key);
+      partial.release(pool);
+      return None;
+    }
+    if partial.is_complete() {
+      return Some(partial.assemble(pool));
+    }
+
+    self.bytes += partial.bytes;
+    self.partials.insert(key, partial);
+    while self.bytes > self.max_bytes {
+      self.evict_oldest(pool);
+    }
+    None
+  }
+
+  /// Drops the datagrams which have been waiting too long, returning how
+  /// many there were
+  pub fn expire(&mut self, pool: &Pool) -> usize {
+    self.expire_with(pool, |_| ())
+  }
+
+  /// Like `expire`, but shows `report` the start of each dropped datagram
+  /// whose first fragment had arrived, so its sender can be told (RFC 792)
+  pub fn expire_with<F>(&mut self, pool: &Pool, mut report: F) -> usize
+    where F: FnMut(&A)
+  {
+    let timeout = self.timeout;
+    let expired: Vec<Key> = self.partials.iter()
+      .filter(|&(_, p)| p.started.elapsed() >= timeout)
+      .map(|(k, _)| *k)
+      .collect();
+    for key in expired.iter() {
+      debug!("reassembly of {:?} timed out", key);
+      if let Some(partial) = self.partials.remove(key) {
+        self.bytes -= partial.bytes;
+        if let Some(start) = partial.start() {
+          report(A::new(&start[..]));
+        }
+        partial.release(pool);
+      }
+    }
+    expired.len()
+  }
+
+  fn evict_oldest(&mut self, pool: &Pool) {
+    let oldest = self.partials.iter()
+      .min_by_key(|&(_, p)| p.started)
+      .map(|(k, _)| *k);
+    if let Some(key) = oldest {
+      debug!("out of room for fragments, dropping {:?}", key);
+      self.drop_partial(&key, pool);
+    }
+  }
+
+  fn drop_partial(&mut self, key: &Key, pool: &Pool) {
+    if let Some(partial) = self.partials.remove(key) {
+      self.bytes -= partial.bytes;
+      partial.release(pool);
+    }
+  }
+}
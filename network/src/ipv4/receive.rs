@@ -2,92 +2,177 @@ use core::fmt::Debug;
 use std::sync::Arc;
 
 use super::{
+  icmp,
   packet,
+  reassembly,
   strategy,
   send
 };
 
 use data_link::interface as dl;
+use misc::pool::Pool;
 
-/// Called upon receipt of an IP packet:
-/// If packet is destined for this node, deliver it to appropriate handlers
-/// If packet is destined elsewhere, fix packet headers and forward
-fn receive<'a, A, E>(state: &super::State<'a, A, E>, buf: Vec<u8>)
+/// Called upon receipt of IP packets:
+/// If a packet is destined for this node, deliver it to appropriate handlers
+/// If a packet is destined elsewhere, fix packet headers and forward
+///
+/// The protocol handlers are locked just once for the whole batch.
+fn receive_batch<'a, A, E>(state:     &super::State<'a, A, E>,
+                          interface: usize,
+                          bufs:      Vec<Vec<u8>>)
   where A: strategy::RoutingTable<'a> + 'a,
-        E: Debug
+        E: Debug + 'a
 {
-  debug!("Received packet.");
-  let packet = match packet::validate(buf.as_slice()) {
-    Ok(_)  => packet::V::new(buf),
-    Err(e) => {
-      debug!("dropping incomming packet because {:?}", e);
-      return;
-    },
-  };
+  // whether or not this batch has fragments, those held too long go
+  expire_fragments(state);
 
-  debug!("packet header:\n{}", packet.borrow());
+  let handlers = state.protocol_handlers.read().unwrap();
+  let counters = &state.interfaces[interface].counters;
 
-  if is_packet_dst_local(state, &packet) {
-    debug!("Packet is local! {}", packet);
-    // local handling
-    let handlers = &state.protocol_handlers.read().unwrap()
-      [packet.borrow().get_protocol() as usize];
-    // If there are no handlers (vector is empty), the packet is just dropped
-    // TODO: factor out this clone-until-last-time pattern
-    let mut iter = handlers.iter().peekable();
-    while let Some(ref handler) = iter.next() {
-      if iter.peek().is_none() {
-        handler(packet);
-        break;
+  for buf in bufs {
+    debug!("Received packet.");
+    counters.received(buf.len());
+    let packet = match packet::validate(buf.as_slice()) {
+      Ok(_)  => packet::V::new(buf),
+      Err(e) => {
+        debug!("dropping incomming packet because {:?}", e);
+        counters.validation_failure();
+        state.pool.give(buf);
+        continue;
+      },
+    };
+
+    debug!("packet header:\n{}", packet.borrow());
+
+    if is_packet_dst_local(state, &packet) {
+      debug!("Packet is local! {}", packet);
+      // fragments are held until the whole datagram is here
+      let packet = if reassembly::is_fragment(packet.borrow()) {
+        let datagram = state.reassembly.lock().unwrap().add(packet, &state.pool);
+        match datagram {
+          Some(datagram) => datagram,
+          None           => continue,
+        }
       } else {
-        handler(packet.clone());
+        packet
+      };
+      // local handling
+      let protocol = packet.borrow().get_protocol() as usize;
+      if handlers[protocol].is_empty() {
+        icmp::send_unreachable(state, packet.borrow(), icmp::unreachable::PROTOCOL);
       }
+      deliver(&handlers[protocol], &state.pool, packet);
+    } else {
+      debug!("packet is not local! {}", packet);
+      // handle errors just for logging purposes
+      match forward(state, packet) {
+        Ok(_) => (),
+        Err(e) => debug!("packet could not be fowarded because {:?}", e),
+      };
+    }
+  }
+}
+
+/// Drops the datagrams which took too long to arrive in full, telling their
+/// senders
+fn expire_fragments<'a, A, E>(state: &super::State<'a, A, E>)
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
+{
+  let mut timed_out = Vec::new();
+  {
+    let mut reassembly = state.reassembly.lock().unwrap();
+    if reassembly.is_empty() {
+      return;
+    }
+    reassembly.expire_with(&state.pool, |start| timed_out.push(start.as_slice().to_vec()));
+  }
+  // not while locked, as the errors might find their way back here
+  for start in timed_out {
+    icmp::send_time_exceeded(state, packet::A::new(&start[..]), icmp::exceeded::REASSEMBLY);
+  }
+}
+
+/// Hands a local packet to every handler for its protocol
+fn deliver(handlers: &[super::Handler], pool: &Pool, packet: packet::V) {
+  // If there are no handlers (vector is empty), the packet is just dropped
+  if handlers.is_empty() {
+    pool.give(packet.to_vec());
+    return;
+  }
+  // TODO: factor out this clone-until-last-time pattern
+  let mut iter = handlers.iter().peekable();
+  while let Some(ref handler) = iter.next() {
+    if iter.peek().is_none() {
+      handler(packet);
+      break;
+    } else {
+      handler(packet.clone());
     }
-    /*
-    match iter.next() {
-      None => (),
-      Some(mut handler) => loop {
-        match iter.next() {
-          None         => {
-            // no clone needed!
-            (&**handler).call((packet,));
-            break;
-          }
-          Some(h_next) => {
-            (&**handler).call((packet.clone(),));
-            handler = h_next;
-          }
+  }
+  /*
+  match iter.next() {
+    None => (),
+    Some(mut handler) => loop {
+      match iter.next() {
+        None         => {
+          // no clone needed!
+          (&**handler).call((packet,));
+          break;
+        }
+        Some(h_next) => {
+          (&**handler).call((packet.clone(),));
+          handler = h_next;
         }
       }
-    }*/
-  } else {
-    debug!("packet is not local! {}", packet);
-    // handle errors just for logging purposes
-    match forward(state, packet) {
-      Ok(_) => (),
-      Err(e) => debug!("packet could not be fowarded because {:?}", e),
-    };
-  }
+    }
+  }*/
 }
 
 /// Forwards a packet back into the network after rewriting its headers
 /// Result status is whether packet was able to be forwarded
 fn forward<'a, A, E>(state: &super::State<'a, A, E>, mut packet: packet::V) -> send::Result<(), E>
-  where A: strategy::RoutingTable<'a> + 'a
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a
 {
-  { // Decrement TTL
-    let ttl = packet.borrow().get_time_to_live() - 1;
-    if ttl == 0 { return Ok(()); }
-    packet.borrow_mut().set_time_to_live(ttl);
+  // Errors quote the packet as it was received, so it is left alone until
+  // it is sure to go
+  let ttl = packet.borrow().get_time_to_live();
+  if ttl <= 1 { // the TTL must not reach 0 on the way out
+    icmp::send_time_exceeded(state, packet.borrow(), icmp::exceeded::TTL);
+    state.pool.give(packet.to_vec());
+    return Ok(());
+  }
+  let dst = packet.borrow().get_destination();
+  let (next_hop, row) = match send::resolve_next_hop(state, dst) {
+    Ok(x)  => x,
+    Err(e) => {
+      if let send::Error::NoRoute = e {
+        icmp::send_unreachable(state, packet.borrow(), icmp::unreachable::NET);
+      }
+      state.pool.give(packet.to_vec());
+      return Err(e);
+    },
+  };
+  { // Too large for the way out, and may not be split up
+    let mtu = row.interface.read().unwrap().mtu();
+    let len = packet.as_vec().len();
+    let (flags, _) = packet.borrow().get_flags_fragment_offset();
+    if len > mtu && flags.contains(packet::DONT_FRAGMENT) {
+      icmp::send_fragmentation_needed(state, packet.borrow(), mtu);
+      row.counters.send_failure();
+      state.pool.give(packet.to_vec());
+      return Err(send::Error::PacketTooLarge { mtu: mtu, len: len });
+    }
   }
-  { // Update checksum
-    packet.borrow_mut().update_checksum();
+  { // Decrement TTL, record the way out if asked, then update checksum
+    let s = packet.borrow_mut();
+    s.set_time_to_live(ttl - 1);
+    s.record_route(row.local_ip);
+    s.update_checksum();
   }
-  let row = try!(send::resolve_route(
-    state,
-    packet.borrow_mut().get_destination()));
   // Do NOT update src address
-  try!(send::send_manual(row, packet));
+  try!(send::send_manual_via(row, next_hop, packet));
   Ok(())
 }
 
@@ -102,12 +187,27 @@ fn is_packet_dst_local<'a, A, E>(state: &super::State<'a, A, E>, packet: &packet
     .any(|&super::InterfaceRow { local_ip, .. }| local_ip == dst)
 }
 
-pub fn make_receive_callback<'a, A, E>(state: Arc<super::State<'a, A, E>>) -> dl::Handler
+/// `interface` is the index of the interface the callback is for
+pub fn make_receive_callback<'a, A, E>(state:     Arc<super::State<'a, A, E>>,
+                                      interface: usize)
+                                      -> dl::Handler
   where A: strategy::RoutingTable<'a> + Send + 'a,
         E: Debug + 'a
 {
   let state = state.clone();
   box move |packet: dl::Packet | {
-    receive(&*state, packet);
+    receive_batch(&*state, interface, vec![packet]);
+  }
+}
+
+/// `interface` is the index of the interface the callback is for
+pub fn make_batch_receive_callback<'a, A, E>(state:     Arc<super::State<'a, A, E>>,
+                                            interface: usize)
+                                            -> dl::BatchHandler
+  where A: strategy::RoutingTable<'a> + Send + 'a,
+        E: Debug + 'a
+{
+  box move |packets: Vec<dl::Packet>| {
+    receive_batch(&*state, interface, packets);
   }
 }
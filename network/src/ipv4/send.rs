@@ -13,6 +13,9 @@ use data_link::interface as dl;
 pub enum Error<E> {
   NoRoute,
   BadPacket(packet::BadPacket),
+  /// The packet, of the given length, does not fit through the interface
+  /// with the given MTU, and may not be fragmented
+  PacketTooLarge { mtu: usize, len: usize },
   External(dl::Error<E>),
 }
 
@@ -42,28 +45,35 @@ pub fn send
         G:  for<'b> FnOnce(&'b mut packet::V) -> result::Result<(), E> + 'st,
 {
   let closure //: for<'p> |&'p mut packet::V| ->
-    = move |packet: &mut packet::V| -> result::Result<&'st super::InterfaceRow<'a, DE>, E> {
+    = move |packet: &mut packet::V|
+             -> result::Result<(super::Addr, &'st super::InterfaceRow<'a, DE>), E>
+    {
       try!(builder(packet));
       debug!("client built packet: {}", packet);
 
-      let row = try!(resolve_route(state, dst));
-      packet.borrow_mut().set_source(row.local_ip);
+      let (next_hop, row) = try!(resolve_next_hop(state, dst));
+      {
+        let s = packet.borrow_mut();
+        s.set_source(row.local_ip);
+        s.set_identification(state.identifiers.next(row.local_ip, dst, protocol));
+      }
 
       // TCP needs to hook in here for checksum of "virtual header"
       // awkward layer violation is awkward
       try!(awkward(packet));
 
-      Ok(row)
+      Ok((next_hop, row))
     };
 
-  let (row, packet) = try!(packet::V::new_with_builder::<E, &'st super::InterfaceRow<DE>, _>(
+  let ((next_hop, row), packet) = try!(packet::V::new_with_builder_in::<E, (super::Addr, &'st super::InterfaceRow<DE>), _>(
+    state.pool.take(),
     dst,
     protocol,
     expected_body_size,
     closure));
 
   // final try to do from_error
-  try!(send_manual(row, packet).map_err(|e| Error::External(e)));
+  try!(send_manual_via(row, next_hop, packet));
   Ok(())
 }
 
@@ -74,6 +84,18 @@ pub fn resolve_route<'a, 'st, A, E>(state: &'st super::State<'a, A, E>,
                                    -> self::Result<&'st super::InterfaceRow<'a, E>, E>
   where A: strategy::RoutingTable<'a> + 'a,
         'a: 'st
+{
+  resolve_next_hop(state, dst).map(|(_, row)| row)
+}
+
+/// Like `resolve_route`, but also returns the neighbor the packet should be
+/// handed to, which links with several neighbors need to know
+pub fn resolve_next_hop<'a, 'st, A, E>(state: &'st super::State<'a, A, E>,
+                                      dst:   super::Addr)
+                                      -> self::Result<(super::Addr,
+                                                       &'st super::InterfaceRow<'a, E>), E>
+  where A: strategy::RoutingTable<'a> + 'a,
+        'a: 'st
 {
   match state.routes.lookup(dst) {
     None           => Err(self::Error::NoRoute),
@@ -82,7 +104,7 @@ pub fn resolve_route<'a, 'st, A, E>(state: &'st super::State<'a, A, E>,
       match state.neighbors.get(&next_hop) {
         None => panic!("IP: Route's next hop is not a neighbor!"),
         // Tell interface to send packet bytes
-        Some(index) => Ok(&state.interfaces[*index])
+        Some(index) => Ok((next_hop, &state.interfaces[*index]))
       }
     }
   }
@@ -91,15 +113,71 @@ pub fn resolve_route<'a, 'st, A, E>(state: &'st super::State<'a, A, E>,
 
 /// For anybody that wants to do their own routing
 /// and set their own checksum
+///
+/// The packet is handed to its destination directly, which must therefore be
+/// a neighbor.
 pub fn send_manual<E>(
   row:            &super::InterfaceRow<E>,
   packet:         packet::V)
-  -> dl::Result<(), E>
+  -> self::Result<(), E>
+{
+  let dst = packet.borrow().get_destination();
+  send_manual_via(row, dst, packet)
+}
+
+/// Like `send_manual`, but hands the packet to the given neighbor
+///
+/// Packets too large for the interface are fragmented, unless they have
+/// `DONT_FRAGMENT` set.
+pub fn send_manual_via<E>(
+  row:            &super::InterfaceRow<E>,
+  next_hop:       super::Addr,
+  packet:         packet::V)
+  -> self::Result<(), E>
 {
-  let &super::InterfaceRow { ref interface, .. } = row;
   // need to let here because send consumes packet
   let dst = packet.borrow().get_destination();
-  try!(interface.write().unwrap().send(packet.to_vec()));
-  debug!("sent packet to {}", dst);
+  // sending only needs a shared reference, and a read lock keeps drivers
+  // which deliver synchronously from deadlocking on replies
+  let interface = row.interface.read().unwrap();
+  let (mtu, len) = (interface.mtu(), packet.as_vec().len());
+  if len <= mtu {
+    try!(send_one(&**interface, &row.counters, next_hop, packet));
+  } else {
+    let fragments = match packet::fragment(packet.borrow(), mtu) {
+      Some(fragments) => fragments,
+      None            => {
+        row.counters.send_failure();
+        return Err(Error::PacketTooLarge { mtu: mtu, len: len });
+      },
+    };
+    debug!("packet of {} bytes split into {} fragments", len, fragments.len());
+    for fragment in fragments {
+      try!(send_one(&**interface, &row.counters, next_hop, fragment));
+    }
+  }
+  debug!("sent packet to {} via {}", dst, next_hop);
+  Ok(())
+}
+
+fn send_one<'a, E>(
+  interface:      &(dl::Interface<'a, Error=E> + Send + Sync + 'a),
+  counters:       &super::stats::Counters,
+  next_hop:       super::Addr,
+  packet:         packet::V)
+  -> self::Result<(), E>
+{
+  let len = packet.as_vec().len();
+  match interface.send_to(super::write_addr(next_hop), packet.to_vec()) {
+    Ok(())                   => counters.sent(len),
+    Err(dl::Error::Disabled) => {
+      counters.disabled_drop();
+      return Err(Error::External(dl::Error::Disabled));
+    },
+    Err(e)                   => {
+      counters.send_failure();
+      return Err(Error::External(e));
+    },
+  };
   Ok(())
 }
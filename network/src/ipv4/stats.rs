@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// Running totals for one interface, updated as packets go by
+#[derive(Default, Debug)]
+pub struct Counters {
+  pub packets_sent:        AtomicUsize,
+  pub bytes_sent:          AtomicUsize,
+  pub packets_received:    AtomicUsize,
+  pub bytes_received:      AtomicUsize,
+  /// Outgoing packets refused because the interface was disabled
+  pub dropped_disabled:    AtomicUsize,
+  /// Outgoing packets the driver failed to send, or which were too large
+  pub send_failures:       AtomicUsize,
+  /// Incoming packets which were not valid IPv4
+  pub validation_failures: AtomicUsize,
+}
+
+#[inline]
+fn bump(counter: &AtomicUsize, by: usize) {
+  counter.fetch_add(by, Ordering::Relaxed);
+}
+
+impl Counters {
+  pub fn new() -> Counters {
+    Default::default()
+  }
+
+  pub fn sent(&self, len: usize) {
+    bump(&self.packets_sent, 1);
+    bump(&self.bytes_sent, len);
+  }
+
+  pub fn received(&self, len: usize) {
+    bump(&self.packets_received, 1);
+    bump(&self.bytes_received, len);
+  }
+
+  pub fn disabled_drop(&self) {
+    bump(&self.dropped_disabled, 1);
+  }
+
+  pub fn send_failure(&self) {
+    bump(&self.send_failures, 1);
+  }
+
+  pub fn validation_failure(&self) {
+    bump(&self.validation_failures, 1);
+  }
+
+  /// Reads every counter. They are not read all at once, so with traffic
+  /// going by they may be slightly out of step with each other.
+  pub fn snapshot(&self) -> Stats {
+    let get = |c: &AtomicUsize| c.load(Ordering::Relaxed);
+    Stats {
+      packets_sent:        get(&self.packets_sent),
+      bytes_sent:          get(&self.bytes_sent),
+      packets_received:    get(&self.packets_received),
+      bytes_received:      get(&self.bytes_received),
+      dropped_disabled:    get(&self.dropped_disabled),
+      send_failures:       get(&self.send_failures),
+      validation_failures: get(&self.validation_failures),
+    }
+  }
+}
+
+
+/// The counters of an interface at some point in time
+#[derive(PartialEq, Eq, Default,
+         Copy, Clone, Hash, Debug)]
+pub struct Stats {
+  pub packets_sent:        usize,
+  pub bytes_sent:          usize,
+  pub packets_received:    usize,
+  pub bytes_received:      usize,
+  /// Packets refused, in either direction, because the interface was disabled
+  pub dropped_disabled:    usize,
+  pub send_failures:       usize,
+  pub validation_failures: usize,
+}
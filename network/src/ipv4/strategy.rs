@@ -11,6 +11,11 @@ pub trait RoutingTable<'a>: Send + Sync + Sized {
 
   fn monitor<E>(state: Arc<super::State<'a, Self, E>>) -> ();
 
+  /// Called when an interface goes up or down, be it through `control` or
+  /// because its driver noticed the link fail or recover. By default nothing
+  /// is done, and routes through the interface stay until they expire.
+  fn on_link_change<E>(_state: &super::State<'a, Self, E>, _interface: usize, _up: bool) {}
+
   fn dump(&self);
 
 }
@@ -0,0 +1,113 @@
+//! Finding the path packets take, like `traceroute`
+//!
+//! Echo requests go out with TTLs of 1, 2 and so on, and each router which
+//! runs a request out of hops reports so with a Time Exceeded message, so
+//! revealing itself. The trace stops once the destination answers, or some
+//! router reports it unreachable.
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use super::{
+  icmp,
+  ping,
+  strategy,
+};
+
+use super::Addr;
+use super::ping::Outcome;
+
+
+pub struct Options {
+  pub max_hops: u8,
+  /// Requests sent with each TTL
+  pub probes:   usize,
+  /// How long to wait on each probe
+  pub timeout:  Duration,
+  /// Bytes of payload in each request, after the ICMP header
+  pub size:     usize,
+}
+
+impl Default for Options {
+  fn default() -> Options {
+    Options {
+      max_hops: 30,
+      probes:   3,
+      timeout:  Duration::from_secs(1),
+      size:     32,
+    }
+  }
+}
+
+/// The probes sent with one TTL, and what became of them
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Hop<E> {
+  pub ttl:    u8,
+  pub probes: Vec<Outcome<E>>,
+}
+
+impl<E> Hop<E> {
+  /// Whoever answered, which had better be just one node
+  pub fn addr(&self) -> Option<Addr> {
+    self.probes.iter().filter_map(|o| match *o {
+      Outcome::Reply { from, .. } | Outcome::Error { from, .. } => Some(from),
+      _                                                         => None,
+    }).next()
+  }
+
+  /// Whether this is the last hop worth trying: the destination answered,
+  /// or it was reported unreachable, or nothing could be sent
+  pub fn is_last(&self) -> bool {
+    self.probes.iter().any(|o| match *o {
+      Outcome::Reply { .. }        => true,
+      Outcome::Error { kind, .. }  => kind != icmp::TIME_EXCEEDED,
+      Outcome::NotSent(_)          => true,
+      Outcome::Timeout             => false,
+    })
+  }
+}
+
+/// Traces the path to `dst`, calling `each` as each hop is done
+pub fn traceroute<'a, A, E, F>(state:    &super::State<'a, A, E>,
+                               dst:      Addr,
+                               options:  &Options,
+                               mut each: F)
+                               -> Vec<Hop<E>>
+  where A: strategy::RoutingTable<'a> + 'a,
+        E: Debug + 'a,
+        F: FnMut(&Hop<E>)
+{
+  let watcher = state.icmp.watch();
+  let id = watcher.id() as u16;
+  let payload: Vec<u8> = (0..options.size).map(|i| i as u8).collect();
+
+  let mut hops = Vec::new();
+  let mut seq: u16 = 0;
+
+  for ttl in 1..(options.max_hops as usize + 1) {
+    let ttl = ttl as u8;
+    let mut hop = Hop { ttl: ttl, probes: Vec::with_capacity(options.probes) };
+    for _ in 0..options.probes {
+      let started = Instant::now();
+      let outcome = match ping::send_echo(state, dst, id, seq, ttl, &payload[..]) {
+        Err(e) => Outcome::NotSent(e),
+        Ok(()) => ping::wait_for(&watcher, id, seq, started, options.timeout),
+      };
+      hop.probes.push(outcome);
+      seq = seq.wrapping_add(1);
+    }
+
+    each(&hop);
+    let last = hop.is_last();
+    hops.push(hop);
+    if last {
+      break;
+    }
+  }
+  hops
+}
+
+/// Who answered at each hop, `None` where nobody did
+pub fn path<E>(hops: &[Hop<E>]) -> Vec<Option<Addr>> {
+  hops.iter().map(|h| h.addr()).collect()
+}
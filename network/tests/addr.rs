@@ -0,0 +1,78 @@
+mod net {
+  pub extern crate network;
+}
+
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use net::network::ipv4::{self, Addr, AddrParseError};
+
+#[test]
+fn parse() {
+  assert_eq!(Addr::from_str("10.0.0.1"), Ok(Addr([10, 0, 0, 1])));
+  assert_eq!(Addr::from_str(" 255.255.255.255\n"), Ok(ipv4::BROADCAST));
+  assert_eq!(Addr::from_str("0.0.0.0"), Ok(ipv4::UNSPECIFIED));
+
+  assert_eq!(Addr::from_str("10.0.0.1.5"), Err(AddrParseError::Parts(5)));
+  assert_eq!(Addr::from_str("10.0.0"), Err(AddrParseError::Parts(3)));
+  assert_eq!(Addr::from_str(""), Err(AddrParseError::Parts(1)));
+  assert_eq!(Addr::from_str("10.0.256.1"), Err(AddrParseError::Octet(2)));
+  assert_eq!(Addr::from_str("10..0.1"), Err(AddrParseError::Octet(1)));
+  assert_eq!(Addr::from_str("+10.0.0.1"), Err(AddrParseError::Octet(0)));
+  assert_eq!(Addr::from_str("10.0.0.01"), Err(AddrParseError::Octet(3)));
+  assert_eq!(Addr::from_str("10.0.0.1 x"), Err(AddrParseError::Octet(3)));
+}
+
+#[test]
+fn conversions() {
+  let a = Addr([192, 168, 1, 20]);
+
+  assert_eq!(Ipv4Addr::from(a), Ipv4Addr::new(192, 168, 1, 20));
+  assert_eq!(Addr::from(Ipv4Addr::new(192, 168, 1, 20)), a);
+
+  assert_eq!(u32::from(a), 0xc0a80114);
+  assert_eq!(Addr::from(0xc0a80114u32), a);
+
+  let octets: [u8; 4] = a.into();
+  assert_eq!(Addr::from(octets), a);
+
+  // both ways agree with the standard library on the text too
+  assert_eq!(Ipv4Addr::from(a).to_string(), a.to_string());
+}
+
+#[test]
+fn masks() {
+  let a = Addr([192, 168, 1, 20]);
+
+  assert_eq!(Addr::netmask(0), ipv4::UNSPECIFIED);
+  assert_eq!(Addr::netmask(20), Addr([255, 255, 240, 0]));
+  assert_eq!(Addr::netmask(32), ipv4::BROADCAST);
+
+  assert_eq!(a.mask(24), Addr([192, 168, 1, 0]));
+  assert_eq!(a & Addr::netmask(16), Addr([192, 168, 0, 0]));
+  assert_eq!(a | !Addr::netmask(24), Addr([192, 168, 1, 255]));
+}
+
+#[test]
+fn classification() {
+  assert!(Addr([127, 0, 0, 1]).is_loopback());
+  assert!(!Addr([128, 0, 0, 1]).is_loopback());
+
+  assert!(Addr([224, 0, 0, 5]).is_multicast());
+  assert!(Addr([239, 255, 255, 255]).is_multicast());
+  assert!(!Addr([240, 0, 0, 1]).is_multicast());
+  assert!(Addr([240, 0, 0, 1]).is_reserved());
+
+  assert!(ipv4::BROADCAST.is_broadcast());
+  assert!(!ipv4::BROADCAST.is_reserved());
+  assert!(ipv4::UNSPECIFIED.is_unspecified());
+
+  for s in &["10.1.2.3", "172.16.0.1", "172.31.255.255", "192.168.0.1"] {
+    assert!(Addr::from_str(s).unwrap().is_private(), "{}", s);
+  }
+  for s in &["11.0.0.1", "172.15.0.1", "172.32.0.1", "192.169.0.1"] {
+    assert!(!Addr::from_str(s).unwrap().is_private(), "{}", s);
+  }
+
+  assert!(Addr([169, 254, 3, 4]).is_link_local());
+}
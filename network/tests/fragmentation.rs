@@ -0,0 +1,208 @@
+mod net {
+  pub extern crate misc;
+  pub extern crate network;
+}
+
+use std::thread;
+use std::time::Duration;
+
+use net::misc::pool::Pool;
+use net::network::ipv4;
+use net::network::ipv4::packet;
+use net::network::ipv4::reassembly::Reassembler;
+
+const MSG: &'static [u8] = b"fragments of a datagram, in several pieces";
+
+fn datagram() -> packet::V {
+  let (_, mut p) = packet::V::new_with_builder(ipv4::Addr([2,2,2,2]), 8, None, |p| {
+    p.as_mut_vec().extend_from_slice(MSG);
+    Ok::<(), ()>(())
+  }).unwrap();
+  p.borrow_mut().set_source(ipv4::Addr([1,1,1,1]));
+  p.borrow_mut().set_identification(7);
+  p.borrow_mut().update_checksum();
+  p
+}
+
+/// The fragment with its payload replaced
+fn tamper(fragment: &packet::V, with: u8) -> packet::V {
+  let mut f = fragment.clone();
+  for b in f.borrow_mut().get_payload_mut() {
+    *b = with;
+  }
+  f
+}
+
+#[test]
+fn fragment_headers() {
+  let original = datagram();
+  // 16 bytes of payload each
+  let fragments = packet::fragment(original.borrow(), 37).unwrap();
+  assert_eq!(fragments.len(), 3);
+
+  for (i, f) in fragments.iter().enumerate() {
+    let f = f.borrow();
+    packet::validate(f.as_slice()).unwrap();
+    assert_eq!(f.get_identification(), 7);
+    let (flags, offset) = f.get_flags_fragment_offset();
+    assert_eq!(offset, 2 * i as u16);
+    assert_eq!(flags.contains(packet::MORE_FRAGMENTS), i < 2);
+  }
+  assert_eq!(fragments[2].borrow().get_payload(), &MSG[32..]);
+
+  let mut df = original.clone();
+  df.borrow_mut().set_flags_fragment_offset(packet::DONT_FRAGMENT, 0);
+  assert!(packet::fragment(df.borrow(), 37).is_none());
+  // no room for 8 bytes of payload
+  assert!(packet::fragment(original.borrow(), 27).is_none());
+}
+
+#[test]
+fn offset_past_largest_datagram() {
+  let mut far = datagram();
+  far.borrow_mut().set_flags_fragment_offset(packet::IpFlags::empty(), 0x1fff);
+  far.borrow_mut().update_checksum();
+  assert_eq!(packet::validate(far.borrow().as_slice()),
+             Err(packet::BadPacket::FragmentTooFar(0x1fff, MSG.len())));
+  // splitting it again would need offsets past 13 bits
+  assert!(packet::fragment(far.borrow(), 37).is_none());
+
+  // ending right at the limit is fine
+  let mut last = datagram();
+  let offset = ((0xffff - MSG.len()) / 8) as u16;
+  last.borrow_mut().set_flags_fragment_offset(packet::IpFlags::empty(), offset);
+  last.borrow_mut().update_checksum();
+  packet::validate(last.borrow().as_slice()).unwrap();
+  for f in packet::fragment(last.borrow(), 37).unwrap() {
+    packet::validate(f.borrow().as_slice()).unwrap();
+  }
+}
+
+#[test]
+fn reassemble_out_of_order() {
+  let pool = Pool::new(64, 16);
+  let mut r = Reassembler::new();
+
+  let original = datagram();
+  let mut fragments = packet::fragment(original.borrow(), 28).unwrap();
+  fragments.reverse();
+  let last = fragments.pop().unwrap();
+  for f in fragments {
+    assert_eq!(r.add(f, &pool), None);
+  }
+  assert_eq!(r.len(), 1);
+
+  assert_eq!(r.add(last, &pool), Some(original));
+  assert!(r.is_empty());
+  assert_eq!(r.bytes(), 0);
+}
+
+#[test]
+fn first_arrival_wins_overlap() {
+  let pool = Pool::new(64, 16);
+  let mut r = Reassembler::new();
+
+  let original = datagram();
+  let small = packet::fragment(original.borrow(), 28).unwrap();
+  let large = packet::fragment(original.borrow(), 44).unwrap();
+
+  // the first 24 bytes, then garbage for 8 up to 40, overlapping them
+  assert_eq!(r.add(large[0].clone(), &pool), None);
+  for f in small[1..small.len() - 1].iter() {
+    assert_eq!(r.add(tamper(f, b'!'), &pool), None);
+  }
+  // the rest, in larger pieces
+  let mut got = None;
+  for f in large[1..].iter() {
+    got = r.add(f.clone(), &pool);
+  }
+
+  let got = got.unwrap();
+  assert_eq!(&got.borrow().get_payload()[..24], &MSG[..24]);
+  assert!(got.borrow().get_payload()[24..40].iter().all(|&b| b == b'!'));
+  assert_eq!(&got.borrow().get_payload()[40..], &MSG[40..]);
+}
+
+#[test]
+fn disagreeing_lengths_dropped() {
+  let pool = Pool::new(64, 16);
+  let mut r = Reassembler::new();
+
+  let fragments = packet::fragment(datagram().borrow(), 28).unwrap();
+  assert_eq!(r.add(fragments[3].clone(), &pool), None);
+  // claims to be last, but ends before a fragment we have
+  let mut short = fragments[1].clone();
+  let (_, offset) = short.borrow().get_flags_fragment_offset();
+  short.borrow_mut().set_flags_fragment_offset(packet::IpFlags::empty(), offset);
+  assert_eq!(r.add(short, &pool), None);
+  assert!(r.is_empty());
+}
+
+#[test]
+fn too_long_with_first_header() {
+  let pool = Pool::new(64, 16);
+  let mut r = Reassembler::new();
+
+  // ends where a bare header would still fit in front of the whole payload
+  let mut last = datagram();
+  last.borrow_mut().set_flags_fragment_offset(packet::IpFlags::empty(), 8184);
+  assert_eq!(r.add(last, &pool), None);
+  assert_eq!(r.len(), 1);
+
+  // but the first fragment's header has options
+  let (_, mut first) = packet::V::new_with_builder(ipv4::Addr([2,2,2,2]), 8, None, |p| {
+    p.as_mut_vec().extend_from_slice(&MSG[..8]);
+    p.set_options(&[packet::IpOption::record_route(1).unwrap()])
+  }).unwrap();
+  first.borrow_mut().set_source(ipv4::Addr([1,1,1,1]));
+  first.borrow_mut().set_identification(7);
+  first.borrow_mut().set_flags_fragment_offset(packet::MORE_FRAGMENTS, 0);
+  assert_eq!(r.add(first, &pool), None);
+  assert!(r.is_empty());
+  assert_eq!(r.bytes(), 0);
+}
+
+#[test]
+fn timeout() {
+  let pool = Pool::new(64, 16);
+  let mut r = Reassembler::with_limits(1 << 16, Duration::from_millis(10));
+
+  let fragments = packet::fragment(datagram().borrow(), 28).unwrap();
+  assert_eq!(r.add(fragments[0].clone(), &pool), None);
+  thread::sleep(Duration::from_millis(20));
+  assert_eq!(r.expire(&pool), 1);
+
+  // the rest is not enough any more
+  for f in fragments[1..].iter() {
+    assert_eq!(r.add(f.clone(), &pool), None);
+  }
+}
+
+#[test]
+fn memory_limit() {
+  let pool = Pool::new(64, 16);
+  let mut r = Reassembler::with_limits(60, Duration::from_secs(30));
+
+  let first = datagram();
+  let mut second = datagram();
+  second.borrow_mut().set_identification(8);
+  second.borrow_mut().update_checksum();
+
+  let f1 = packet::fragment(first.borrow(), 28).unwrap();
+  let f2 = packet::fragment(second.borrow(), 28).unwrap();
+
+  // 20 + 8 + 8 bytes held for the first, then the second pushes it out
+  assert_eq!(r.add(f1[0].clone(), &pool), None);
+  assert_eq!(r.add(f1[1].clone(), &pool), None);
+  thread::sleep(Duration::from_millis(1));
+  assert_eq!(r.add(f2[0].clone(), &pool), None);
+  assert_eq!(r.add(f2[1].clone(), &pool), None);
+  assert_eq!(r.len(), 1);
+  assert_eq!(r.bytes(), 36);
+
+  let mut got = None;
+  for f in f2[2..].iter() {
+    got = r.add(f.clone(), &pool);
+  }
+  assert_eq!(got, Some(second));
+}
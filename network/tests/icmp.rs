@@ -0,0 +1,352 @@
+#![feature(box_syntax)]
+
+mod net {
+  pub extern crate misc;
+
+  pub mod data_link {
+    pub extern crate channel;
+    pub extern crate udp_mock;
+  }
+
+  pub extern crate network;
+
+  pub mod transport {
+    pub extern crate static_routing;
+  }
+}
+
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver};
+
+use net::misc::SenderClosure;
+use net::data_link::channel::{Error, Queue};
+use net::network::ipv4::{self, control, icmp, packet, ping, send, InterfaceRow, State};
+use net::network::ipv4::icmp::Message;
+use net::transport::static_routing::StaticTable;
+
+type Node = Arc<State<'static, StaticTable, Error>>;
+
+const IA1: ipv4::Addr = ipv4::Addr([1,1,1,1]);
+const IA2: ipv4::Addr = ipv4::Addr([2,2,2,2]);
+
+/// Two nodes on a link, and what ICMP reaches the first
+fn two_nodes(queue: &Queue<'static>) -> (Node, Node, Receiver<(packet::V,)>) {
+  let (di1, di2) = queue.link(box |_|(), box |_|());
+
+  let mut n1 = ::std::collections::HashMap::new();
+  n1.insert(IA2, 0);
+  let mut n2 = ::std::collections::HashMap::new();
+  n2.insert(IA1, 0);
+
+  let i1 = State::new(vec![InterfaceRow::new(IA1, box di1)], n1);
+  let i2 = State::new(vec![InterfaceRow::new(IA2, box di2)], n2);
+
+  let (tx, rx) = channel();
+  control::register_protocol_handler(&*i1, icmp::PROTOCOL, box SenderClosure::new(tx));
+  (i1, i2, rx)
+}
+
+/// A packet from the first node, to be handed to the second
+fn probe(dst: ipv4::Addr, protocol: u8, ttl: u8) -> packet::V {
+  packet::V::new_with_builder(dst, protocol, None, |p| {
+    p.as_mut_vec().extend_from_slice(b"probe, and then some");
+    let s = p.borrow_mut();
+    s.set_source(IA1);
+    s.set_time_to_live(ttl);
+    Ok::<(), ()>(())
+  }).unwrap().1
+}
+
+/// The message, and the destination of the packet it quotes
+fn error_about(p: &packet::V) -> (u8, u8, ipv4::Addr) {
+  let m = Message::parse(p.borrow().get_payload()).unwrap();
+  (m.kind, m.code, packet::A::new(m.data).get_destination())
+}
+
+#[test]
+fn echo() {
+  let queue = Queue::new();
+  let (i1, _i2, rx) = two_nodes(&queue);
+
+  icmp::send_message(&*i1, IA2, Message {
+    kind: icmp::ECHO_REQUEST,
+    code: 0,
+    rest: [0, 1, 0, 7],
+    data: b"ping",
+  }).unwrap();
+  assert_eq!(queue.run(), 2);
+
+  let (reply,) = rx.try_recv().unwrap();
+  assert_eq!(reply.borrow().get_source(), IA2);
+  let m = Message::parse(reply.borrow().get_payload()).unwrap();
+  assert_eq!(m.kind, icmp::ECHO_REPLY);
+  assert_eq!(m.echo_id_seq(), (1, 7));
+  assert_eq!(m.data, &b"ping"[..]);
+}
+
+#[test]
+fn protocol_unreachable() {
+  let queue = Queue::new();
+  let (i1, _i2, rx) = two_nodes(&queue);
+
+  send::send_manual(&i1.interfaces[0], probe(IA2, 99, 64)).unwrap();
+  assert_eq!(queue.run(), 2);
+
+  let (p,) = rx.try_recv().unwrap();
+  assert_eq!(error_about(&p),
+             (icmp::DEST_UNREACHABLE, icmp::unreachable::PROTOCOL, IA2));
+}
+
+#[test]
+fn forwarding_errors() {
+  let queue = Queue::new();
+  let (i1, _i2, rx) = two_nodes(&queue);
+  let far = ipv4::Addr([3,3,3,3]);
+
+  // out of hops
+  send::send_manual_via(&i1.interfaces[0], IA2, probe(far, 99, 1)).unwrap();
+  // nowhere to go
+  send::send_manual_via(&i1.interfaces[0], IA2, probe(far, 99, 64)).unwrap();
+  assert_eq!(queue.run(), 4);
+
+  let (p,) = rx.try_recv().unwrap();
+  assert_eq!(error_about(&p), (icmp::TIME_EXCEEDED, icmp::exceeded::TTL, far));
+  let (p,) = rx.try_recv().unwrap();
+  assert_eq!(error_about(&p), (icmp::DEST_UNREACHABLE, icmp::unreachable::NET, far));
+
+  // quoted as it was sent, not as it would have been forwarded
+  let m = Message::parse(p.borrow().get_payload()).unwrap();
+  let quoted = packet::Ipv4Packet::new_checked(m.data).unwrap();
+  assert_eq!(quoted.time_to_live(), 64);
+  assert!(quoted.is_checksum_valid());
+}
+
+#[test]
+fn port_unreachable() {
+  let queue = Queue::new();
+  let (i1, i2, rx) = two_nodes(&queue);
+
+  // as a transport with nobody listening would
+  let weak = Arc::downgrade(&i2);
+  control::register_protocol_handler(&*i2, 17, box move |p: packet::V| {
+    if let Some(state) = weak.upgrade() {
+      icmp::send_port_unreachable(&*state, p.borrow());
+    }
+  });
+
+  send::send_manual(&i1.interfaces[0], probe(IA2, 17, 64)).unwrap();
+  assert_eq!(queue.run(), 2);
+
+  let (p,) = rx.try_recv().unwrap();
+  assert_eq!(error_about(&p),
+             (icmp::DEST_UNREACHABLE, icmp::unreachable::PORT, IA2));
+}
+
+#[test]
+fn fragmentation_needed() {
+  let queue = Queue::new();
+  let (di1, mut di2) = queue.link(box |_|(), box |_|());
+  // smaller than the probe, but big enough for the error's fragments
+  di2.set_mtu(36);
+
+  let mut n1 = ::std::collections::HashMap::new();
+  n1.insert(IA2, 0);
+  let mut n2 = ::std::collections::HashMap::new();
+  n2.insert(IA1, 0);
+  let i1: Node = State::new(vec![InterfaceRow::new(IA1, box di1)], n1);
+  let _i2: Node = State::new(vec![InterfaceRow::new(IA2, box di2)], n2);
+  let (tx, rx) = channel();
+  control::register_protocol_handler(&*i1, icmp::PROTOCOL, box SenderClosure::new(tx));
+
+  // to be forwarded straight back, which won't fit
+  let mut p = probe(IA1, 99, 64);
+  p.borrow_mut().set_flags_fragment_offset(packet::DONT_FRAGMENT, 0);
+  p.borrow_mut().update_checksum();
+  assert_eq!(p.as_vec().len(), 40);
+  send::send_manual_via(&i1.interfaces[0], IA2, p).unwrap();
+  queue.run();
+
+  let (p,) = rx.try_recv().unwrap();
+  assert_eq!(error_about(&p),
+             (icmp::DEST_UNREACHABLE, icmp::unreachable::FRAGMENTATION, IA1));
+  let m = Message::parse(p.borrow().get_payload()).unwrap();
+  assert_eq!(m.rest, [0, 0, 0, 36]);
+  assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn reassembly_timeout() {
+  use std::thread;
+  use std::time::Duration;
+
+  use net::network::ipv4::reassembly::Reassembler;
+
+  let queue = Queue::new();
+  let (i1, i2, rx) = two_nodes(&queue);
+  *i2.reassembly.lock().unwrap() = Reassembler::with_limits(1 << 16, Duration::from_millis(10));
+
+  let first_only = |id: u16| {
+    let mut p = probe(IA2, 99, 64);
+    p.borrow_mut().set_identification(id);
+    p.borrow_mut().set_flags_fragment_offset(packet::MORE_FRAGMENTS, 0);
+    p.borrow_mut().update_checksum();
+    p
+  };
+
+  send::send_manual(&i1.interfaces[0], first_only(1)).unwrap();
+  assert_eq!(queue.run(), 1);
+  thread::sleep(Duration::from_millis(20));
+
+  // the next fragment to arrive finds the first has waited too long
+  send::send_manual(&i1.interfaces[0], first_only(2)).unwrap();
+  assert_eq!(queue.run(), 2);
+
+  let (p,) = rx.try_recv().unwrap();
+  assert_eq!(error_about(&p),
+             (icmp::TIME_EXCEEDED, icmp::exceeded::REASSEMBLY, IA2));
+  let m = Message::parse(p.borrow().get_payload()).unwrap();
+  assert_eq!(packet::A::new(m.data).get_identification(), 1);
+}
+
+#[test]
+fn rate_limit() {
+  // no refill to speak of
+  let limiter = icmp::RateLimiter::new(2, 0);
+  assert!(limiter.allow());
+  assert!(limiter.allow());
+  assert!(!limiter.allow());
+}
+
+#[test]
+fn ping_over_udp() {
+  use std::collections::HashMap;
+  use std::io;
+  use std::time::Duration;
+
+  use net::data_link::udp_mock::{Interface, Listener};
+  use net::network::ipv4::ping::Outcome;
+
+  let (l1, da1) = Listener::new_loopback(1).unwrap();
+  let (l2, da2) = Listener::new_loopback(1).unwrap();
+
+  let mut n1 = HashMap::new();
+  n1.insert(IA2, 0);
+  let mut n2 = HashMap::new();
+  n2.insert(IA1, 0);
+
+  let i1 = State::<StaticTable, io::Error>::new(
+    vec![InterfaceRow::new(IA1, box Interface::new(&l1, da2, box |_|()))], n1);
+  let _i2 = State::<StaticTable, io::Error>::new(
+    vec![InterfaceRow::new(IA2, box Interface::new(&l2, da1, box |_|()))], n2);
+
+  let options = ping::Options {
+    count:    3,
+    interval: Duration::from_millis(0),
+    .. ping::Options::default()
+  };
+  let mut seqs = Vec::new();
+  let stats = ping::ping(&*i1, IA2, &options, |seq, outcome| {
+    match *outcome {
+      Outcome::Reply { from, bytes, .. } => {
+        assert_eq!(from, IA2);
+        assert_eq!(bytes, 64);
+      },
+      ref o => panic!("expected reply, got {:?}", o),
+    };
+    seqs.push(seq);
+  });
+  assert_eq!(seqs, vec![0, 1, 2]);
+  assert_eq!((stats.transmitted, stats.received, stats.errors), (3, 3, 0));
+  assert!(stats.min <= stats.avg && stats.avg <= stats.max);
+
+  // no route, so nothing goes out
+  let stats = ping::ping(&*i1, ipv4::Addr([3,3,3,3]), &options, |_, outcome| {
+    match *outcome {
+      Outcome::NotSent(send::Error::NoRoute) => (),
+      ref o => panic!("expected no route, got {:?}", o),
+    }
+  });
+  assert_eq!((stats.transmitted, stats.loss()), (0, 0));
+
+  l1.shutdown();
+  l2.shutdown();
+}
+
+/// Routes anything which isn't a neighbor to the lowest neighbor
+struct DefaultRoute {
+  neighbors: Vec<ipv4::Addr>,
+}
+
+impl<'a> ipv4::strategy::RoutingTable<'a> for DefaultRoute {
+  fn init<I>(i: I) -> DefaultRoute where I: Iterator<Item=ipv4::Addr> {
+    let mut neighbors: Vec<ipv4::Addr> = i.collect();
+    neighbors.sort();
+    DefaultRoute { neighbors: neighbors }
+  }
+
+  fn lookup(&self, dst: ipv4::Addr) -> Option<ipv4::Addr> {
+    if self.neighbors.contains(&dst) {
+      Some(dst)
+    } else {
+      self.neighbors.first().cloned()
+    }
+  }
+
+  fn monitor<E>(_: Arc<State<'a, DefaultRoute, E>>) {}
+
+  fn dump(&self) {}
+}
+
+#[test]
+fn traceroute_over_udp() {
+  use std::collections::HashMap;
+  use std::io;
+  use std::time::Duration;
+
+  use net::data_link::udp_mock::{Interface, Listener};
+  use net::network::ipv4::traceroute;
+
+  // 1 -- 2 -- 3
+  let a12 = ipv4::Addr([10,0,0,1]);
+  let a21 = ipv4::Addr([10,0,0,2]);
+  let a23 = ipv4::Addr([10,0,1,1]);
+  let a32 = ipv4::Addr([10,0,1,2]);
+
+  let (l1, da1) = Listener::new_loopback(1).unwrap();
+  let (l2, da2) = Listener::new_loopback(1).unwrap();
+  let (l3, da3) = Listener::new_loopback(1).unwrap();
+
+  let mut n1 = HashMap::new();
+  n1.insert(a21, 0);
+  let mut n2 = HashMap::new();
+  n2.insert(a12, 0);
+  n2.insert(a32, 1);
+  let mut n3 = HashMap::new();
+  n3.insert(a23, 0);
+
+  let i1 = State::<DefaultRoute, io::Error>::new(
+    vec![InterfaceRow::new(a12, box Interface::new(&l1, da2, box |_|()))], n1);
+  let _i2 = State::<DefaultRoute, io::Error>::new(
+    vec![InterfaceRow::new(a21, box Interface::new(&l2, da1, box |_|())),
+         InterfaceRow::new(a23, box Interface::new(&l2, da3, box |_|()))], n2);
+  let _i3 = State::<DefaultRoute, io::Error>::new(
+    vec![InterfaceRow::new(a32, box Interface::new(&l3, da2, box |_|()))], n3);
+
+  let options = traceroute::Options {
+    probes:  2,
+    timeout: Duration::from_secs(1),
+    .. traceroute::Options::default()
+  };
+  let hops = traceroute::traceroute(&*i1, a32, &options, |_| ());
+
+  assert_eq!(traceroute::path(&hops[..]), vec![Some(a21), Some(a32)]);
+  match hops[0].probes[0] {
+    ping::Outcome::Error { kind: icmp::TIME_EXCEEDED, .. } => (),
+    ref o => panic!("expected time exceeded, got {:?}", o),
+  }
+  assert!(hops[1].is_last());
+
+  l1.shutdown();
+  l2.shutdown();
+  l3.shutdown();
+}
@@ -5,6 +5,7 @@ mod net {
 
   pub mod data_link {
     pub extern crate interface;
+    pub extern crate channel;
     pub extern crate udp_mock;
   }
 
@@ -18,11 +19,15 @@ mod net {
 
 use std::fmt;
 use std::str::from_utf8;
-use std::sync::{Arc, Barrier, RwLock};
+use std::sync::{Arc, Barrier};
+use std::sync::mpsc::{channel, Receiver};
 
 #[macro_use]
 extern crate log;
 
+use net::misc::SenderClosure;
+use net::data_link::interface as dl;
+use net::data_link::channel::Queue;
 use net::data_link::udp_mock::*;
 use net::network::ipv4;
 use net::network::ipv4::*;
@@ -50,6 +55,21 @@ pub fn make_ip_to_wait
   state
 }
 
+pub fn make_ip_to_collect
+  <'st, R, E>
+  (interfaces: Vec<InterfaceRow<'st, E>>,
+   neighbors: InterfaceTable)
+   -> (Arc<State<'st, R, E>>, Receiver<(packet::V,)>)
+  where R: strategy::RoutingTable<'st> + 'st,
+        E: fmt::Debug + 'st
+
+{
+  let state = ipv4::State::<'st, R, E>::new(interfaces, neighbors);
+  let (tx, rx) = channel();
+  control::register_protocol_handler::<R, E>(&*state, 8, box SenderClosure::new(tx));
+  (state, rx)
+}
+
 fn sending
   <'st, R, E>
   (state: &ipv4::State<'st, R, E>,
@@ -84,6 +104,28 @@ macro_rules! map(
      };
 );
 
+const IA1: ipv4::Addr = ipv4::Addr([1,1,1,1]);
+const IA2: ipv4::Addr = ipv4::Addr([2,2,2,2]);
+
+type Node<'st> = (Arc<State<'st, StaticTable, net::data_link::channel::Error>>,
+                  Receiver<(packet::V,)>);
+
+/// Two nodes, `IA1` and `IA2`, one on each end of the link, collecting
+/// whatever they are sent
+fn two_nodes<'st>(link: (net::data_link::channel::Interface<'st>,
+                         net::data_link::channel::Interface<'st>))
+                  -> (Node<'st>, Node<'st>)
+{
+  let (di1, di2) = link;
+  let n1 = make_ip_to_collect::<StaticTable, _>(
+    vec![InterfaceRow::new(IA1, box di1)],
+    map!{IA2 => 0});
+  let n2 = make_ip_to_collect::<StaticTable, _>(
+    vec![InterfaceRow::new(IA2, box di2)],
+    map!{IA1 => 0});
+  (n1, n2)
+}
+
 #[test]
 fn direct_two_nodes() {
   static NUM_THREADS: usize = 1;
@@ -96,26 +138,216 @@ fn direct_two_nodes() {
   let di1 = Interface::new(&l1, da2, box |_|());
   let di2 = Interface::new(&l2, da1, box |_|());
 
-  let ia1 = ipv4::Addr([1,1,1,1]);
-  let ia2 = ipv4::Addr([2,2,2,2]);
-
   const M1: &'static str = "Hey Node 1!";
   const M2: &'static str = "Hey Node 2!";
 
   let i1 = make_ip_to_wait::<StaticTable, _>(
-    vec![InterfaceRow { local_ip: ia1, interface: RwLock::new(box di1) }],
-    map!{ia2 => 0},
+    vec![InterfaceRow::new(IA1, box di1)],
+    map!{IA2 => 0},
     M1,
     barrier.clone());
 
   let i2 = make_ip_to_wait::<StaticTable, _>(
-    vec![InterfaceRow { local_ip: ia2, interface: RwLock::new(box di2) }],
-    map!{ia1 => 0},
+    vec![InterfaceRow::new(IA2, box di2)],
+    map!{IA1 => 0},
     M2,
     barrier.clone());
 
-  sending(&*i1, ia2, M2).unwrap();
-  sending(&*i2, ia1, M1).unwrap();
+  sending(&*i1, IA2, M2).unwrap();
+  sending(&*i2, IA1, M1).unwrap();
 
   barrier.wait();
 }
+
+#[test]
+fn direct_two_nodes_queued() {
+  let queue = Queue::new();
+
+  const M1: &'static str = "Hey Node 1!";
+  const M2: &'static str = "Hey Node 2!";
+
+  let ((i1, rx1), (i2, rx2)) = two_nodes(queue.link(box |_|(), box |_|()));
+
+  sending(&*i1, IA2, M2).unwrap();
+  sending(&*i2, IA1, M1).unwrap();
+
+  assert_eq!(queue.run(), 2);
+
+  let (p1,) = rx1.try_recv().unwrap();
+  assert_eq!(p1.borrow().get_payload(), M1.as_bytes());
+  assert_eq!(p1.borrow().get_source(), IA2);
+
+  let (p2,) = rx2.try_recv().unwrap();
+  assert_eq!(p2.borrow().get_payload(), M2.as_bytes());
+  assert_eq!(p2.borrow().get_source(), IA1);
+}
+
+#[test]
+fn too_large_for_mtu() {
+  let queue = Queue::new();
+
+  let (mut di1, di2) = queue.link(box |_|(), box |_|());
+  // 20 byte header, plus a little
+  di1.set_mtu(24);
+
+  let ((i1, _), (_i2, rx2)) = two_nodes((di1, di2));
+
+  sending(&*i1, IA2, "Hey").unwrap();
+  assert_eq!(sending(&*i1, IA2, "Hey Node 2!"),
+             Err(send::Error::PacketTooLarge { mtu: 24, len: 31 }));
+
+  assert_eq!(queue.run(), 1);
+  assert_eq!(rx2.try_recv().unwrap().0.borrow().get_payload(), b"Hey");
+}
+
+#[test]
+fn fragmented_to_fit_mtu() {
+  let queue = Queue::new();
+
+  let (mut di1, di2) = queue.link(box |_|(), box |_|());
+  // room for 8 bytes of payload per fragment
+  di1.set_mtu(31);
+
+  let ((i1, _), (_i2, rx2)) = two_nodes((di1, di2));
+
+  sending(&*i1, IA2, "Hey Node 2, how are you?").unwrap();
+  assert_eq!(queue.run(), 3);
+  assert_eq!(i1.interface_stats(0).unwrap().packets_sent, 3);
+
+  // and put back together on the other side
+  let (p2,) = rx2.try_recv().unwrap();
+  assert_eq!(p2.borrow().get_payload(), b"Hey Node 2, how are you?");
+  assert!(rx2.try_recv().is_err());
+
+  // unless asked not to
+  assert_eq!(send::send(&*i1, IA2, 8, None,
+                        |p| {
+                          p.as_mut_vec().extend_from_slice(b"Hey Node 2, hey!");
+                          p.borrow_mut().set_flags_fragment_offset(packet::DONT_FRAGMENT, 0);
+                          Ok(())
+                        },
+                        |_| Ok(())),
+             Err(send::Error::PacketTooLarge { mtu: 31, len: 36 }));
+}
+
+#[test]
+fn identification_per_flow() {
+  let queue = Queue::new();
+
+  let ((i1, _), (_i2, rx2)) = two_nodes(queue.link(box |_|(), box |_|()));
+
+  sending(&*i1, IA2, "one").unwrap();
+  sending(&*i1, IA2, "two").unwrap();
+  assert_eq!(queue.run(), 2);
+
+  let (p1,) = rx2.try_recv().unwrap();
+  let (p2,) = rx2.try_recv().unwrap();
+  let id = p1.borrow().get_identification();
+  assert_eq!(p2.borrow().get_identification(), id.wrapping_add(1));
+}
+
+#[test]
+fn unwanted_packets_return_to_pool() {
+  let queue = Queue::new();
+
+  let (di1, di2) = queue.link(box |_|(), box |_|());
+
+  let i1 = ipv4::State::<StaticTable, _>::new(
+    vec![InterfaceRow::new(IA1, box di1)],
+    map!{IA2 => 0});
+
+  // nobody registered for protocol 8 here
+  let i2 = ipv4::State::<StaticTable, _>::new(
+    vec![InterfaceRow::new(IA2, box di2)],
+    map!{IA1 => 0});
+
+  sending(&*i1, IA2, "Hey Node 2!").unwrap();
+  assert!(i2.pool.is_empty());
+
+  // and the protocol unreachable sent back
+  assert_eq!(queue.run(), 2);
+  assert_eq!(i2.pool.len(), 1);
+
+  // and is reused for the next packet
+  sending(&*i2, IA1, "Hey Node 1!").unwrap();
+  assert!(i2.pool.is_empty());
+}
+
+#[test]
+fn counters() {
+  let queue = Queue::new();
+
+  let ((i1, _), (i2, _rx2)) = two_nodes(queue.link(box |_|(), box |_|()));
+
+  sending(&*i1, IA2, "Hey").unwrap();
+  assert_eq!(queue.run(), 1);
+
+  // garbage, straight from the link
+  dl::Interface::send(&**i1.interfaces[0].interface.read().unwrap(), vec![1, 2, 3]).unwrap();
+  assert_eq!(queue.run(), 1);
+
+  // refused by the driver on the way out, then dropped on the way in
+  control::down(&*i1, 0).unwrap();
+  let (_, p) = packet::V::new_with_builder(IA2, 8, None, |p| {
+    p.as_mut_vec().push(0);
+    Ok::<(), ()>(())
+  }).unwrap();
+  assert_eq!(send::send_manual(&i1.interfaces[0], p),
+             Err(send::Error::External(dl::Error::Disabled)));
+  sending(&*i2, IA1, "Hey").unwrap();
+  assert_eq!(queue.run(), 1);
+  control::up(&*i1, 0).unwrap();
+
+  assert_eq!(i1.interface_stats(0), Some(stats::Stats {
+    packets_sent:     1,
+    bytes_sent:       23,
+    dropped_disabled: 2,
+    .. Default::default()
+  }));
+  assert_eq!(i2.stats(), vec![stats::Stats {
+    packets_sent:        1,
+    bytes_sent:          23,
+    packets_received:    2,
+    bytes_received:      26,
+    validation_failures: 1,
+    .. Default::default()
+  }]);
+  assert_eq!(i1.interface_stats(1), None);
+}
+
+#[test]
+fn down_interface_has_no_routes() {
+  let queue = Queue::new();
+
+  let ((i1, _), (_i2, rx2)) = two_nodes(queue.link(box |_|(), box |_|()));
+
+  control::down(&*i1, 0).unwrap();
+  assert_eq!(sending(&*i1, IA2, "Hey"), Err(send::Error::NoRoute));
+
+  control::up(&*i1, 0).unwrap();
+  sending(&*i1, IA2, "Hey").unwrap();
+  assert_eq!(queue.run(), 1);
+  assert_eq!(rx2.try_recv().unwrap().0.borrow().get_payload(), b"Hey");
+}
+
+#[test]
+fn carrier_loss_withdraws_routes() {
+  let queue = Queue::new();
+
+  let (di1, di2) = queue.link(box |_|(), box |_|());
+  let cable = di1.cable();
+
+  let ((i1, rx1), (i2, rx2)) = two_nodes((di1, di2));
+
+  // the driver tells both ends, not `control`
+  cable.set_carrier(false);
+  assert_eq!(sending(&*i1, IA2, "Hey"), Err(send::Error::NoRoute));
+  assert_eq!(sending(&*i2, IA1, "Hey"), Err(send::Error::NoRoute));
+
+  cable.set_carrier(true);
+  sending(&*i1, IA2, "Hey").unwrap();
+  sending(&*i2, IA1, "Hey").unwrap();
+  assert_eq!(queue.run(), 2);
+  assert_eq!(rx1.try_recv().unwrap().0.borrow().get_payload(), b"Hey");
+  assert_eq!(rx2.try_recv().unwrap().0.borrow().get_payload(), b"Hey");
+}
@@ -0,0 +1,103 @@
+mod net {
+  pub extern crate network;
+}
+
+use net::network::ipv4;
+use net::network::ipv4::packet::{self, IpOption};
+
+const MSG: &'static [u8] = b"options, then this";
+
+fn with_options(options: &[IpOption]) -> packet::V {
+  let (_, p) = packet::V::new_with_builder(ipv4::Addr([2,2,2,2]), 8, None, |p| {
+    // after the body, to check it is moved along
+    p.as_mut_vec().extend_from_slice(MSG);
+    p.set_options(options)
+  }).unwrap();
+  p
+}
+
+#[test]
+fn round_trip() {
+  let p = with_options(&[IpOption::RouterAlert(0), IpOption::record_route(3).unwrap()]);
+  packet::validate(p.borrow().as_slice()).unwrap();
+
+  // 4 + 15 bytes of options, padded
+  assert_eq!(p.borrow().hdr_bytes(), 40);
+  assert_eq!(p.borrow().get_payload(), MSG);
+
+  let options: Vec<IpOption> = p.borrow().options().map(|o| o.unwrap()).collect();
+  assert_eq!(options, vec![
+    IpOption::RouterAlert(0),
+    IpOption::RecordRoute { pointer: 4, route: &[0; 12] },
+    IpOption::End,
+  ]);
+}
+
+#[test]
+fn record_route_until_full() {
+  let mut p = with_options(&[IpOption::Nop, IpOption::record_route(2).unwrap()]);
+  let hops = [ipv4::Addr([10,0,0,1]), ipv4::Addr([10,0,1,1]), ipv4::Addr([10,0,2,1])];
+
+  assert!(p.borrow_mut().record_route(hops[0]));
+  assert!(p.borrow_mut().record_route(hops[1]));
+  assert!(!p.borrow_mut().record_route(hops[2]));
+  p.borrow_mut().update_checksum();
+  packet::validate(p.borrow().as_slice()).unwrap();
+
+  let recorded = p.borrow().options().nth(1).unwrap().unwrap();
+  assert_eq!(recorded.addresses(), &hops[..2]);
+}
+
+#[test]
+fn bad_option_lengths() {
+  let mut p = with_options(&[IpOption::RouterAlert(0)]);
+  // Router Alert is always 4 bytes long
+  p.as_mut_vec()[21] = 2;
+  p.borrow_mut().update_checksum();
+  assert_eq!(packet::validate(p.borrow().as_slice()), Err(packet::BadPacket::BadOptions));
+
+  // runs off the end of the header
+  p.as_mut_vec()[21] = 5;
+  p.borrow_mut().update_checksum();
+  assert_eq!(packet::validate(p.borrow().as_slice()), Err(packet::BadPacket::BadOptions));
+}
+
+#[test]
+fn fragments_copy_some_options() {
+  let p = with_options(&[IpOption::record_route(1).unwrap(), IpOption::RouterAlert(0)]);
+  let fragments = packet::fragment(p.borrow(), 40).unwrap();
+  assert_eq!(fragments.len(), 3);
+
+  let first: Vec<IpOption> = fragments[0].borrow().options().map(|o| o.unwrap()).collect();
+  assert_eq!(first, vec![IpOption::record_route(1).unwrap(), IpOption::RouterAlert(0), IpOption::End]);
+
+  for f in fragments[1..].iter() {
+    packet::validate(f.borrow().as_slice()).unwrap();
+    assert_eq!(f.borrow().hdr_bytes(), 24);
+    let rest: Vec<IpOption> = f.borrow().options().map(|o| o.unwrap()).collect();
+    assert_eq!(rest, vec![IpOption::RouterAlert(0)]);
+  }
+}
+
+#[test]
+fn record_route_limit() {
+  let most = IpOption::record_route(packet::MAX_RECORD_ROUTE_SLOTS).unwrap();
+  let p = with_options(&[most]);
+  packet::validate(p.borrow().as_slice()).unwrap();
+  assert_eq!(p.borrow().hdr_bytes(), 60);
+
+  assert_eq!(IpOption::record_route(packet::MAX_RECORD_ROUTE_SLOTS + 1), None);
+}
+
+#[test]
+fn too_long_to_write() {
+  let data = [0; 254];
+  let mut buf = Vec::new();
+  assert_eq!(IpOption::Unknown { kind: 0x99, data: &data[..253] }.write(&mut buf), Ok(()));
+  assert_eq!(buf.len(), 255);
+
+  buf.clear();
+  assert_eq!(IpOption::Unknown { kind: 0x99, data: &data[..] }.write(&mut buf),
+             Err(packet::BadPacket::BadOptions));
+  assert!(buf.is_empty());
+}
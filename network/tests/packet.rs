@@ -0,0 +1,110 @@
+mod net {
+  pub extern crate network;
+}
+
+use net::network::ipv4::Addr;
+use net::network::ipv4::packet::{self, BadPacket, Ipv4Packet};
+
+const MSG: &'static [u8] = b"some payload";
+
+fn build() -> packet::V {
+  let (_, mut p) = packet::V::new_with_builder(Addr([10, 0, 0, 2]), 17, None, |p| {
+    p.as_mut_vec().extend_from_slice(MSG);
+    Ok::<(), ()>(())
+  }).unwrap();
+  p.borrow_mut().set_source(Addr([10, 0, 0, 1]));
+  p.borrow_mut().set_identification(0x1234);
+  p.borrow_mut().update_checksum();
+  p
+}
+
+#[test]
+fn view_agrees() {
+  let p = build();
+  let a = p.borrow();
+  let view = Ipv4Packet::new_checked(p.borrow().as_slice()).unwrap();
+
+  assert_eq!(view.version(), 4);
+  assert_eq!(view.hdr_bytes(), a.hdr_bytes());
+  assert_eq!(view.total_length(), a.get_total_length());
+  assert_eq!(view.identification(), 0x1234);
+  assert_eq!(view.flags_fragment_offset(), a.get_flags_fragment_offset());
+  assert_eq!(view.type_of_service(), a.get_type_of_service());
+  assert_eq!(view.time_to_live(), a.get_time_to_live());
+  assert_eq!(view.protocol(), 17);
+  assert_eq!(view.source(), Addr([10, 0, 0, 1]));
+  assert_eq!(view.destination(), Addr([10, 0, 0, 2]));
+  assert_eq!(view.payload(), MSG);
+  assert_eq!(view.header_checksum(), a.make_header_checksum());
+  assert!(view.is_checksum_valid());
+}
+
+#[test]
+fn short_buffers() {
+  let p = build();
+  let buf = p.borrow().as_slice();
+
+  assert_eq!(packet::validate(&[]), Err(BadPacket::TooShort(0)));
+  // not even the version is right
+  assert_eq!(packet::validate(&[0x60]), Err(BadPacket::BadVersion(6)));
+  for len in 1..packet::MIN_HDR_LEN_8S as usize {
+    assert_eq!(packet::validate(&buf[..len]), Err(BadPacket::TooShort(len)));
+  }
+  // the header is there, but not all of the payload
+  let cut = buf.len() - 1;
+  assert_eq!(packet::validate(&buf[..cut]),
+             Err(BadPacket::BadPacketLength(cut, buf.len() as u16)));
+}
+
+#[test]
+#[should_panic]
+fn unchecked_needs_a_header() {
+  let p = build();
+  packet::A::new(&p.borrow().as_slice()[..packet::MIN_HDR_LEN_8S as usize - 1]);
+}
+
+#[test]
+fn bad_header_lengths() {
+  let mut buf = build().to_vec();
+
+  buf[0] = 0x44;
+  assert_eq!(Ipv4Packet::new_checked(&buf[..]).err(), Some(BadPacket::HeaderTooShort(16)));
+
+  // longer than the whole packet
+  buf[0] = 0x4f;
+  assert_eq!(Ipv4Packet::new_checked(&buf[..]).err(),
+             Some(BadPacket::HeaderTooLong(60, buf.len())));
+}
+
+#[test]
+fn quoted_start() {
+  // as ICMP errors quote packets: the header and the start of the payload
+  let p = build();
+  let quoted = &p.borrow().as_slice()[..packet::MIN_HDR_LEN_8S as usize + 4];
+  let view = Ipv4Packet::new_checked(quoted).unwrap();
+  assert_eq!(view.payload(), &MSG[..4]);
+  assert_eq!(view.total_length() as usize, p.borrow().as_slice().len());
+  assert!(packet::validate(quoted).is_err());
+}
+
+#[test]
+fn mutable_view() {
+  let mut buf = build().to_vec();
+  {
+    let mut view = Ipv4Packet::new_checked(&mut buf[..]).unwrap();
+    view.set_time_to_live(3);
+    view.set_destination(Addr([192, 168, 0, 1]));
+    view.set_flags_fragment_offset(packet::DONT_FRAGMENT, 0);
+    view.payload_mut()[0] = b'S';
+    assert!(!view.is_checksum_valid());
+    view.update_checksum();
+  }
+  packet::validate(&buf[..]).unwrap();
+
+  let view = Ipv4Packet::new_checked(buf).unwrap();
+  assert_eq!(view.time_to_live(), 3);
+  assert_eq!(view.destination(), Addr([192, 168, 0, 1]));
+  assert_eq!(view.flags_fragment_offset(), (packet::DONT_FRAGMENT, 0));
+  assert_eq!(&view.payload()[1..], &MSG[1..]);
+  assert_eq!(view.payload()[0], b'S');
+}
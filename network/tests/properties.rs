@@ -0,0 +1,128 @@
+//! Whatever bytes come in, the parsers must say no rather than panic, and
+//! what the builders make, the parsers must take back.
+
+extern crate quickcheck;
+
+mod net {
+  pub extern crate network;
+}
+
+use quickcheck::{quickcheck, TestResult};
+
+use net::network::ipv4::Addr;
+use net::network::ipv4::packet::{self, IpOption, Ipv4Packet};
+
+/// Reads everything there is to read of the packet, if it is accepted
+fn exercise(buf: &[u8]) {
+  if let Ok(view) = Ipv4Packet::new_checked(buf) {
+    view.type_of_service();
+    view.flags_fragment_offset();
+    view.source();
+    view.destination();
+    view.payload();
+    view.options().count();
+    view.is_checksum_valid();
+  }
+  if packet::validate(buf).is_ok() {
+    let p = packet::A::new(buf);
+    p.get_payload();
+    p.options().count();
+    format!("{}", p);
+    packet::fragment(p, 68);
+  }
+}
+
+fn build(dst: Addr, protocol: u8, slots: usize, payload: &[u8]) -> packet::V {
+  let (_, p) = packet::V::new_with_builder(dst, protocol, None, |p| {
+    p.as_mut_vec().extend_from_slice(payload);
+    if slots > 0 {
+      p.set_options(&[IpOption::record_route(slots).unwrap()])
+    } else {
+      Ok(())
+    }
+  }).unwrap();
+  p
+}
+
+#[test]
+fn arbitrary_bytes() {
+  fn prop(buf: Vec<u8>) -> bool {
+    exercise(&buf[..]);
+    true
+  }
+  quickcheck(prop as fn(Vec<u8>) -> bool);
+}
+
+#[test]
+fn damaged_packets() {
+  // random bytes are rarely IPv4 at all, so start from a packet which is
+  fn prop(payload: Vec<u8>, slots: u8, cut: usize, flips: Vec<(usize, u8)>) -> TestResult {
+    let slots = slots as usize % (packet::MAX_RECORD_ROUTE_SLOTS + 1);
+    if payload.is_empty() && slots == 0 {
+      return TestResult::discard();
+    }
+    let mut buf = build(Addr([10, 0, 0, 1]), 17, slots, &payload[..]).to_vec();
+    let len = buf.len();
+    for (i, x) in flips {
+      buf[i % len] ^= x;
+    }
+    buf.truncate(cut % (len + 1));
+    exercise(&buf[..]);
+    TestResult::passed()
+  }
+  quickcheck(prop as fn(Vec<u8>, u8, usize, Vec<(usize, u8)>) -> TestResult);
+}
+
+#[test]
+fn round_trip() {
+  fn prop(dst: (u8, u8, u8, u8), protocol: u8, ttl: u8, slots: u8, payload: Vec<u8>) -> TestResult {
+    // an empty packet is a bug in the builder's caller
+    let slots = slots as usize % (packet::MAX_RECORD_ROUTE_SLOTS + 1);
+    if payload.is_empty() && slots == 0 {
+      return TestResult::discard();
+    }
+    let dst = Addr([dst.0, dst.1, dst.2, dst.3]);
+    let mut p = build(dst, protocol, slots, &payload[..]);
+    p.borrow_mut().set_time_to_live(ttl);
+    p.borrow_mut().update_checksum();
+
+    let buf = p.borrow().as_slice();
+    if packet::validate(buf).is_err() {
+      return TestResult::failed();
+    }
+    let view = Ipv4Packet::new_checked(buf).unwrap();
+    let options: Vec<IpOption> = view.options().map(|o| o.unwrap()).collect();
+    TestResult::from_bool(
+      view.destination() == dst
+        && view.protocol() == protocol
+        && view.time_to_live() == ttl
+        && view.payload() == &payload[..]
+        && (slots == 0) == options.is_empty()
+        && (slots == 0 || options[0] == IpOption::record_route(slots).unwrap()))
+  }
+  quickcheck(prop as fn((u8, u8, u8, u8), u8, u8, u8, Vec<u8>) -> TestResult);
+}
+
+#[test]
+fn fragments_round_trip() {
+  fn prop(payload: Vec<u8>, slots: u8, mtu: u8) -> TestResult {
+    let slots = slots as usize % (packet::MAX_RECORD_ROUTE_SLOTS + 1);
+    if payload.is_empty() {
+      return TestResult::discard();
+    }
+    let p = build(Addr([10, 0, 0, 1]), 17, slots, &payload[..]);
+    let mtu = p.borrow().hdr_bytes() + 8 + mtu as usize;
+    let fragments = packet::fragment(p.borrow(), mtu).unwrap();
+
+    let mut joined = Vec::new();
+    for f in fragments.iter() {
+      let f = f.borrow();
+      if packet::validate(f.as_slice()).is_err() || f.as_slice().len() > mtu {
+        return TestResult::failed();
+      }
+      joined.extend_from_slice(f.get_payload());
+    }
+    TestResult::from_bool(joined == payload)
+  }
+  quickcheck(prop as fn(Vec<u8>, u8, u8) -> TestResult);
+}
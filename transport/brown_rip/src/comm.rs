@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::io::IoResult;
 use std::sync::Arc;
 use std::option::None;
@@ -216,3 +217,57 @@ fn update<I>(state: &ipv4::State<RipTable>,
                  state.interfaces.as_slice()));
   Ok(())
 }
+
+
+/// Poisons every route through the neighbors on a downed interface, and tells
+/// the remaining neighbors right away rather than waiting for the routes to
+/// expire. When the interface comes back up, its neighbors are directly
+/// reachable again, so everybody hears of that instead.
+pub fn link_change<'a, E>(state:     &ipv4::State<'a, RipTable, E>,
+                          interface: usize,
+                          up:        bool)
+                          -> IoResult<()>
+{
+  let affected: Vec<ipv4::Addr> = state.neighbors.iter()
+    .filter(|&(_, &ix)| ix == interface)
+    .map(|(addr, _)| *addr)
+    .collect();
+
+  let mut changed = HashMap::new();
+  { // naked block to make sure lock is released before sending
+    let mut unlocked = state.routes.map.write();
+    if up {
+      for neighbor in affected.iter() {
+        let row = RipRow {
+          time_added: ::time::get_time(),
+          next_hop:   *neighbor,
+          cost:       1,
+        };
+        unlocked.insert(*neighbor, row.clone());
+        changed.insert(*neighbor, row);
+      }
+    } else {
+      for (dst, row) in unlocked.iter_mut() {
+        if row.cost < RIP_INFINITY && affected.contains(&row.next_hop) {
+          debug!("poisoning route to {} via {}", dst, row.next_hop);
+          // the garbage collector will forget it
+          row.cost = RIP_INFINITY;
+          changed.insert(*dst, row.clone());
+        }
+      }
+    }
+  }
+
+  // the neighbors on a downed interface can't hear us anyway
+  let listeners: Vec<ipv4::Addr> = state.neighbors.iter()
+    .filter(|&(_, &ix)| up || ix != interface)
+    .map(|(addr, _)| *addr)
+    .collect();
+
+  let factory = || changed.iter().map(|(a,r)| (*a,r));
+
+  propagate(factory,
+            listeners.iter().map(|x| *x),
+            &state.neighbors,
+            state.interfaces.as_slice())
+}
@@ -13,6 +13,12 @@ extern crate misc;
 extern crate time;
 extern crate network;
 
+#[cfg(test)]
+#[phase(plugin)]
+extern crate quickcheck_macros;
+#[cfg(test)]
+extern crate quickcheck;
+
 use std::collections::HashMap;
 use std::sync::{Arc, RWLock};
 
@@ -23,7 +29,7 @@ use network::ipv4::strategy::RoutingTable;
 
 mod comm;
 mod periodic;
-mod packet;
+pub mod packet;
 
 const RIP_INFINITY:    u8  = 16;
 const RIP_MAX_ENTRIES: u16 = 64;
@@ -42,7 +48,7 @@ pub struct RipTable {
   map: RWLock<HashMap<ipv4::Addr, RipRow>>,
 }
 
-impl RoutingTable for RipTable {
+impl<'a> RoutingTable<'a> for RipTable {
 
   fn lookup(&self, ip: ipv4::Addr) -> Option<ipv4::Addr> {
     self.map.read().get(&ip).and_then( |table| {
@@ -71,6 +77,11 @@ impl RoutingTable for RipTable {
     periodic::spawn_garbage_collector(state);
   }
 
+  fn on_link_change<E>(state: &ipv4::State<'a, RipTable, E>, interface: usize, up: bool) {
+    // ignore errors, for now
+    let _ = comm::link_change(state, interface, up);
+  }
+
   fn dump(&self) {
     for dst in self.map.read().keys() {
       let RipRow { cost, next_hop, time_added } = self.map.read().deref()[*dst];
@@ -35,7 +35,7 @@ pub fn parse<'a>(buf: &'a [u8]) -> Result<Packet<Entries<'a>>, ()> {
 
 pub type Entries<'a> = EntryIter<BufReader<'a>>;
 
-struct EntryIter<R>(R);
+pub struct EntryIter<R>(R);
 
 impl<R> EntryIter<R> where R: Reader {
 
@@ -143,6 +143,9 @@ mod test {
     IoResult,
   };
 
+  use network::ipv4;
+  use quickcheck::TestResult;
+
   #[test]
   fn parse_invalid() {
     assert!(parse(&[0]).is_err());
@@ -203,4 +206,31 @@ mod test {
     }
   }
 
+  #[quickcheck]
+  fn parse_never_panics(buf: Vec<u8>) -> bool {
+    match parse(buf.as_slice()) {
+      Ok(Packet::Response(entries)) => { entries.count(); },
+      _                             => (),
+    }
+    true
+  }
+
+  #[quickcheck]
+  fn round_trip(raw: Vec<(u32, u32)>) -> TestResult {
+    // empty responses aren't sent, and long ones are split
+    if raw.is_empty() || raw.len() > ::RIP_MAX_ENTRIES as uint {
+      return TestResult::discard();
+    }
+    let entries: Vec<Entry> = raw.iter().map(|&(cost, a)| Entry {
+      cost:    cost,
+      address: ipv4::Addr([(a >> 24) as u8, (a >> 16) as u8, (a >> 8) as u8, a as u8]),
+    }).collect();
+
+    let mut buf = Vec::new();
+    super::write_response(&mut entries.iter().map(|e| *e))(&mut buf).unwrap();
+    match parse(buf.as_slice()) {
+      Ok(Packet::Response(parsed)) => TestResult::from_bool(parsed.collect::<Vec<Entry>>() == entries),
+      _                            => TestResult::failed(),
+    }
+  }
 }
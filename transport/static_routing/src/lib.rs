@@ -1,3 +1,5 @@
+#![feature(question_mark)]
+
 #[macro_use]
 extern crate log;
 
@@ -6,39 +8,194 @@ extern crate misc;
 
 extern crate network;
 
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 
 use network::ipv4;
+use network::ipv4::prefix::{Prefix, PrefixMap};
 use network::ipv4::strategy::RoutingTable;
 
+/// Routes to neighbors, plus whatever routes are added by hand or loaded from
+/// a file
+///
+/// The file has a route per line: a prefix, then the neighbor to send to,
+/// e.g. `10.0.2.0/24 10.0.0.2`. Blank lines, and anything after a `#`, are
+/// ignored.
 #[derive(Debug)]
 pub struct StaticTable {
-  // key:   Ip we want to reach, NOT our interface's IP
+  // key:   Prefix we want to reach, NOT our interface's IP
   // value: Ip of neighbor we want to send to
-  map: RwLock<HashMap<ipv4::Addr, ipv4::Addr>>,
+  map: RwLock<PrefixMap<ipv4::Addr>>,
+  // the only valid next hops
+  neighbors: HashSet<ipv4::Addr>,
+  // neighbors whose interface is down, which routes through are ignored
+  down: RwLock<HashSet<ipv4::Addr>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+  /// Routes must lead to a neighbor. When loading, this is a `Parse` error
+  /// instead, so the line is known.
+  NotNeighbor(ipv4::Addr),
+  /// The route to a neighbor itself, which is neither removed nor pointed
+  /// elsewhere
+  NeighborRoute(ipv4::Addr),
+  Io(io::Error),
+  /// Line number, counting from 1, and what is wrong with it
+  Parse(usize, &'static str),
+}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Error {
+    Error::Io(e)
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::NotNeighbor(hop)   => write!(f, "{} is not a neighbor", hop),
+      Error::NeighborRoute(n)   => write!(f, "the route to neighbor {} must stay", n),
+      Error::Io(ref e)          => write!(f, "{}", e),
+      Error::Parse(line, msg)   => write!(f, "line {}: {}", line, msg),
+    }
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl StaticTable {
+  /// Whether the prefix is just a neighbor, whose route `init` made
+  fn is_neighbor_route(&self, prefix: Prefix) -> bool {
+    prefix.len() == 32 && self.neighbors.contains(&prefix.addr())
+  }
+
+  /// Sends packets for the prefix to the neighbor, returning where they went
+  /// before, if anywhere
+  pub fn add_route(&self, prefix: Prefix, next_hop: ipv4::Addr) -> Result<Option<ipv4::Addr>> {
+    if !self.neighbors.contains(&next_hop) {
+      return Err(Error::NotNeighbor(next_hop));
+    }
+    if self.is_neighbor_route(prefix) && prefix.addr() != next_hop {
+      return Err(Error::NeighborRoute(prefix.addr()));
+    }
+    Ok(self.map.write().unwrap().insert(prefix, next_hop))
+  }
+
+  /// Returns where packets for the prefix went, if anywhere. The routes to
+  /// neighbors themselves stay.
+  pub fn remove_route(&self, prefix: Prefix) -> Result<Option<ipv4::Addr>> {
+    if self.is_neighbor_route(prefix) {
+      return Err(Error::NeighborRoute(prefix.addr()));
+    }
+    Ok(self.map.write().unwrap().remove(prefix))
+  }
+
+  /// Every route and its next hop, shortest prefixes first
+  pub fn routes(&self) -> Vec<(Prefix, ipv4::Addr)> {
+    self.map.read().unwrap().entries().into_iter()
+      .map(|(prefix, next_hop)| (prefix, *next_hop))
+      .collect()
+  }
+
+  /// Adds the routes described, returning how many there were. Nothing is
+  /// added unless they all are good.
+  pub fn load_str(&self, s: &str) -> Result<usize> {
+    let mut routes = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+      let line_no = i + 1;
+      let line = match line.find('#') {
+        Some(start) => &line[..start],
+        None        => line,
+      };
+      let words: Vec<&str> = line.split_whitespace().collect();
+      match words.len() {
+        0 => continue,
+        2 => (),
+        _ => return Err(Error::Parse(line_no, "expected prefix and next hop")),
+      };
+      let prefix = Prefix::from_str(words[0])
+        .map_err(|_| Error::Parse(line_no, "bad prefix"))?;
+      let next_hop = ipv4::Addr::from_str(words[1])
+        .map_err(|_| Error::Parse(line_no, "bad next hop"))?;
+      if !self.neighbors.contains(&next_hop) {
+        return Err(Error::Parse(line_no, "next hop is not a neighbor"));
+      }
+      if self.is_neighbor_route(prefix) && prefix.addr() != next_hop {
+        return Err(Error::Parse(line_no, "the route to a neighbor must stay"));
+      }
+      routes.push((prefix, next_hop));
+    }
+
+    let mut map = self.map.write().unwrap();
+    for &(prefix, next_hop) in routes.iter() {
+      map.insert(prefix, next_hop);
+    }
+    Ok(routes.len())
+  }
+
+  pub fn load<P>(&self, path: P) -> Result<usize>
+    where P: AsRef<Path>
+  {
+    let mut s = String::new();
+    File::open(path)?.read_to_string(&mut s)?;
+    self.load_str(&s[..])
+  }
 }
 
 impl<'a> RoutingTable<'a> for StaticTable {
 
   fn lookup(&self, ip: ipv4::Addr) -> Option<ipv4::Addr> {
-    self.map.read().unwrap().get(&ip).map(|x| *x)
+    self.map.read().unwrap().lookup(ip)
+      .map(|(_, x)| *x)
+      .and_then(|next_hop| if self.down.read().unwrap().contains(&next_hop) {
+        None
+      } else {
+        Some(next_hop)
+      })
   }
 
   fn init<I>(elements: I) -> StaticTable where I: Iterator<Item=ipv4::Addr> {
     // make I <-> I, the ID map
-    let routes_iter = elements.map(|neighbor_ip| (neighbor_ip, neighbor_ip));
-    StaticTable { map: RwLock::new(routes_iter.collect()) }
+    let neighbors: HashSet<ipv4::Addr> = elements.collect();
+    let mut map = PrefixMap::new();
+    for &neighbor_ip in neighbors.iter() {
+      map.insert(Prefix::host(neighbor_ip), neighbor_ip);
+    }
+    StaticTable {
+      map:       RwLock::new(map),
+      neighbors: neighbors,
+      down:      RwLock::new(HashSet::new()),
+    }
   }
 
   fn monitor<E>(_state: Arc<ipv4::State<'a, StaticTable, E>>) -> () {
     debug!("In use");
   }
 
+  fn on_link_change<E>(state: &ipv4::State<'a, StaticTable, E>, interface: usize, up: bool) {
+    let mut down = state.routes.down.write().unwrap();
+    for (neighbor, &ix) in state.neighbors.iter() {
+      if ix != interface {
+        continue;
+      }
+      if up {
+        down.remove(neighbor);
+      } else {
+        down.insert(*neighbor);
+      }
+    }
+  }
+
   fn dump(&self) {
-    for vip in self.map.read().unwrap().keys() {
-      let next_hop = self.map.read().unwrap()[vip];
-      info!("{} -> {}", vip, next_hop);
+    for (prefix, next_hop) in self.map.read().unwrap().entries() {
+      info!("{} -> {}", prefix, next_hop);
     }
   }
 
@@ -0,0 +1,96 @@
+extern crate network;
+extern crate static_routing;
+
+use std::str::FromStr;
+
+use network::ipv4::Addr;
+use network::ipv4::prefix::Prefix;
+use network::ipv4::strategy::RoutingTable;
+use static_routing::{Error, StaticTable};
+
+fn p(s: &str) -> Prefix {
+  Prefix::from_str(s).unwrap()
+}
+
+fn table() -> StaticTable {
+  StaticTable::init(vec![Addr([10,0,0,2]), Addr([10,0,1,2])].into_iter())
+}
+
+#[test]
+fn add_and_remove() {
+  let t = table();
+  assert_eq!(t.lookup(Addr([10,0,2,1])), None);
+
+  assert_eq!(t.add_route(p("10.0.2.0/24"), Addr([10,0,0,2])).unwrap(), None);
+  assert_eq!(t.add_route(p("0.0.0.0/0"), Addr([10,0,1,2])).unwrap(), None);
+  assert_eq!(t.lookup(Addr([10,0,2,1])), Some(Addr([10,0,0,2])));
+  assert_eq!(t.lookup(Addr([8,8,8,8])), Some(Addr([10,0,1,2])));
+  // neighbors are still reached directly
+  assert_eq!(t.lookup(Addr([10,0,0,2])), Some(Addr([10,0,0,2])));
+
+  match t.add_route(p("10.0.3.0/24"), Addr([10,0,3,1])) {
+    Err(Error::NotNeighbor(hop)) => assert_eq!(hop, Addr([10,0,3,1])),
+    r => panic!("expected the route to be refused, got {:?}", r),
+  }
+
+  assert_eq!(t.remove_route(p("10.0.2.0/24")).unwrap(), Some(Addr([10,0,0,2])));
+  assert_eq!(t.lookup(Addr([10,0,2,1])), Some(Addr([10,0,1,2])));
+
+  assert_eq!(t.routes(), vec![
+    (p("0.0.0.0/0"), Addr([10,0,1,2])),
+    (p("10.0.0.2/32"), Addr([10,0,0,2])),
+    (p("10.0.1.2/32"), Addr([10,0,1,2])),
+  ]);
+}
+
+#[test]
+fn neighbor_routes_stay() {
+  let t = table();
+  match t.remove_route(p("10.0.0.2/32")) {
+    Err(Error::NeighborRoute(n)) => assert_eq!(n, Addr([10,0,0,2])),
+    r => panic!("expected the removal to be refused, got {:?}", r),
+  }
+  match t.add_route(p("10.0.0.2/32"), Addr([10,0,1,2])) {
+    Err(Error::NeighborRoute(n)) => assert_eq!(n, Addr([10,0,0,2])),
+    r => panic!("expected the route to be refused, got {:?}", r),
+  }
+  match t.load_str("10.0.1.2/32 10.0.0.2") {
+    Err(Error::Parse(1, _)) => (),
+    r => panic!("expected the route to be refused, got {:?}", r),
+  }
+  assert_eq!(t.lookup(Addr([10,0,0,2])), Some(Addr([10,0,0,2])));
+  assert_eq!(t.lookup(Addr([10,0,1,2])), Some(Addr([10,0,1,2])));
+
+  // a shorter prefix covering a neighbor is fine to drop
+  t.add_route(p("10.0.0.0/24"), Addr([10,0,1,2])).unwrap();
+  assert_eq!(t.remove_route(p("10.0.0.0/24")).unwrap(), Some(Addr([10,0,1,2])));
+  assert_eq!(t.lookup(Addr([10,0,0,2])), Some(Addr([10,0,0,2])));
+}
+
+#[test]
+fn load_routes() {
+  let t = table();
+  assert_eq!(t.load_str("
+    # to the far side of 10.0.0.2
+    10.0.2.0/24 10.0.0.2
+
+    10.0.3.0/24 10.0.1.2 # and of 10.0.1.2
+  ").unwrap(), 2);
+  assert_eq!(t.lookup(Addr([10,0,3,9])), Some(Addr([10,0,1,2])));
+
+  // all or nothing
+  match t.load_str("10.0.4.0/24 10.0.0.2\n10.0.5.0/24 10.9.9.9") {
+    Err(Error::Parse(2, _)) => (),
+    r => panic!("expected the routes to be refused, got {:?}", r),
+  }
+  assert_eq!(t.lookup(Addr([10,0,4,1])), None);
+
+  match t.load_str("10.0.4.0/24") {
+    Err(Error::Parse(1, _)) => (),
+    r => panic!("expected parse error, got {:?}", r),
+  }
+  match t.load_str("\n10.0.4.1/24 10.0.0.2") {
+    Err(Error::Parse(2, _)) => (),
+    r => panic!("expected parse error, got {:?}", r),
+  }
+}
@@ -278,7 +278,7 @@ impl TCB
     while !bytes_to_send.is_empty() {
 
       // Make a packet builder
-      let builder: for<'p> |&'p mut TcpPacket| -> send::Result<()> = |packet| {
+      let builder: for<'p, 'q> |&'p mut packet::TcpPacketMut<'q>| -> send::Result<()> = |packet| {
 
         // Set Packet Header Params
         packet.set_ack_num(cur_recv_nxt);
@@ -190,7 +190,7 @@ impl Handshaking
     debug!("{} to {} pre send: want {}, owe {}", them, us, self.want, self.owe);
 
     {
-      let builder: for<'p> |&'p mut packet::TcpPacket| -> send::Result<()> = |packet|
+      let builder: for<'p, 'q> |&'p mut packet::TcpPacketMut<'q>| -> send::Result<()> = |packet|
       {
         // Set SEQ to our ISN
         packet.set_seq_num(self.our_number);
@@ -199,7 +199,8 @@ impl Handshaking
         // gotta SYN them at least once for double handshake
         if ! self.synd_before {
           debug!("{} will SYN {}", us, them);
-          packet.flags_mut().insert(packet::SYN);
+          let flags = packet.flags() | packet::SYN;
+          packet.set_flags(flags);
 
           self.want = true;
           self.synd_before = true;
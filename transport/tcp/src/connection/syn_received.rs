@@ -113,9 +113,9 @@ fn handshake_2<A>(state:   &::State<A>,
   where A: RoutingTable
 {
   // TODO: Report ICE if this signature is removed
-  let builder: for<'p> |&'p mut packet::TcpPacket| -> send::Result<()> = |packet| {
+  let builder: for<'p, 'q> |&'p mut packet::TcpPacketMut<'q>| -> send::Result<()> = |packet| {
     use packet::{SYN, ACK};
-    *packet.flags_mut() = SYN | ACK;
+    packet.set_flags(SYN | ACK);
     Ok(())
   };
 
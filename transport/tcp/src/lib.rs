@@ -22,6 +22,12 @@ extern crate misc;
 extern crate time;
 extern crate network;
 
+#[cfg(test)]
+#[phase(plugin)]
+extern crate quickcheck_macros;
+#[cfg(test)]
+extern crate quickcheck;
+
 use std::fmt;
 use std::collections::HashMap;
 use std::default::Default;
@@ -37,7 +43,7 @@ use concurrent_hash_map::ConcurrentHashMap;
 use listener::Listener;
 use connection::Connection;
 
-mod packet;
+pub mod packet;
 mod concurrent_hash_map;
 mod ring_buf;
 
@@ -7,8 +7,6 @@ use std::io::{
   IoError,
   IoResult,
 };
-use std::mem::{transmute, size_of};
-use std::num::Int;
 use std::fmt;
 
 use network::ipv4::Addr;
@@ -17,9 +15,43 @@ use network::ipv4::packet;
 // Length of TCP header in bytes
 pub const TCP_HDR_LEN: uint = 20;
 
+/// A segment in the IP packet it came or goes in, which `B` holds or
+/// borrows
 #[deriving(PartialEq, Eq, Clone)]
-pub struct TcpPacket {
-  ip: packet::V
+pub struct TcpPacket<B = packet::V> {
+  ip: B
+}
+
+/// A segment being built in place
+pub type TcpPacketMut<'a> = TcpPacket<&'a mut packet::V>;
+
+/// Whatever holds or borrows the IP packet around a segment
+pub trait IpPacket {
+  fn ip(&self) -> &packet::V;
+}
+
+pub trait IpPacketMut: IpPacket {
+  fn ip_mut(&mut self) -> &mut packet::V;
+}
+
+impl IpPacket for packet::V {
+  fn ip(&self) -> &packet::V { self }
+}
+
+impl IpPacketMut for packet::V {
+  fn ip_mut(&mut self) -> &mut packet::V { self }
+}
+
+impl<'a> IpPacket for &'a packet::V {
+  fn ip(&self) -> &packet::V { &**self }
+}
+
+impl<'a> IpPacket for &'a mut packet::V {
+  fn ip(&self) -> &packet::V { &**self }
+}
+
+impl<'a> IpPacketMut for &'a mut packet::V {
+  fn ip_mut(&mut self) -> &mut packet::V { &mut **self }
 }
 
 #[deriving(PartialEq, PartialOrd, Eq, Ord,
@@ -50,30 +82,48 @@ bitflags! {
 
 impl TcpPacket {
 
-  pub fn new(ip_packet: packet::V) -> TcpPacket {
-    TcpPacket { ip: ip_packet }
+  pub fn validate(ip: packet::V) -> Result<TcpPacket, BadPacket>
+  {
+    TcpPacket::new_checked(ip)
+  }
+
+  /// Like `validate`, but leaves the caller with the packet either way
+  pub fn check(ip: &packet::V) -> Result<(), BadPacket>
+  {
+    TcpPacket::new_checked(ip).map(|_| ())
   }
 
-  pub fn hack(ip_packet: &packet::V) -> &TcpPacket {
-    unsafe {transmute(ip_packet) }
+  pub fn to_vec(self) -> Vec<u8> {
+    self.ip.to_vec()
   }
+}
 
-  pub fn hack_mut(ip_packet: &mut packet::V) -> &mut TcpPacket {
-    unsafe {transmute(ip_packet) }
+impl<B> TcpPacket<B> where B: IpPacket {
+
+  /// The header fields are read without checks, so there must be room for a
+  /// header without options. `new_checked` checks the rest.
+  ///
+  /// Panics if there is less than that.
+  pub fn new(ip: B) -> TcpPacket<B> {
+    let len = ip.ip().borrow().get_payload().len();
+    assert!(len >= TCP_HDR_LEN, "{} bytes is too short for a TCP header", len);
+    TcpPacket { ip: ip }
   }
 
-  pub fn validate(ip: packet::V) -> Result<TcpPacket, BadPacket>
+  pub fn new_checked(ip: B) -> Result<TcpPacket<B>, BadPacket>
   {
-    // have to check this first to avoid out-of-bounds panic on version check
-    if ip.borrow().get_total_length() < TCP_HDR_LEN as u16 + packet::MIN_HDR_LEN_8S {
-      return Err(BadPacket::TooShort(ip.borrow().get_total_length() as uint))
+    // have to check this first to avoid out-of-bounds panic on header
+    // fields. Not the total length, as IP options may take some of it.
+    let len = ip.ip().borrow().get_payload().len();
+    if len < TCP_HDR_LEN {
+      return Err(BadPacket::TooShort(len))
     }
-
     let packet = TcpPacket::new(ip);
 
     // this should be true as long as IP does it's job and our CODE is correct
     // therefore is assert, not check
-    assert_eq!(packet.ip.borrow().get_total_length() as uint - packet.ip.borrow().hdr_bytes() as uint,
+    assert_eq!(packet.ip.ip().borrow().get_total_length() as uint
+               - packet.ip.ip().borrow().hdr_bytes() as uint,
                packet.get_tcp().len());
 
     let hdr_len = packet.get_hdr_size() as uint * 4;
@@ -101,25 +151,12 @@ impl TcpPacket {
   }
 
   pub fn as_vec(&self) -> &Vec<u8> {
-    self.ip.as_vec()
-  }
-
-  pub fn as_mut_vec(&mut self) -> &mut Vec<u8> {
-    self.ip.as_mut_vec()
-  }
-
-  pub fn to_vec(self) -> Vec<u8> {
-    self.ip.to_vec()
+    self.ip.ip().as_vec()
   }
 
   /// Returns slice containing TCP packet
   fn get_tcp(&self) -> &[u8] {
-    self.ip.borrow().get_payload()
-  }
-
-  /// Returns mutable slice containing TCP packet body
-  fn get_tcp_mut(&mut self) -> &mut [u8] {
-    self.ip.borrow_mut().get_payload_mut()
+    self.ip.ip().borrow().get_payload()
   }
 
   /// Returns immutable slice containing TCP packet header
@@ -128,12 +165,6 @@ impl TcpPacket {
     self.get_tcp()[..TCP_HDR_LEN]
   }
 
-  /// Returns mutable slice containing TCP packet header
-  /// NOTE: assumes no TCP options
-  fn tcp_hdr_mut(&mut self) -> &mut [u8] {
-    self.get_tcp_mut()[mut ..TCP_HDR_LEN]
-  }
-
   /// Returns length of the TCP body
   pub fn get_body_len(&self) -> u32 {
     (self.get_tcp().len() - TCP_HDR_LEN) as u32
@@ -141,61 +172,38 @@ impl TcpPacket {
 
   // 4-tuple info
   pub fn get_src_addr(&self) -> Addr {
-    self.ip.borrow().get_source()
+    self.ip.ip().borrow().get_source()
   }
   pub fn get_src_port(&self) -> u16 {
     BufReader::new(self.tcp_hdr()[0..2]).read_be_u16().unwrap()
   }
-  pub fn set_src_port(&mut self, port: u16) {
-    BufWriter::new(self.tcp_hdr_mut()[mut 0..2]).write_be_u16(port).unwrap();
-  }
   pub fn get_dst_addr(&self) -> Addr {
-    self.ip.borrow().get_destination()
+    self.ip.ip().borrow().get_destination()
   }
   pub fn get_dst_port(&self) -> u16 {
     BufReader::new(self.tcp_hdr()[2..4]).read_be_u16().unwrap()
   }
-  pub fn set_dst_port(&mut self, port: u16) {
-    BufWriter::new(self.tcp_hdr_mut()[mut 2..4]).write_be_u16(port).unwrap();
-  }
 
   // Control Flags
   pub fn flags(&self) -> Flags {
-    const MASK: u8 = 0b_00_111111;
-    Flags { bits: self.tcp_hdr()[13] & MASK }
-  }
-  pub fn flags_mut(&mut self) -> &mut Flags{
-    unsafe { transmute(&mut self.tcp_hdr_mut()[13]) }
+    Flags::from_bits_truncate(self.tcp_hdr()[13])
   }
 
-
   // Recv Window size
   pub fn get_window_size(&self) -> u16 {
     BufReader::new(self.tcp_hdr()[14..16]).read_be_u16().unwrap()
   }
-  pub fn set_window_size(&mut self, window_size: u16) {
-    BufWriter::new(self.tcp_hdr_mut()[mut 14..16]).write_be_u16(window_size)
-      .unwrap();
-  }
 
   // AKA data offset: 4 bytes
   pub fn get_hdr_size(&self) -> u8 {
     (self.tcp_hdr()[12] >> 4) as u8
   }
-  // FIXME: way to ensure this always gets called
-  pub fn set_hdr_size(&mut self, size: u8) {
-    self.tcp_hdr_mut()[12] = size << 4;
-  }
 
   // Sequence Number Ops
   pub fn get_seq_num(&self) -> u32 {
     // assert!(self.is_seq())
     BufReader::new(self.tcp_hdr()[4..8]).read_be_u32().unwrap()
   }
-  pub fn set_seq_num(&mut self, seq_num: u32) {
-    BufWriter::new(self.tcp_hdr_mut()[mut 4..8]).write_be_u32(seq_num)
-      .unwrap();
-  }
 
   // Acknowledgement Number Ops
   pub fn get_ack_num(&self) -> Option<u32> {
@@ -205,20 +213,11 @@ impl TcpPacket {
       None
     }
   }
-  pub fn set_ack_num(&mut self, ack_num: u32) {
-    self.flags_mut().insert(ACK);
-    BufWriter::new(self.tcp_hdr_mut()[mut 8..12]).write_be_u32(ack_num)
-      .unwrap();
-  }
 
   // Checksum Ops
   pub fn get_checksum(&self) -> u16 {
     BufReader::new(self.tcp_hdr()[16..18]).read_be_u16().unwrap()
   }
-  pub fn set_checksum(&mut self, checksum: u16) {
-    BufWriter::new(self.tcp_hdr_mut()[mut 16..18]).write_be_u16(checksum)
-      .unwrap();
-  }
 
   /// Returns TCP payload as slice
   pub fn get_payload(&self) -> &[u8] {
@@ -226,12 +225,7 @@ impl TcpPacket {
   }
 
   pub fn get_payload_offset(&self) -> uint {
-    self.ip.borrow().hdr_bytes() as uint + TCP_HDR_LEN
-  }
-
-  /// Returns TCP payload as mut slice
-  pub fn get_mut_payload(&mut self) -> &mut[u8] {
-    self.get_tcp_mut()[mut TCP_HDR_LEN..]
+    self.ip.ip().borrow().hdr_bytes() as uint + TCP_HDR_LEN
   }
 
   /// returns native endian
@@ -245,47 +239,85 @@ impl TcpPacket {
     // |  zero  |  PTCL  |    TCP Length   |
     // +--------+--------+--------+--------+
 
-    let len_tcp_bytes = self.get_tcp().len();
-
-    // the WHOLE IP PACKET
-    let bytes: &[u8]  = self.ip.borrow().as_slice();
-    let words: &[u16] = unsafe { packet::cast_slice(bytes[..bytes.len() & !1]) };
+    let ip  = self.ip.ip().borrow();
+    let tcp = self.get_tcp();
 
     // src and dest
-    let pseudo1: &[u16] = words[6..10];
-    debug!("done magic number index 1");
-
-    let pseudo2: [u16, ..2] = [
-      (self.ip.borrow().get_protocol() as u16).to_be(),
-      (self.get_tcp().len() as u16).to_be(), // tcp_len
+    let addrs = ip.as_slice()[12..20].chunks(2).map(word);
+    let pseudo: [u16, ..2] = [
+      ip.get_protocol() as u16,
+      tcp.len() as u16,
     ];
 
-    // TODO: Factor out singleton iterator
-    let zero_checksum_or_last_byte: [u16, ..1] = [
-      if len_tcp_bytes % 2 == 0 {
-        debug!("no magic number index 2 needed");
-        0
-      } else { // compensate for last byte
-        let n = bytes[bytes.len() - 1] as u16;
-        debug!("done magic number index 2");
-        n
-      }.to_be(),
-    ];
+    let segment = tcp.chunks(2).enumerate().map(|(i, c)| {
+      // the checksum field itself
+      if i == 8 { 0 } else { word(c) }
+    });
+
+    packet::make_checksum(segment.chain(addrs).chain(pseudo.iter().map(|x| *x)))
+  }
+}
+
+impl<B> TcpPacket<B> where B: IpPacketMut {
+
+  pub fn as_mut_vec(&mut self) -> &mut Vec<u8> {
+    self.ip.ip_mut().as_mut_vec()
+  }
+
+  /// Returns mutable slice containing TCP packet body
+  fn get_tcp_mut(&mut self) -> &mut [u8] {
+    self.ip.ip_mut().borrow_mut().get_payload_mut()
+  }
 
-    let tcp_words = words[packet::MIN_HDR_LEN_16S as uint..];
+  /// Returns mutable slice containing TCP packet header
+  /// NOTE: assumes no TCP options
+  fn tcp_hdr_mut(&mut self) -> &mut [u8] {
+    self.get_tcp_mut()[mut ..TCP_HDR_LEN]
+  }
 
-    let real1 = tcp_words[..8];
-    debug!("done magic number index 3");
-    let real2 = tcp_words[9..];
-    debug!("done magic number index 4");
+  pub fn set_src_port(&mut self, port: u16) {
+    BufWriter::new(self.tcp_hdr_mut()[mut 0..2]).write_be_u16(port).unwrap();
+  }
+  pub fn set_dst_port(&mut self, port: u16) {
+    BufWriter::new(self.tcp_hdr_mut()[mut 2..4]).write_be_u16(port).unwrap();
+  }
 
-    let iter = real1.iter()
-      .chain(zero_checksum_or_last_byte.iter())
-      .chain(real2.iter())
-      .chain(pseudo1.iter())
-      .chain(pseudo2.iter());
+  /// Leaves the reserved bits alone
+  pub fn set_flags(&mut self, flags: Flags) {
+    let hdr = self.tcp_hdr_mut();
+    hdr[13] = hdr[13] & !Flags::all().bits() | flags.bits();
+  }
 
-    packet::make_checksum(iter.map(|x| Int::from_be(*x)))
+  pub fn set_window_size(&mut self, window_size: u16) {
+    BufWriter::new(self.tcp_hdr_mut()[mut 14..16]).write_be_u16(window_size)
+      .unwrap();
+  }
+
+  // FIXME: way to ensure this always gets called
+  pub fn set_hdr_size(&mut self, size: u8) {
+    self.tcp_hdr_mut()[12] = size << 4;
+  }
+
+  pub fn set_seq_num(&mut self, seq_num: u32) {
+    BufWriter::new(self.tcp_hdr_mut()[mut 4..8]).write_be_u32(seq_num)
+      .unwrap();
+  }
+
+  pub fn set_ack_num(&mut self, ack_num: u32) {
+    let flags = self.flags() | ACK;
+    self.set_flags(flags);
+    BufWriter::new(self.tcp_hdr_mut()[mut 8..12]).write_be_u32(ack_num)
+      .unwrap();
+  }
+
+  pub fn set_checksum(&mut self, checksum: u16) {
+    BufWriter::new(self.tcp_hdr_mut()[mut 16..18]).write_be_u16(checksum)
+      .unwrap();
+  }
+
+  /// Returns TCP payload as mut slice
+  pub fn get_mut_payload(&mut self) -> &mut[u8] {
+    self.get_tcp_mut()[mut TCP_HDR_LEN..]
   }
 
   pub fn update_checksum(&mut self) {
@@ -294,6 +326,12 @@ impl TcpPacket {
   }
 }
 
+/// Two bytes in network order, the second zero if missing. Native byte
+/// order.
+fn word(c: &[u8]) -> u16 {
+  (c[0] as u16) << 8 | *c.get(1).unwrap_or(&0) as u16
+}
+
 // For purposes of sorting by sequence number
 impl Ord for TcpPacket {
   fn cmp(&self, other: &TcpPacket) -> Ordering {
@@ -307,7 +345,7 @@ impl PartialOrd for TcpPacket {
   }
 }
 
-impl fmt::Show for TcpPacket {
+impl<B> fmt::Show for TcpPacket<B> where B: IpPacket {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     write!(f, "TCP: [Flags:{}] <srcAddr: {}, dstAddr: {}>, |srcPort {}|dstPort {}|\n|Seq# {}|\n|Ack# {}|\n|offset {}|ACK {}|SYN {}|FIN {}|window {}|\n|checksum {}|\n{}", self.tcp_hdr()[13],
            self.get_src_addr(), self.get_dst_addr(),
@@ -327,6 +365,60 @@ impl fmt::Show for TcpPacket {
 mod test {
   use super::*;
 
+  use network::ipv4;
+  use network::ipv4::packet as ip;
+  use network::ipv4::packet::IpOption;
+  use quickcheck::TestResult;
+
+  /// A valid IP packet around the segment, with room for `slots` addresses
+  /// in a Record Route option
+  fn wrap(segment: &[u8], slots: uint) -> ip::V {
+    let (_, v) = ip::V::new_with_builder(ipv4::Addr([10, 0, 0, 2]), ::PROTOCOL, None, |p| {
+      p.borrow_mut().set_source(ipv4::Addr([10, 0, 0, 1]));
+      p.as_mut_vec().push_all(segment);
+      if slots > 0 {
+        p.set_options(&[IpOption::record_route(slots).unwrap()])
+      } else {
+        Ok(())
+      }
+    }).unwrap();
+    v
+  }
+
+  #[quickcheck]
+  fn check_never_panics(segment: Vec<u8>, slots: u8) -> TestResult {
+    let slots = slots as uint % (ip::MAX_RECORD_ROUTE_SLOTS + 1);
+    if segment.is_empty() && slots == 0 {
+      return TestResult::discard();
+    }
+    let _ = TcpPacket::check(&wrap(segment.as_slice(), slots));
+    TestResult::passed()
+  }
+
+  #[quickcheck]
+  fn round_trip(src_port: u16, dst_port: u16, seq: u32, window: u16, payload: Vec<u8>) -> bool {
+    let mut segment = Vec::from_elem(TCP_HDR_LEN, 0u8);
+    segment.push_all(payload.as_slice());
+    let mut v = wrap(segment.as_slice(), 0);
+    {
+      let mut tcp = TcpPacket::new(&mut v);
+      tcp.set_src_port(src_port);
+      tcp.set_dst_port(dst_port);
+      tcp.set_seq_num(seq);
+      tcp.set_window_size(window);
+      tcp.set_hdr_size((TCP_HDR_LEN / 4) as u8);
+      tcp.update_checksum();
+    }
+    match TcpPacket::validate(v) {
+      Err(_)  => false,
+      Ok(tcp) => tcp.get_src_port() == src_port
+        && tcp.get_dst_port() == dst_port
+        && tcp.get_seq_num() == seq
+        && tcp.get_window_size() == window
+        && tcp.get_payload() == payload.as_slice(),
+    }
+  }
+
   #[test]
   fn byte(){
     let b = [1u8]; // 0x1 == 1
@@ -19,13 +19,15 @@ fn handle<A>(state:  &Arc<::State<A>>,
              packet: ipv4::packet::V)
   where A: RoutingTable
 {
-  let packet = match TcpPacket::validate(packet) {
-    Ok(p)  => p,
+  match TcpPacket::check(&packet) {
+    Ok(()) => (),
     Err(e) => {
       debug!("TCP packet invalid because {}", e);
+      state.ip.pool.give(packet.to_vec());
       return;
     },
   };
+  let packet = TcpPacket::new(packet);
 
   trace::log_trace(&packet, true);
   debug!("Got TCP Packet: {}", &packet);
@@ -36,6 +38,7 @@ fn handle<A>(state:  &Arc<::State<A>>,
     Some(p) => p,
     None    => {
       debug!("no sub-table--definitely no listener or connection to handle this")
+      state.ip.pool.give(packet.to_vec());
       return;
     },
   };
@@ -37,7 +37,7 @@ pub fn send
    dst:                super::ConAddr,
    expected_body_size: Option<u16>,
    upcaster:           |self::Error| -> E,
-   builder:            for<'a> |&'a mut packet::TcpPacket|:'clos -> result::Result<(), E>)
+   builder:            for<'a, 'b> |&'a mut packet::TcpPacketMut<'b>|:'clos -> result::Result<(), E>)
    -> result::Result<(), E>
   where A: strategy::RoutingTable,
         E: FromError<send::Error>,
@@ -48,21 +48,21 @@ pub fn send
     let new_len = packet.as_vec().len() + packet::TCP_HDR_LEN;
     unsafe { packet.as_mut_vec().set_len(new_len) };
 
-    let packet = packet::TcpPacket::hack_mut(packet);
+    let mut packet = packet::TcpPacket::new(packet);
 
     packet.set_src_port(src_port);
     packet.set_dst_port(dst.1);
     packet.set_hdr_size((packet::TCP_HDR_LEN / 4) as u8); // # 32-bit words in header
 
-    builder(packet)
+    builder(&mut packet)
   };
 
   let awkward_checksum_fixer: for<'p> |&'p mut ipv4::packet::V| -> result::Result<(), E> = | packet |
   {
-    // Log the sending of this packet
-    trace::log_trace(packet::TcpPacket::hack(packet), false);
+    let mut packet = packet::TcpPacket::new(packet);
 
-    let packet = packet::TcpPacket::hack_mut(packet);
+    // Log the sending of this packet
+    trace::log_trace(&packet, false);
 
     match src_addr {
       Some(addr) => if addr != packet.get_src_addr() {
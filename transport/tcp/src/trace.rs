@@ -2,12 +2,12 @@ use time;
 use std::sync::Arc;
 
 use network::ipv4::{mod, control, send};
-use packet::TcpPacket;
+use packet::{IpPacket, TcpPacket};
 
 static RECV_STR : &'static str = "RECV";
 static SEND_STR : &'static str = "SEND";
 
-pub fn log_trace(tcp_packet: &TcpPacket, is_recv: bool) {
+pub fn log_trace<B>(tcp_packet: &TcpPacket<B>, is_recv: bool) where B: IpPacket {
   let action = if is_recv { RECV_STR } else { SEND_STR };
   info!("[TRACE][{}][ns:{}][seq:{}][ack:{}][len:{}][flags:{}][data:{}]",
         action,